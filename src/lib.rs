@@ -2,182 +2,5685 @@
 //! Follows secure coding practices: strong input validation, clear Result-based errors,
 //! safe file operations with atomic writes, and append-only logging.
 
-use anyhow::{Context, Result};
-use chrono::Utc;
+use chrono::format::StrftimeItems;
+use chrono::{DateTime, Local, NaiveDateTime, Utc};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use sha2::{Digest, Sha256};
+use std::fmt;
 use std::fs::{self, File, OpenOptions};
-use std::io::{self, Read, Write};
-use std::path::{Path, PathBuf};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use unicode_normalization::UnicodeNormalization;
+
+/// Every way an operation can fail. Match on the variant instead of the message.
+#[derive(Debug, thiserror::Error)]
+pub enum BackupError {
+    #[error("filename is empty")]
+    EmptyFilename,
+    #[error("filename too long")]
+    FilenameTooLong,
+    #[error("absolute paths are not allowed")]
+    AbsolutePath,
+    #[error("path has an empty component")]
+    EmptyComponent,
+    #[error("control characters are not allowed")]
+    ControlCharacter,
+    #[error("path separators are not allowed")]
+    PathSeparator,
+    #[error("traversal tokens are not allowed")]
+    TraversalToken,
+    #[error("filename contains invalid characters")]
+    InvalidCharacter,
+    #[error("{name} is a reserved device name on Windows")]
+    ReservedName { name: String },
+    #[error("filename must not end in a dot or space")]
+    TrailingDotOrSpace,
+    #[error("names ending in {suffix} are reserved for files this tool creates")]
+    ReservedSuffix { suffix: String },
+    #[error("file must have an extension")]
+    MissingExtension,
+    #[error("files with extension .{ext} are not allowed in this tool")]
+    DisallowedExtension { ext: String },
+    #[error("path escapes base directory")]
+    PathEscapesBase,
+    #[error("source file does not exist")]
+    SourceMissing,
+    #[error("source is not a regular file")]
+    NotRegularFile,
+    #[error("file is {size} bytes, over the {max} byte limit")]
+    FileTooLarge { size: u64, max: u64 },
+    #[error("file contents do not look like text")]
+    NotTextContent,
+    #[error("source is a symbolic link")]
+    Symlink,
+    #[error("source is not a directory")]
+    NotADirectory,
+    #[error("backup already exists, refusing to overwrite")]
+    BackupExists,
+    #[error("backup file does not exist")]
+    BackupMissing,
+    #[error("source and destination are the same file")]
+    SourceEqualsDestination,
+    #[error("file was modified after its backup was made")]
+    SourceNewerThanBackup,
+    #[error("destination already exists, refusing to overwrite")]
+    DestinationExists,
+    #[error("checksum mismatch: source {source_hex}, backup {backup_hex}")]
+    ChecksumMismatch {
+        source_hex: String,
+        backup_hex: String,
+    },
+    #[error("file has {links} hard links; overwriting it destroys the data they share")]
+    HardLinked { links: u64 },
+    #[error("overwrite read-back did not match; file was not removed")]
+    WipeVerificationFailed,
+    #[error("restored file does not match the backup")]
+    RestoreVerificationFailed,
+    #[error("refusing to operate on the active log file")]
+    CannotOperateOnLogFile,
+    #[error("file is locked by another operation")]
+    Locked,
+    #[error("unknown command `{0}` (expected backup, restore, or delete)")]
+    UnknownCommand(String),
+    #[error("confirmation was for `{confirmed}`, not `{requested}`")]
+    ConfirmationMismatch {
+        confirmed: String,
+        requested: String,
+    },
+    #[error("cannot undo: {0}")]
+    CannotUndo(String),
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
+    #[error("invalid configuration: {0}")]
+    InvalidConfig(String),
+    #[error("cannot move {} across filesystems", path.display())]
+    CrossDevice {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error(
+        "{} was not fully wiped: access denied to streams {}",
+        path.display(),
+        streams.join(", ")
+    )]
+    StreamsNotWiped { path: PathBuf, streams: Vec<String> },
+    #[error("could not write the audit log: {0}")]
+    AuditLogFailed(#[source] Box<BackupError>),
+    #[error("permission denied: {context}")]
+    PermissionDenied {
+        path: PathBuf,
+        context: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("{context}: {source}")]
+    Io {
+        context: String,
+        #[source]
+        source: io::Error,
+    },
+}
+
+pub type Result<T, E = BackupError> = std::result::Result<T, E>;
+
+/// Attach a short description of what was being attempted to an I/O error.
+trait IoContext<T> {
+    fn context(self, context: &str) -> Result<T>;
+    fn with_context<F: FnOnce() -> String>(self, f: F) -> Result<T>;
+    /// Context `"<op> <path>"`, reported as [`BackupError::PermissionDenied`] when it is one.
+    fn path_context(self, op: &str, path: &Path) -> Result<T>;
+}
+
+impl<T> IoContext<T> for io::Result<T> {
+    fn context(self, context: &str) -> Result<T> {
+        self.with_context(|| context.to_string())
+    }
+
+    fn with_context<F: FnOnce() -> String>(self, f: F) -> Result<T> {
+        self.map_err(|source| BackupError::Io {
+            context: f(),
+            source,
+        })
+    }
+
+    fn path_context(self, op: &str, path: &Path) -> Result<T> {
+        self.map_err(|source| {
+            let context = format!("{} {}", op, path.display());
+            if source.kind() == io::ErrorKind::PermissionDenied {
+                BackupError::PermissionDenied {
+                    path: path.to_path_buf(),
+                    context,
+                    source,
+                }
+            } else {
+                BackupError::Io { context, source }
+            }
+        })
+    }
+}
+
+/// Extensions accepted by [`sanitize_filename`] and by a default [`BackupConfig`].
+pub const DEFAULT_ALLOWED_EXTS: &[&str] = &["txt", "log", "md"];
+
+/// Longest name, in bytes, most filesystems accept for a single path component.
+pub const DEFAULT_MAX_NAME_LEN: usize = 255;
+
+/// Longest suffix this crate appends to a filename, `.<timestamp>-<counter>.bak` from
+/// [`backup_file_versioned`]. Names are limited to the maximum minus this, so every
+/// generated name still fits.
+pub const MAX_SUFFIX_LEN: usize = ".20240101T000000Z-4294967295.bak".len();
+
+/// Appended to a filename to name its backup, unless [`BackupConfig::set_backup_suffix`]
+/// picks another.
+pub const DEFAULT_BACKUP_SUFFIX: &str = ".bak";
+
+/// Suffix of the temp files writes go through; names ending in it are refused like backups.
+pub const TEMP_SUFFIX: &str = ".tmp";
+
+/// Longest suffix [`BackupConfig::set_backup_suffix`] accepts.
+pub const MAX_BACKUP_SUFFIX_LEN: usize = 16;
 
 /// Allowed filename pattern: ASCII letters, digits, underscore, hyphen, and dot.
-/// No path separators, no traversal tokens, length <= 255, not empty.
+/// No path separators, no traversal tokens, not empty, no trailing dot, no Windows device
+/// names such as `CON` or `LPT1`, and at most `DEFAULT_MAX_NAME_LEN - MAX_SUFFIX_LEN` bytes.
 pub fn sanitize_filename(input: &str) -> Result<String> {
+    Sanitizer::default().validate(input)
+}
+
+/// One of [`DEFAULT_ALLOWED_EXTS`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum AllowedExt {
+    Txt,
+    Log,
+    Md,
+}
+
+impl AllowedExt {
+    pub const ALL: [AllowedExt; 3] = [AllowedExt::Txt, AllowedExt::Log, AllowedExt::Md];
+
+    /// The extension without its dot, as listed in [`DEFAULT_ALLOWED_EXTS`].
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AllowedExt::Txt => "txt",
+            AllowedExt::Log => "log",
+            AllowedExt::Md => "md",
+        }
+    }
+
+    fn from_ext(ext: &str) -> Option<Self> {
+        AllowedExt::ALL.into_iter().find(|e| e.as_str() == ext)
+    }
+}
+
+/// A name that passed [`sanitize_filename_typed`], with the extension it was accepted for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SanitizedName {
+    pub name: String,
+    pub ext: AllowedExt,
+}
+
+/// Like [`sanitize_filename`], but also reports which allowed extension the name has.
+pub fn sanitize_filename_typed(input: &str) -> Result<SanitizedName> {
+    let name = sanitize_filename(input)?;
+    let ext = Path::new(&name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .and_then(AllowedExt::from_ext)
+        .expect("check_extension accepted one of DEFAULT_ALLOWED_EXTS");
+    Ok(SanitizedName { name, ext })
+}
+
+/// Like [`sanitize_filename`], but accepts the extensions in `allowed_exts` instead.
+pub fn sanitize_filename_with<S: AsRef<str>>(input: &str, allowed_exts: &[S]) -> Result<String> {
+    sanitize_file(input, allowed_exts, false, DEFAULT_MAX_NAME_LEN)
+}
+
+/// Like [`sanitize_filename`], but also allows Unicode letters and digits.
+/// The name is NFKC-normalized first and the normalized form is returned, so composed and
+/// decomposed spellings of `café.txt` name the same file, and compatibility forms such as
+/// fullwidth `／` are checked as the `/` they become.
+pub fn sanitize_filename_unicode(input: &str) -> Result<String> {
+    sanitize_filename_unicode_with(input, DEFAULT_ALLOWED_EXTS)
+}
+
+/// Like [`sanitize_filename_unicode`], but accepts the extensions in `allowed_exts` instead.
+pub fn sanitize_filename_unicode_with<S: AsRef<str>>(
+    input: &str,
+    allowed_exts: &[S],
+) -> Result<String> {
+    sanitize_file(input, allowed_exts, true, DEFAULT_MAX_NAME_LEN)
+}
+
+/// A single filename, NFKC-normalized first when `unicode` is set.
+fn sanitize_file<S: AsRef<str>>(
+    input: &str,
+    allowed_exts: &[S],
+    unicode: bool,
+    max_len: usize,
+) -> Result<String> {
+    let input: String = if unicode {
+        input.nfkc().collect()
+    } else {
+        input.to_string()
+    };
+    sanitize_name(&input, unicode, max_len)?;
+    check_extension(&input, allowed_exts)?;
+    Ok(input)
+}
+
+/// Like [`sanitize_filename`], but also accepts relative paths such as `docs/notes.txt`.
+/// Each `/`-separated component must pass the filename rules; absolute paths, empty
+/// components, and `..` are rejected. Only the last component needs an allowed extension.
+pub fn sanitize_relative_path(input: &str) -> Result<String> {
+    sanitize_relative_path_with(input, DEFAULT_ALLOWED_EXTS)
+}
+
+/// Like [`sanitize_relative_path`], but accepts the extensions in `allowed_exts` instead.
+pub fn sanitize_relative_path_with<S: AsRef<str>>(
+    input: &str,
+    allowed_exts: &[S],
+) -> Result<String> {
+    sanitize_path(input, allowed_exts, false, DEFAULT_MAX_NAME_LEN)
+}
+
+fn sanitize_path<S: AsRef<str>>(
+    input: &str,
+    allowed_exts: &[S],
+    unicode: bool,
+    max_len: usize,
+) -> Result<String> {
+    let input: String = if unicode {
+        input.nfkc().collect()
+    } else {
+        input.to_string()
+    };
+    if input.is_empty() {
+        return Err(BackupError::EmptyFilename);
+    }
+    if input.starts_with('/') {
+        return Err(BackupError::AbsolutePath);
+    }
+    for component in input.split('/') {
+        if component.is_empty() {
+            return Err(BackupError::EmptyComponent);
+        }
+        sanitize_name(component, unicode, max_len)?;
+    }
+    check_extension(input.rsplit('/').next().unwrap_or(&input), allowed_exts)?;
+    Ok(input)
+}
+
+fn check_extension<S: AsRef<str>>(input: &str, allowed_exts: &[S]) -> Result<()> {
+    if let Some(ext) = Path::new(input).extension().and_then(|s| s.to_str()) {
+        if !allowed_exts.iter().any(|a| a.as_ref() == ext) {
+            return Err(BackupError::DisallowedExtension {
+                ext: ext.to_string(),
+            });
+        }
+    } else {
+        return Err(BackupError::MissingExtension);
+    }
+    Ok(())
+}
+
+/// The rules every path component must pass, without the extension check.
+/// `unicode` widens the letters and digits allowed from ASCII to all of Unicode.
+/// `max_len` is the filesystem limit; room for [`MAX_SUFFIX_LEN`] is kept free.
+fn sanitize_name(input: &str, unicode: bool, max_len: usize) -> Result<()> {
     if input.is_empty() {
-        anyhow::bail!("filename is empty")
+        return Err(BackupError::EmptyFilename);
+    }
+    if input.len() > max_len.saturating_sub(MAX_SUFFIX_LEN) {
+        return Err(BackupError::FilenameTooLong);
     }
-    if input.len() > 255 {
-        anyhow::bail!("filename too long")
+    // Also excluded by the whitelist below; checked first so NUL and newlines get a clear error.
+    if input.chars().any(char::is_control) {
+        return Err(BackupError::ControlCharacter);
     }
     if input.contains('/') || input.contains('\\') {
-        anyhow::bail!("path separators are not allowed")
+        return Err(BackupError::PathSeparator);
     }
     if input.contains("..") {
-        anyhow::bail!("traversal tokens are not allowed")
+        return Err(BackupError::TraversalToken);
     }
-    if !input
-        .bytes()
-        .all(|b| b.is_ascii_alphanumeric() || b == b'.' || b == b'_' || b == b'-')
-    {
-        anyhow::bail!("filename contains invalid characters")
+    // Windows strips these, so `notes.` and `notes` would name the same file.
+    if input.ends_with('.') || input.ends_with(' ') {
+        return Err(BackupError::TrailingDotOrSpace);
     }
-    // Optional: allow only .txt or .log and .md for safety. Adjust if needed.
-    let allowed_exts = ["txt", "log", "md"];
-    if let Some(ext) = Path::new(input).extension().and_then(|s| s.to_str()) {
-        if !allowed_exts.contains(&ext) {
-            anyhow::bail!("only .txt, .log, or .md files are allowed in this tool")
+    let allowed = |c: char| {
+        let alnum = if unicode {
+            c.is_alphanumeric()
+        } else {
+            c.is_ascii_alphanumeric()
+        };
+        alnum || c == '.' || c == '_' || c == '-'
+    };
+    if !input.chars().all(allowed) {
+        return Err(BackupError::InvalidCharacter);
+    }
+    // Windows reserves device names whatever the extension, so `CON.txt` is out too.
+    let stem = input.split('.').next().unwrap_or(input);
+    if is_reserved_device_name(stem) {
+        return Err(BackupError::ReservedName {
+            name: stem.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// `CON`, `PRN`, `AUX`, `NUL`, `COM1`-`COM9`, `LPT1`-`LPT9`, in any case.
+fn is_reserved_device_name(stem: &str) -> bool {
+    let upper = stem.to_ascii_uppercase();
+    match upper.as_str() {
+        "CON" | "PRN" | "AUX" | "NUL" => true,
+        _ => upper
+            .strip_prefix("COM")
+            .or_else(|| upper.strip_prefix("LPT"))
+            .is_some_and(|n| matches!(n.as_bytes(), [b'1'..=b'9'])),
+    }
+}
+
+/// A set of filename rules, built with [`SanitizerBuilder`]. The default applies the same
+/// rules as [`sanitize_filename`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sanitizer {
+    allowed_exts: Vec<String>,
+    unicode: bool,
+    max_len: usize,
+    uppercase: bool,
+    subdirs: bool,
+}
+
+impl Default for Sanitizer {
+    fn default() -> Self {
+        Self {
+            allowed_exts: DEFAULT_ALLOWED_EXTS.iter().map(|e| e.to_string()).collect(),
+            unicode: false,
+            max_len: DEFAULT_MAX_NAME_LEN,
+            uppercase: true,
+            subdirs: false,
         }
-    } else {
-        anyhow::bail!("file must have an extension")
     }
-    Ok(input.to_string())
+}
+
+impl Sanitizer {
+    pub fn builder() -> SanitizerBuilder {
+        SanitizerBuilder::default()
+    }
+
+    /// Check `input` against these rules and return the name to use, which is the
+    /// NFKC-normalized form when Unicode is allowed.
+    pub fn validate(&self, input: &str) -> Result<String> {
+        let name = if self.subdirs {
+            sanitize_path(input, &self.allowed_exts, self.unicode, self.max_len)?
+        } else {
+            sanitize_file(input, &self.allowed_exts, self.unicode, self.max_len)?
+        };
+        if !self.uppercase && name.chars().any(char::is_uppercase) {
+            return Err(BackupError::InvalidCharacter);
+        }
+        Ok(name)
+    }
+}
+
+/// Builds a [`Sanitizer`], starting from the [`sanitize_filename`] rules.
+#[derive(Debug, Clone, Default)]
+pub struct SanitizerBuilder {
+    rules: Sanitizer,
+}
+
+impl SanitizerBuilder {
+    /// Allow Unicode letters and digits, as [`sanitize_filename_unicode`] does.
+    pub fn allow_unicode(mut self, allow: bool) -> Self {
+        self.rules.unicode = allow;
+        self
+    }
+
+    /// Filesystem limit on a name, in bytes; names may use this minus [`MAX_SUFFIX_LEN`].
+    pub fn max_len(mut self, max_len: usize) -> Self {
+        self.rules.max_len = max_len;
+        self
+    }
+
+    /// Extensions, without the dot, a name may have.
+    pub fn allowed_extensions(mut self, exts: Vec<String>) -> Self {
+        self.rules.allowed_exts = exts;
+        self
+    }
+
+    /// Accept uppercase letters; on by default. Turned off, `Notes.txt` is rejected with
+    /// [`BackupError::InvalidCharacter`].
+    pub fn allow_uppercase(mut self, allow: bool) -> Self {
+        self.rules.uppercase = allow;
+        self
+    }
+
+    /// Accept relative paths like `docs/notes.txt`, as [`sanitize_relative_path`] does.
+    pub fn allow_subdirs(mut self, allow: bool) -> Self {
+        self.rules.subdirs = allow;
+        self
+    }
+
+    pub fn build(self) -> Sanitizer {
+        self.rules
+    }
 }
 
 fn cwd() -> Result<PathBuf> {
     std::env::current_dir().context("cannot read current directory")
 }
 
-fn within_cwd(p: &Path) -> Result<()> {
-    let base = cwd()?.canonicalize().context("canonicalize base dir failed")?;
-    let parent = p.parent().unwrap_or_else(|| Path::new("."));
-    let parent = base.join(parent);
-    let candidate = parent.join(
-        p.file_name()
-            .ok_or_else(|| anyhow::anyhow!("invalid filename"))?,
-    );
-    // We avoided separators already, so this should be inside base.
-    if !candidate.starts_with(&base) {
-        anyhow::bail!("path escapes working directory")
+/// Overrides [`BackupConfig::base_dir`] in [`BackupConfig::from_env`].
+pub const ENV_BASE_DIR: &str = "SAFE_BACKUP_BASE_DIR";
+/// Comma-separated extensions overriding [`DEFAULT_ALLOWED_EXTS`] in [`BackupConfig::from_env`].
+pub const ENV_ALLOWED_EXTS: &str = "SAFE_BACKUP_ALLOWED_EXTS";
+
+/// Split a comma-separated extension list, refusing anything that is not plain letters and digits.
+fn parse_allowed_exts(list: &str) -> Result<Vec<String>> {
+    let mut exts = Vec::new();
+    for ext in list.split(',').map(str::trim) {
+        if ext.is_empty() || !ext.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(BackupError::InvalidConfig(format!(
+                "invalid extension `{}` in {}",
+                ext, ENV_ALLOWED_EXTS
+            )));
+        }
+        exts.push(ext.to_string());
     }
-    Ok(())
+    Ok(exts)
 }
 
-fn logfile_path() -> Result<PathBuf> {
-    Ok(cwd()?.join("logfile.txt"))
+/// Counts of finished operations, from [`metrics_snapshot`] or [`BackupConfig::metrics`].
+/// Batch and convenience functions count once per file they back up, restore, or delete;
+/// [`backup_dir_in`] counts once per directory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Metrics {
+    pub backups: u64,
+    pub backup_failures: u64,
+    pub restores: u64,
+    pub restore_failures: u64,
+    pub deletes: u64,
+    pub delete_failures: u64,
 }
 
-fn log_event(level: &str, msg: &str) -> Result<()> {
-    let ts = Utc::now().format("%Y-%m-%d %H:%M:%S").to_string();
-    let line = format!("[{}] {}: {}\n", ts, level, msg);
-    let path = logfile_path()?;
-    let mut f = OpenOptions::new()
-        .append(true)
-        .create(true)
-        .open(&path)
-        .with_context(|| format!("open logfile at {}", path.display()))?;
-    f.write_all(line.as_bytes())?;
-    Ok(())
+/// The counters in [`Metrics`] an operation adds to.
+#[derive(Debug, Clone, Copy)]
+enum Tally {
+    Backup,
+    Restore,
+    Delete,
 }
 
-/// Create `<filename>.bak` without overwriting. Copies bytes safely.
-pub fn backup_file(filename: &str) -> Result<PathBuf> {
-    let filename = sanitize_filename(filename)?;
-    let src = Path::new(&filename);
-    within_cwd(src)?;
-    if !src.exists() {
-        anyhow::bail!("source file does not exist")
+/// Thread-safe counters behind [`Metrics`], indexed by operation and then success/failure.
+#[derive(Debug, Default)]
+struct Counters([[AtomicU64; 2]; 3]);
+
+impl Counters {
+    fn record(&self, op: Tally, ok: bool) {
+        self.0[op as usize][usize::from(!ok)].fetch_add(1, Ordering::Relaxed);
     }
-    if !src.is_file() {
-        anyhow::bail!("source is not a regular file")
+
+    fn snapshot(&self) -> Metrics {
+        let get =
+            |op: Tally, ok: bool| self.0[op as usize][usize::from(!ok)].load(Ordering::Relaxed);
+        Metrics {
+            backups: get(Tally::Backup, true),
+            backup_failures: get(Tally::Backup, false),
+            restores: get(Tally::Restore, true),
+            restore_failures: get(Tally::Restore, false),
+            deletes: get(Tally::Delete, true),
+            delete_failures: get(Tally::Delete, false),
+        }
     }
-    let bak = Path::new(&(filename.to_string() + ".bak"));
-    if bak.exists() {
-        anyhow::bail!("backup already exists, refusing to overwrite")
+}
+
+/// Shared by every config that has not called [`BackupConfig::use_own_metrics`].
+static PROCESS_COUNTERS: LazyLock<Arc<Counters>> = LazyLock::new(Arc::default);
+
+/// Operation counts for the whole process, including every plain (non-`_in`) call.
+pub fn metrics_snapshot() -> Metrics {
+    PROCESS_COUNTERS.snapshot()
+}
+
+/// Settings shared by every operation.
+/// The plain functions build one with [`BackupConfig::from_env`]; the `*_in` variants take it explicitly.
+#[derive(Debug, Clone)]
+pub struct BackupConfig {
+    /// Directory that filenames are resolved against and must stay inside.
+    pub base_dir: PathBuf,
+    /// Extensions a filename may have; see [`sanitize_filename_with`].
+    pub allowed_exts: Vec<String>,
+    /// Accept Unicode filenames, NFKC-normalized; see [`sanitize_filename_unicode`].
+    pub unicode_names: bool,
+    /// Accept relative paths like `docs/notes.txt`; see [`sanitize_relative_path`].
+    /// Backups are kept next to the file, or mirrored under `backup_dir` if that is set.
+    pub allow_subdirs: bool,
+    /// Accept absolute paths that resolve to inside `base_dir`; they are turned into the
+    /// equivalent relative name, so backups are named the same either way.
+    pub allow_absolute: bool,
+    /// When set, [`backup_dir_in`] also copies entries whose name starts with a dot.
+    /// Off by default, and each hidden entry left out is logged at `DEBUG`.
+    pub include_hidden: bool,
+    /// Filesystem limit on a single name, in bytes. Filenames may use this minus
+    /// [`MAX_SUFFIX_LEN`], so the backups generated from them fit too.
+    pub max_name_len: usize,
+    backup_suffix: String,
+    /// How lines are written to the logfile.
+    pub log_format: LogFormat,
+    log_path: Option<PathBuf>,
+    log_timestamp_format: String,
+    log_hook: Option<LogHook>,
+    clock: SharedClock,
+    metrics: Arc<Counters>,
+    log_note: Option<String>,
+    log_batch: Option<Arc<Mutex<Option<Vec<u8>>>>>,
+    /// Append log lines to the logfile. Turn off to send events only to the log hook.
+    pub log_to_file: bool,
+    /// Fail with [`BackupError::AuditLogFailed`] when a log line cannot be written, instead
+    /// of carrying on without it. The file operation may already have happened by then.
+    pub strict_logging: bool,
+    /// Write log timestamps in the local timezone instead of UTC.
+    pub log_local_time: bool,
+    /// Rotate the logfile once it would grow past this many bytes; `None` disables rotation.
+    pub log_max_bytes: Option<u64>,
+    /// How many rotated logfiles (`logfile.1.txt` ...) to keep.
+    pub log_keep: usize,
+    /// Operate on symlinks whose target stays inside `base_dir`, instead of refusing them.
+    pub follow_symlinks: bool,
+    /// Refuse to securely delete a file with more than one hard link, instead of only
+    /// logging a warning. Overwriting it wipes every link, and removing it removes only one.
+    pub refuse_hard_linked: bool,
+    /// Chunk size for secure-delete overwrite passes, clamped to [`MAX_WIPE_BUFFER_SIZE`].
+    pub wipe_buffer_size: usize,
+    /// Bytes [`wipe_free_space`] leaves free on the filesystem.
+    pub free_space_margin: u64,
+    /// Buffer size for backup and restore copies, clamped to [`MAX_COPY_BUFFER_SIZE`].
+    /// Where the OS can copy in-kernel (`copy_file_range` on Linux) it still does.
+    pub copy_buffer_size: usize,
+    /// Refuse to back up files larger than this many bytes; `None` means no limit.
+    pub max_file_size: Option<u64>,
+    /// Refuse to back up files whose contents do not look like text, such as a binary
+    /// renamed to `.txt`. Off by default, so any file with an allowed extension is accepted.
+    pub require_text_content: bool,
+    /// Whether backup, restore, and delete wait for or give up on a file another caller holds.
+    pub lock_mode: LockMode,
+    /// Directory, relative to `base_dir`, that file backups are written to and restored
+    /// from. `None` keeps them next to the source. Must stay inside `base_dir`.
+    pub backup_dir: Option<PathBuf>,
+}
+
+/// `hook(level, message)`, as passed to [`BackupConfig::set_log_hook`].
+type LogHookFn = dyn Fn(&str, &str) + Send + Sync;
+
+/// Log hook shared between clones of the config.
+#[derive(Clone)]
+struct LogHook(Arc<LogHookFn>);
+
+impl fmt::Debug for LogHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("LogHook")
     }
+}
 
-    // Open source for read
-    let mut reader = File::open(src)
-        .with_context(|| format!("open source {}", src.display()))?;
+/// Where log timestamps, versioned backup names, and manifest times come from.
+/// Set with [`BackupConfig::set_clock`]; any `Fn() -> DateTime<Utc>` closure is a clock.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
 
-    // Create dest with create_new to avoid race
-    let mut writer = OpenOptions::new()
-        .write(true)
-        .create_new(true)
-        .open(&bak)
-        .with_context(|| format!("create backup {}", bak.display()))?;
+/// The real clock, used unless another is configured.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
 
-    io::copy(&mut reader, &mut writer).context("copy to backup failed")?;
-    writer.flush()?;
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
 
-    log_event("INFO", &format!("Backup created for {}", filename)).ok();
-    Ok(bak.to_path_buf())
+impl<F> Clock for F
+where
+    F: Fn() -> DateTime<Utc> + Send + Sync,
+{
+    fn now(&self) -> DateTime<Utc> {
+        self()
+    }
 }
 
-/// Restore from `<filename>.bak` to `<filename>` atomically by writing to a temp file.
-pub fn restore_file(filename: &str) -> Result<PathBuf> {
-    let filename = sanitize_filename(filename)?;
-    let src_bak = Path::new(&(filename.to_string() + ".bak"));
-    within_cwd(src_bak)?;
+/// Clock shared between clones of the config.
+#[derive(Clone)]
+struct SharedClock(Arc<dyn Clock>);
 
-    if !src_bak.exists() || !src_bak.is_file() {
-        anyhow::bail!("backup file does not exist")
+impl fmt::Debug for SharedClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SharedClock")
     }
+}
 
-    let tmp = Path::new(&(filename.to_string() + ".tmp"));
+/// Default `strftime` format of text log timestamps.
+pub const DEFAULT_LOG_TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
 
-    // Open bak for read
-    let mut reader = File::open(src_bak)
-        .with_context(|| format!("open backup {}", src_bak.display()))?;
+/// Default rotation threshold for the logfile.
+pub const DEFAULT_LOG_MAX_BYTES: u64 = 10 * 1024 * 1024;
 
-    // Create temp new file
-    let mut writer = OpenOptions::new()
-        .write(true)
-        .create_new(true)
-        .open(&tmp)
-        .with_context(|| format!("create temp {}", tmp.display()))?;
+/// Default chunk size for secure-delete overwrite passes.
+pub const DEFAULT_WIPE_BUFFER_SIZE: usize = 64 * 1024;
 
-    io::copy(&mut reader, &mut writer).context("copy from backup failed")?;
-    writer.flush()?;
+/// Default space [`wipe_free_space`] leaves free, so other writers keep working.
+pub const DEFAULT_FREE_SPACE_MARGIN: u64 = 256 * 1024 * 1024;
 
-    // Atomic replace
-    fs::rename(&tmp, &filename).with_context(|| {
-        // Clean temp on failure best effort
-        let _ = fs::remove_file(&tmp);
-        format!("rename {} to {}", tmp.display(), filename)
-    })?;
+/// Largest overwrite chunk that will be allocated, whatever the config says.
+pub const MAX_WIPE_BUFFER_SIZE: usize = 16 * 1024 * 1024;
+
+/// Default buffer size for backup and restore copies.
+pub const DEFAULT_COPY_BUFFER_SIZE: usize = 128 * 1024;
+
+/// Largest copy buffer that will be allocated, whatever the config says.
+pub const MAX_COPY_BUFFER_SIZE: usize = 16 * 1024 * 1024;
 
-    log_event("INFO", &format!("Restore completed for {}", filename)).ok();
-    Ok(PathBuf::from(filename))
+/// Shape of the lines appended to the logfile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// `[2024-01-01 00:00:00] INFO: Backup created for x.txt (1234 bytes in 12ms)`
+    #[default]
+    Text,
+    /// One JSON object per line with `timestamp`, `level`, `operation`, `filename`, `message`.
+    Json,
 }
 
-/// Securely delete a file by overwriting with zeros and then removing.
-pub fn delete_file(filename: &str) -> Result<()> {
-    let filename = sanitize_filename(filename)?;
-    let path = Path::new(&filename);
-    within_cwd(path)?;
+/// The operations a front-end may offer, parsed from user input with [`str::parse`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Command {
+    Backup,
+    Restore,
+    Delete,
+}
 
-    if !path.exists() || !path.is_file() {
-        anyhow::bail!("file does not exist")
+impl Command {
+    /// Every command, in the order they are usually listed.
+    pub const ALL: [Command; 3] = [Command::Backup, Command::Restore, Command::Delete];
+
+    /// The lowercase name this command is parsed from.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Command::Backup => "backup",
+            Command::Restore => "restore",
+            Command::Delete => "delete",
+        }
     }
+}
 
-    // Overwrite with zeros
-    let metadata = fs::metadata(path).with_context(|| format!("metadata {}", path.display()))?;
-    let len = metadata.len();
-    {
-        let mut f = OpenOptions::new()
-            .write(true)
-            .open(path)
-            .with_context(|| format!("open {} for overwrite", path.display()))?;
-        // Write in chunks
-        let chunk = vec![0u8; 8192];
-        let mut written: u64 = 0;
-        while written < len {
-            let to_write = std::cmp::min(8192u64, len - written) as usize;
-            f.write_all(&chunk[..to_write])?;
-            written += to_write as u64;
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for Command {
+    type Err = BackupError;
+
+    /// Case-insensitive, ignoring surrounding whitespace.
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        Command::ALL
+            .into_iter()
+            .find(|c| c.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| BackupError::UnknownCommand(s.to_string()))
+    }
+}
+
+/// What an operation does when another caller holds the lock on its file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LockMode {
+    /// Wait until the other caller is done.
+    #[default]
+    Block,
+    /// Fail right away with [`BackupError::Locked`].
+    FailFast,
+}
+
+impl BackupConfig {
+    pub fn new(base_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            base_dir: base_dir.into(),
+            allowed_exts: DEFAULT_ALLOWED_EXTS.iter().map(|e| e.to_string()).collect(),
+            unicode_names: false,
+            allow_subdirs: false,
+            allow_absolute: false,
+            include_hidden: false,
+            max_name_len: DEFAULT_MAX_NAME_LEN,
+            backup_suffix: DEFAULT_BACKUP_SUFFIX.to_string(),
+            log_format: LogFormat::default(),
+            log_path: None,
+            log_timestamp_format: DEFAULT_LOG_TIMESTAMP_FORMAT.to_string(),
+            log_local_time: false,
+            log_hook: None,
+            clock: SharedClock(Arc::new(SystemClock)),
+            metrics: Arc::clone(&PROCESS_COUNTERS),
+            log_note: None,
+            log_batch: None,
+            log_to_file: true,
+            strict_logging: false,
+            log_max_bytes: Some(DEFAULT_LOG_MAX_BYTES),
+            log_keep: 5,
+            follow_symlinks: false,
+            refuse_hard_linked: false,
+            wipe_buffer_size: DEFAULT_WIPE_BUFFER_SIZE,
+            free_space_margin: DEFAULT_FREE_SPACE_MARGIN,
+            copy_buffer_size: DEFAULT_COPY_BUFFER_SIZE,
+            max_file_size: None,
+            require_text_content: false,
+            lock_mode: LockMode::default(),
+            backup_dir: None,
+        }
+    }
+
+    /// Config rooted at the process working directory.
+    pub fn from_cwd() -> Result<Self> {
+        Ok(Self::new(cwd()?))
+    }
+
+    /// Like [`BackupConfig::from_cwd`], with [`ENV_BASE_DIR`] and [`ENV_ALLOWED_EXTS`] applied
+    /// when set. A base dir that is not a directory or a malformed extension list is an error.
+    pub fn from_env() -> Result<Self> {
+        let base_dir = match std::env::var_os(ENV_BASE_DIR) {
+            Some(dir) => {
+                let dir = cwd()?.join(dir);
+                if !dir.is_dir() {
+                    return Err(BackupError::InvalidConfig(format!(
+                        "{} is not a directory: {}",
+                        ENV_BASE_DIR,
+                        dir.display()
+                    )));
+                }
+                dir
+            }
+            None => cwd()?,
+        };
+        let mut cfg = Self::new(base_dir);
+        match std::env::var(ENV_ALLOWED_EXTS) {
+            Ok(list) => cfg.allowed_exts = parse_allowed_exts(&list)?,
+            Err(std::env::VarError::NotPresent) => {}
+            Err(std::env::VarError::NotUnicode(_)) => {
+                return Err(BackupError::InvalidConfig(format!(
+                    "{} is not valid UTF-8",
+                    ENV_ALLOWED_EXTS
+                )))
+            }
+        }
+        Ok(cfg)
+    }
+
+    /// Send log lines to `path` instead of `<base_dir>/logfile.txt`.
+    /// Relative paths are taken from `base_dir`. The file is opened for append right away,
+    /// so a missing or read-only location is reported here rather than on the first event.
+    pub fn set_log_path(&mut self, path: impl Into<PathBuf>) -> Result<()> {
+        let path = path.into();
+        let full = self.base_dir.join(&path);
+        match full.parent() {
+            Some(parent) if parent.as_os_str().is_empty() || parent.is_dir() => {}
+            _ => {
+                return Err(BackupError::InvalidConfig(format!(
+                    "log directory for {} does not exist",
+                    full.display()
+                )))
+            }
+        }
+        OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&full)
+            .with_context(|| format!("log path {} is not writable", full.display()))?;
+        self.log_path = Some(path);
+        Ok(())
+    }
+
+    /// Name backups `<file><suffix>` instead of `<file>.bak`, e.g. `".safebak"` when another
+    /// tool already uses `.bak`. The suffix is a dot followed by ASCII letters, digits, `_`,
+    /// or `-`, at most [`MAX_BACKUP_SUFFIX_LEN`] bytes in all, and must not be one of the
+    /// allowed extensions, or backups would pass for files to back up.
+    pub fn set_backup_suffix(&mut self, suffix: &str) -> Result<()> {
+        let valid = suffix.len() <= MAX_BACKUP_SUFFIX_LEN
+            && suffix.strip_prefix('.').is_some_and(|rest| {
+                !rest.is_empty()
+                    && rest
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            });
+        if !valid {
+            return Err(BackupError::InvalidConfig(format!(
+                "invalid backup suffix {:?}",
+                suffix
+            )));
+        }
+        if self
+            .allowed_exts
+            .iter()
+            .any(|ext| ext.eq_ignore_ascii_case(&suffix[1..]))
+        {
+            return Err(BackupError::InvalidConfig(format!(
+                "backup suffix {} is an allowed extension",
+                suffix
+            )));
+        }
+        self.backup_suffix = suffix.to_string();
+        Ok(())
+    }
+
+    /// Appended to filenames to name their backups; `.bak` unless changed.
+    pub fn backup_suffix(&self) -> &str {
+        &self.backup_suffix
+    }
+
+    /// `filename` with the backup suffix appended.
+    fn suffixed(&self, filename: &str) -> String {
+        format!("{}{}", filename, self.backup_suffix)
+    }
+
+    /// Name length limit, less whatever a suffix longer than `.bak` needs on top of
+    /// [`MAX_SUFFIX_LEN`].
+    fn name_len_limit(&self) -> usize {
+        let extra = self
+            .backup_suffix
+            .len()
+            .saturating_sub(DEFAULT_BACKUP_SUFFIX.len());
+        self.max_name_len.saturating_sub(extra)
+    }
+
+    /// Use `format` (chrono `strftime` syntax) for text log timestamps,
+    /// e.g. `"%Y-%m-%d %H:%M:%S%.3f %z"`. Unknown specifiers are rejected here.
+    /// JSON log lines always carry an RFC 3339 timestamp.
+    pub fn set_log_timestamp_format(&mut self, format: &str) -> Result<()> {
+        if StrftimeItems::new(format).parse().is_err() {
+            return Err(BackupError::InvalidConfig(format!(
+                "invalid log timestamp format {:?}",
+                format
+            )));
+        }
+        self.log_timestamp_format = format.to_string();
+        Ok(())
+    }
+
+    /// `strftime` format of text log timestamps.
+    pub fn log_timestamp_format(&self) -> &str {
+        &self.log_timestamp_format
+    }
+
+    /// Call `hook(level, message)` for every log event, e.g. to forward it to `tracing`.
+    /// The logfile is still written unless [`BackupConfig::log_to_file`] is turned off.
+    pub fn set_log_hook(&mut self, hook: impl Fn(&str, &str) + Send + Sync + 'static) {
+        self.log_hook = Some(LogHook(Arc::new(hook)));
+    }
+
+    /// Take the current time from `clock` instead of the system, e.g. a fixed time in tests.
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = SharedClock(Arc::new(clock));
+    }
+
+    fn now(&self) -> DateTime<Utc> {
+        self.clock.0.now()
+    }
+
+    /// Operation counts for this config and its clones.
+    pub fn metrics(&self) -> Metrics {
+        self.metrics.snapshot()
+    }
+
+    /// Count this config's operations (and its clones') separately from the process-wide
+    /// [`metrics_snapshot`], starting from zero.
+    pub fn use_own_metrics(&mut self) {
+        self.metrics = Arc::default();
+    }
+
+    /// A clone whose log messages end in ` [note: <note>]`.
+    fn with_note(&self, note: &str) -> Result<Self> {
+        let note = note.trim();
+        if note.is_empty() || note.len() > MAX_NOTE_LEN {
+            return Err(BackupError::InvalidArgument(format!(
+                "a note must be 1 to {} bytes",
+                MAX_NOTE_LEN
+            )));
+        }
+        // Keeps every event on one log line.
+        if note.chars().any(char::is_control) {
+            return Err(BackupError::InvalidArgument(
+                "a note must not contain control characters".to_string(),
+            ));
+        }
+        let mut cfg = self.clone();
+        cfg.log_note = Some(note.to_string());
+        Ok(cfg)
+    }
+
+    fn counted<T>(&self, op: Tally, run: impl FnOnce() -> Result<T>) -> Result<T> {
+        let result = run();
+        self.metrics.record(op, result.is_ok());
+        result
+    }
+
+    /// Where log lines are appended.
+    pub fn log_path(&self) -> PathBuf {
+        match &self.log_path {
+            Some(path) => self.base_dir.join(path),
+            None => self.base_dir.join("logfile.txt"),
+        }
+    }
+
+    fn sanitize(&self, name: &str) -> Result<String> {
+        self.check_reserved_suffix(name)?;
+        if self.allow_absolute && Path::new(name).is_absolute() {
+            return self.sanitize_relative(&self.relative_to_base(name)?);
+        }
+        self.sanitize_relative(name)
+    }
+
+    /// Backups and temp files are the crate's own artifacts; taking them as sources would
+    /// only produce `notes.txt.bak.bak` and the like.
+    fn check_reserved_suffix(&self, name: &str) -> Result<()> {
+        for suffix in [self.backup_suffix.as_str(), TEMP_SUFFIX] {
+            let reserved = name.len() >= suffix.len()
+                && name.as_bytes()[name.len() - suffix.len()..]
+                    .eq_ignore_ascii_case(suffix.as_bytes());
+            if reserved {
+                return Err(BackupError::ReservedSuffix {
+                    suffix: suffix.to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// `path` relative to `base_dir`, after resolving symlinks in its parent directories.
+    /// The last component is not resolved, so a symlink is still refused as one.
+    fn relative_to_base(&self, path: &str) -> Result<String> {
+        let path = Path::new(path);
+        let (Some(parent), Some(file)) = (path.parent(), path.file_name()) else {
+            return Err(BackupError::PathEscapesBase);
+        };
+        let resolved = parent
+            .canonicalize()
+            .path_context("canonicalize", parent)?
+            .join(file);
+        if !is_within_base(&self.base_dir, &resolved)? {
+            return Err(BackupError::PathEscapesBase);
+        }
+        let base = self
+            .base_dir
+            .canonicalize()
+            .context("canonicalize base dir failed")?;
+        let relative = resolved
+            .strip_prefix(&base)
+            .map_err(|_| BackupError::PathEscapesBase)?;
+        let parts = relative
+            .components()
+            .map(|c| c.as_os_str().to_str().ok_or(BackupError::InvalidCharacter))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(parts.join("/"))
+    }
+
+    fn sanitize_relative(&self, name: &str) -> Result<String> {
+        if self.allow_subdirs {
+            sanitize_path(
+                name,
+                &self.allowed_exts,
+                self.unicode_names,
+                self.name_len_limit(),
+            )
+        } else {
+            sanitize_file(
+                name,
+                &self.allowed_exts,
+                self.unicode_names,
+                self.name_len_limit(),
+            )
+        }
+    }
+
+    fn resolve(&self, name: &str) -> PathBuf {
+        self.base_dir.join(name)
+    }
+
+    /// Where the backup file `name` lives, relative to `base_dir`.
+    fn backup_name(&self, name: &str) -> String {
+        match &self.backup_dir {
+            Some(dir) => dir.join(name).to_string_lossy().into_owned(),
+            None => name.to_string(),
+        }
+    }
+}
+
+/// Whether `candidate`, taken relative to `base` (absolute paths are used as is), stays
+/// inside `base` once symlinked directories are resolved. This is the same check every
+/// operation in this crate applies before touching a path.
+pub fn is_within_base(base: &Path, candidate: &Path) -> Result<bool> {
+    let base = base
+        .canonicalize()
+        .context("canonicalize base dir failed")?;
+    let parent = candidate.parent().unwrap_or_else(|| Path::new("."));
+    let parent = base.join(parent);
+    // Resolve symlinked directories so they cannot smuggle the path outside.
+    let parent = match parent.canonicalize() {
+        Ok(parent) => parent,
+        // Nothing to resolve, so `..` cannot be checked against the real tree.
+        Err(_) if candidate.components().any(|c| c == Component::ParentDir) => return Ok(false),
+        Err(_) => parent,
+    };
+    let Some(name) = candidate.file_name() else {
+        return Ok(false);
+    };
+    Ok(parent.join(name).starts_with(&base))
+}
+
+fn within_base(cfg: &BackupConfig, p: &Path) -> Result<()> {
+    if !is_within_base(&cfg.base_dir, p)? {
+        return Err(BackupError::PathEscapesBase);
+    }
+    Ok(())
+}
+
+/// Record an event. A logfile that cannot be written is only an error with
+/// [`BackupConfig::strict_logging`]; otherwise the line is lost and the operation goes on.
+fn log_event(
+    cfg: &BackupConfig,
+    level: &str,
+    operation: &str,
+    filename: &str,
+    msg: &str,
+) -> Result<()> {
+    let noted;
+    let msg = match &cfg.log_note {
+        Some(note) => {
+            noted = format!("{} [note: {}]", msg, note);
+            &noted
+        }
+        None => msg,
+    };
+    match write_log_event(cfg, level, operation, filename, msg) {
+        Err(e) if cfg.strict_logging => Err(BackupError::AuditLogFailed(Box::new(e))),
+        _ => Ok(()),
+    }
+}
+
+fn write_log_event(
+    cfg: &BackupConfig,
+    level: &str,
+    operation: &str,
+    filename: &str,
+    msg: &str,
+) -> Result<()> {
+    if let Some(hook) = &cfg.log_hook {
+        (hook.0)(level, msg);
+    }
+    if !cfg.log_to_file {
+        return Ok(());
+    }
+    let now = cfg.now();
+    let (stamp, rfc3339) = if cfg.log_local_time {
+        let now = now.with_timezone(&Local);
+        (
+            now.format(&cfg.log_timestamp_format).to_string(),
+            now.to_rfc3339(),
+        )
+    } else {
+        (
+            now.format(&cfg.log_timestamp_format).to_string(),
+            now.to_rfc3339(),
+        )
+    };
+    let line = match cfg.log_format {
+        LogFormat::Text => format!("[{}] {}: {}\n", stamp, level, msg),
+        LogFormat::Json => {
+            let entry = serde_json::json!({
+                "timestamp": rfc3339,
+                "level": level,
+                "operation": operation,
+                "filename": filename,
+                "message": msg,
+            });
+            format!("{}\n", entry)
+        }
+    };
+    if let Some(batch) = &cfg.log_batch {
+        let mut sink = batch.lock().unwrap_or_else(|e| e.into_inner());
+        // `None` once the batch is gone; clones of its config then append directly.
+        if let Some(pending) = sink.as_mut() {
+            pending.extend_from_slice(line.as_bytes());
+            if pending.len() < LOG_BATCH_FLUSH_BYTES {
+                return Ok(());
+            }
+            return append_to_log(cfg, &std::mem::take(pending));
         }
-        f.flush()?;
     }
+    append_to_log(cfg, line.as_bytes())
+}
 
-    fs::remove_file(path).with_context(|| format!("remove {}", path.display()))?;
-    log_event("INFO", &format!("Secure delete completed for {}", filename)).ok();
+/// Append whole lines to the logfile in one write, rotating it first if they would take
+/// it past `cfg.log_max_bytes`.
+fn append_to_log(cfg: &BackupConfig, lines: &[u8]) -> Result<()> {
+    let path = cfg.log_path();
+    let mut f = open_log_locked(&path)?;
+    if let Some(max) = cfg.log_max_bytes {
+        let len = f.metadata().path_context("metadata", &path)?.len();
+        if len > 0 && len + lines.len() as u64 > max {
+            rotate_logs(&path, cfg.log_keep)?;
+            f = open_log_locked(&path)?;
+        }
+    }
+    f.write_all(lines).path_context("write logfile at", &path)?;
+    // An event that reported success must survive a crash as well as the file it describes.
+    f.sync_data().path_context("sync logfile at", &path)?;
     Ok(())
 }
+
+/// Pending lines a [`LogBatch`] writes out early, so a long run never holds many in memory.
+pub const LOG_BATCH_FLUSH_BYTES: usize = 64 * 1024;
+
+/// Collects the logfile lines of many operations and appends them together, so a bulk run
+/// such as [`backup_many_in`] opens, locks, and syncs the logfile once per
+/// [`LOG_BATCH_FLUSH_BYTES`] instead of once per event. Run operations against
+/// [`LogBatch::config`]; lines are written in order on [`LogBatch::flush`], on
+/// [`LogBatch::finish`], when the batch is dropped, or whenever enough are pending. Until
+/// then a crash loses them, so flush before relying on the log. Log hooks still see each
+/// event as it happens. Clones of the config that outlive the batch log directly again.
+#[derive(Debug)]
+pub struct LogBatch {
+    cfg: BackupConfig,
+}
+
+impl LogBatch {
+    pub fn new(cfg: &BackupConfig) -> Self {
+        let mut cfg = cfg.clone();
+        cfg.log_batch = Some(Arc::new(Mutex::new(Some(Vec::new()))));
+        Self { cfg }
+    }
+
+    /// `cfg` as passed to [`LogBatch::new`], with its log lines going to this batch.
+    pub fn config(&self) -> &BackupConfig {
+        &self.cfg
+    }
+
+    /// Append every pending line to the logfile. Lines that fail to be written are dropped,
+    /// as they would be one at a time, and the error is returned: as
+    /// [`BackupError::AuditLogFailed`] with [`BackupConfig::strict_logging`], and as the
+    /// write error otherwise.
+    pub fn flush(&self) -> Result<()> {
+        flush_log_batch(&self.cfg).map_err(|e| {
+            if self.cfg.strict_logging {
+                BackupError::AuditLogFailed(Box::new(e))
+            } else {
+                e
+            }
+        })
+    }
+
+    /// Flush the pending lines and end the batch, returning what [`LogBatch::flush`] does.
+    /// Dropping the batch flushes too, but has to discard the error.
+    pub fn finish(self) -> Result<()> {
+        let flushed = self.flush();
+        self.close();
+        flushed
+    }
+
+    /// Stop buffering, writing out anything logged since the last flush.
+    fn close(&self) {
+        if let Some(batch) = &self.cfg.log_batch {
+            let mut sink = batch.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(pending) = sink.take().filter(|p| !p.is_empty()) {
+                let _ = append_to_log(&self.cfg, &pending);
+            }
+        }
+    }
+}
+
+impl Drop for LogBatch {
+    fn drop(&mut self) {
+        // Call `finish` to see the error; there is nobody to report it to here.
+        self.close();
+    }
+}
+
+/// Write out the lines `cfg` is holding for a [`LogBatch`], if it is batching.
+fn flush_log_batch(cfg: &BackupConfig) -> Result<()> {
+    let Some(batch) = &cfg.log_batch else {
+        return Ok(());
+    };
+    let mut sink = batch.lock().unwrap_or_else(|e| e.into_inner());
+    match sink.as_mut() {
+        Some(pending) if !pending.is_empty() => append_to_log(cfg, &std::mem::take(pending)),
+        _ => Ok(()),
+    }
+}
+
+/// Open the logfile for append and take an exclusive lock on it, so rotation and
+/// appends from other callers are serialized.
+fn open_log_locked(path: &Path) -> Result<File> {
+    loop {
+        let f = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)
+            .path_context("open logfile at", path)?;
+        f.lock().path_context("lock logfile at", path)?;
+        // Another caller may have rotated the file away while we waited for the lock.
+        if is_same_file(&f, path) {
+            return Ok(f);
+        }
+    }
+}
+
+/// Hex SHA-256 of the whole current logfile (not rotated ones), read in chunks under a
+/// shared lock so no half-written line is included. A missing logfile hashes as empty.
+/// Record it somewhere safe; if the log is later edited, the digest of that prefix changes.
+pub fn log_digest() -> Result<String> {
+    log_digest_in(&BackupConfig::from_env()?)
+}
+
+/// Like [`log_digest`], for the logfile of `cfg`.
+pub fn log_digest_in(cfg: &BackupConfig) -> Result<String> {
+    let path = cfg.log_path();
+    let f = match File::open(&path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(hex_digest(Sha256::new())),
+        Err(e) => return Err(e).path_context("open logfile at", &path),
+    };
+    f.lock_shared().path_context("lock logfile at", &path)?;
+    sha256_reader(f).path_context("hash", &path)
+}
+
+/// Append `Log checkpoint sha256=<digest>` to the log, where the digest is [`log_digest`]
+/// of everything before this line, and return it. Each checkpoint covers the previous
+/// ones, so the checkpoints form a chain over the whole log.
+pub fn append_log_checkpoint() -> Result<String> {
+    append_log_checkpoint_in(&BackupConfig::from_env()?)
+}
+
+/// Like [`append_log_checkpoint`], for the logfile of `cfg`.
+pub fn append_log_checkpoint_in(cfg: &BackupConfig) -> Result<String> {
+    // The digest has to cover every line written before the checkpoint.
+    flush_log_batch(cfg)?;
+    let digest = log_digest_in(cfg)?;
+    // A checkpoint that did not make it into the log is no checkpoint, strict or not.
+    write_log_event(
+        cfg,
+        "INFO",
+        "checkpoint",
+        "",
+        &format!("Log checkpoint sha256={}", digest),
+    )?;
+    flush_log_batch(cfg)?;
+    Ok(digest)
+}
+
+/// Exclusive advisory lock on `<filename>.lock`, released (and the lockfile removed) on drop.
+struct FileLock {
+    _file: File,
+    path: PathBuf,
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        // Removed while still locked; waiters on the old file notice and retry.
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Take the lock that serializes backup, restore, and delete of `filename`.
+fn lock_file(cfg: &BackupConfig, filename: &str) -> Result<FileLock> {
+    let path = cfg.resolve(&format!("{}.lock", filename));
+    loop {
+        let f = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .path_context("open lockfile", &path)?;
+        match cfg.lock_mode {
+            LockMode::Block => f.lock().path_context("lock", &path)?,
+            LockMode::FailFast => match f.try_lock() {
+                Ok(()) => {}
+                Err(fs::TryLockError::WouldBlock) => return Err(BackupError::Locked),
+                Err(fs::TryLockError::Error(source)) => {
+                    return Err(BackupError::Io {
+                        context: format!("lock {}", path.display()),
+                        source,
+                    })
+                }
+            },
+        }
+        // The previous holder may have removed the lockfile while we waited.
+        if is_same_file(&f, &path) {
+            return Ok(FileLock { _file: f, path });
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_same_file(f: &File, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (f.metadata(), fs::metadata(path)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+/// Open files cannot be renamed here, so a locked handle always matches its path.
+#[cfg(not(unix))]
+fn is_same_file(_f: &File, _path: &Path) -> bool {
+    true
+}
+
+/// `logfile.txt` -> `logfile.<n>.txt`
+fn rotated_log_path(path: &Path, n: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{}.{}.{}", stem, n, ext.to_string_lossy()),
+        None => format!("{}.{}", stem, n),
+    };
+    path.with_file_name(name)
+}
+
+/// Shift `logfile.<n>.txt` up by one, dropping anything past `keep`, and move the
+/// current logfile to `logfile.1.txt`. Must be called with the logfile lock held.
+fn rotate_logs(path: &Path, keep: usize) -> Result<()> {
+    if keep == 0 {
+        return fs::remove_file(path).path_context("remove", path);
+    }
+    let oldest = rotated_log_path(path, keep);
+    if oldest.exists() {
+        fs::remove_file(&oldest).path_context("remove", &oldest)?;
+    }
+    for n in (1..keep).rev() {
+        let from = rotated_log_path(path, n);
+        if from.exists() {
+            let to = rotated_log_path(path, n + 1);
+            fs::rename(&from, &to)
+                .with_context(|| format!("rename {} to {}", from.display(), to.display()))?;
+        }
+    }
+    let first = rotated_log_path(path, 1);
+    fs::rename(path, &first)
+        .with_context(|| format!("rename {} to {}", path.display(), first.display()))
+}
+
+/// Validate that `filename` names an existing regular file inside the base dir.
+fn source_path(cfg: &BackupConfig, filename: &str) -> Result<PathBuf> {
+    within_base(cfg, Path::new(filename))?;
+    let src = cfg.resolve(filename);
+    check_source(cfg, &src)?;
+    Ok(src)
+}
+
+/// Fail unless `src` is an existing regular file other than the logfile.
+fn check_source(cfg: &BackupConfig, src: &Path) -> Result<()> {
+    check_not_log_file(cfg, src)?;
+    check_symlink(cfg, src)?;
+    if !src.exists() {
+        return Err(BackupError::SourceMissing);
+    }
+    if !src.is_file() {
+        return Err(BackupError::NotRegularFile);
+    }
+    Ok(())
+}
+
+/// Like [`source_path`], and also enforce `cfg.max_file_size` and
+/// `cfg.require_text_content` before anything is copied.
+fn backup_source(cfg: &BackupConfig, filename: &str) -> Result<PathBuf> {
+    within_base(cfg, Path::new(filename))?;
+    let src = cfg.resolve(filename);
+    check_backup_source(cfg, &src)?;
+    Ok(src)
+}
+
+/// [`check_source`], [`check_size`], and [`check_text_content`].
+fn check_backup_source(cfg: &BackupConfig, src: &Path) -> Result<()> {
+    check_source(cfg, src)?;
+    check_size(cfg, src)?;
+    check_text_content(cfg, src)
+}
+
+/// Validate and resolve the backup file `name` that is about to be written,
+/// creating `cfg.backup_dir` if it does not exist yet.
+fn backup_dest(cfg: &BackupConfig, name: &str) -> Result<PathBuf> {
+    let name = cfg.backup_name(name);
+    within_base(cfg, Path::new(&name))?;
+    let path = cfg.resolve(&name);
+    create_backup_dir(cfg, &path)?;
+    Ok(path)
+}
+
+/// With `cfg.backup_dir`, create the directory the backup `path` goes in.
+fn create_backup_dir(cfg: &BackupConfig, path: &Path) -> Result<()> {
+    if cfg.backup_dir.is_some() {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).path_context("create", dir)?;
+        }
+        // Check again now that the directories exist and symlinks among them resolve.
+        within_base(cfg, path)?;
+    }
+    Ok(())
+}
+
+/// Fail with [`BackupError::FileTooLarge`] if `path` is over `cfg.max_file_size`.
+fn check_size(cfg: &BackupConfig, path: &Path) -> Result<()> {
+    let Some(max) = cfg.max_file_size else {
+        return Ok(());
+    };
+    let size = fs::metadata(path).path_context("metadata", path)?.len();
+    if size > max {
+        return Err(BackupError::FileTooLarge { size, max });
+    }
+    Ok(())
+}
+
+/// How much of a file [`BackupConfig::require_text_content`] looks at.
+pub const TEXT_SNIFF_LEN: usize = 8 * 1024;
+
+/// With `cfg.require_text_content`, fail with [`BackupError::NotTextContent`] unless the
+/// first [`TEXT_SNIFF_LEN`] bytes of `path` look like text: no NUL bytes, and at most one
+/// byte in ten either invalid UTF-8 or a control character other than tab, newline,
+/// carriage return, and form feed.
+fn check_text_content(cfg: &BackupConfig, path: &Path) -> Result<()> {
+    if !cfg.require_text_content {
+        return Ok(());
+    }
+    let file = File::open(path).path_context("open", path)?;
+    let mut head = Vec::with_capacity(TEXT_SNIFF_LEN);
+    file.take(TEXT_SNIFF_LEN as u64)
+        .read_to_end(&mut head)
+        .path_context("read", path)?;
+    if looks_like_text(&head) {
+        Ok(())
+    } else {
+        Err(BackupError::NotTextContent)
+    }
+}
+
+fn looks_like_text(mut bytes: &[u8]) -> bool {
+    if bytes.contains(&0) {
+        return false;
+    }
+    let total = bytes.len();
+    let mut bad = 0usize;
+    while !bytes.is_empty() {
+        let (valid, rest) = match std::str::from_utf8(bytes) {
+            Ok(text) => (text, &[][..]),
+            Err(e) => {
+                let (good, tail) = bytes.split_at(e.valid_up_to());
+                let text = std::str::from_utf8(good).unwrap_or_default();
+                match e.error_len() {
+                    Some(len) => {
+                        bad += len;
+                        (text, &tail[len..])
+                    }
+                    // A character cut off by the end of the sample is not held against it.
+                    None => (text, &[][..]),
+                }
+            }
+        };
+        bad += valid
+            .chars()
+            .filter(|&c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c'))
+            .map(char::len_utf8)
+            .sum::<usize>();
+        bytes = rest;
+    }
+    bad * 10 <= total
+}
+
+/// `is_file` and friends follow symlinks, so a link that passed the name checks could
+/// still point anywhere. Refuse links unless `follow_symlinks` is set, and even then
+/// require the target to resolve inside the base dir.
+fn check_symlink(cfg: &BackupConfig, path: &Path) -> Result<()> {
+    let is_link = fs::symlink_metadata(path)
+        .map(|m| m.file_type().is_symlink())
+        .unwrap_or(false);
+    if !is_link {
+        return Ok(());
+    }
+    if !cfg.follow_symlinks {
+        return Err(BackupError::Symlink);
+    }
+    let base = cfg
+        .base_dir
+        .canonicalize()
+        .context("canonicalize base dir failed")?;
+    let target = path.canonicalize().path_context("resolve symlink", path)?;
+    if !target.starts_with(&base) {
+        return Err(BackupError::PathEscapesBase);
+    }
+    Ok(())
+}
+
+/// Refuse to copy a file onto itself, whether by the same name or through a symlink.
+fn ensure_distinct(src: &Path, dest: &Path) -> Result<()> {
+    if same_path(src, dest) {
+        return Err(BackupError::SourceEqualsDestination);
+    }
+    Ok(())
+}
+
+/// Compare canonical paths when both exist, and the paths as given otherwise.
+fn same_path(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Refuse to touch the file [`log_event`] appends to.
+fn check_not_log_file(cfg: &BackupConfig, path: &Path) -> Result<()> {
+    if same_path(path, &cfg.log_path()) {
+        return Err(BackupError::CannotOperateOnLogFile);
+    }
+    Ok(())
+}
+
+/// `io::copy` through a `BufReader` and `BufWriter` of `buffer_size` bytes each,
+/// clamped to `8192..=MAX_COPY_BUFFER_SIZE`.
+fn copy_buffered(reader: &mut File, writer: &mut File, buffer_size: usize) -> io::Result<u64> {
+    let size = buffer_size.clamp(8192, MAX_COPY_BUFFER_SIZE);
+    let mut reader = BufReader::with_capacity(size, reader);
+    let mut writer = BufWriter::with_capacity(size, writer);
+    let bytes = io::copy(&mut reader, &mut writer)?;
+    writer.flush()?;
+    Ok(bytes)
+}
+
+/// Copy `src` into a freshly created `dest`; fails if `dest` already exists.
+fn copy_to_new(cfg: &BackupConfig, src: &Path, dest: &Path) -> Result<u64> {
+    copy_to_new_by(cfg, src, dest, |reader, writer, _| {
+        copy_buffered(reader, writer, cfg.copy_buffer_size)
+    })
+}
+
+/// Like [`copy_to_new`], with the byte transfer done by `copy(reader, writer, source_len)`.
+fn copy_to_new_by<F>(cfg: &BackupConfig, src: &Path, dest: &Path, copy: F) -> Result<u64>
+where
+    F: FnOnce(&mut File, &mut File, u64) -> io::Result<u64>,
+{
+    ensure_distinct(src, dest)?;
+    // Open source for read
+    let mut reader = File::open(src).path_context("open source", src)?;
+    let meta = reader.metadata().path_context("metadata", src)?;
+
+    // Create dest with create_new to avoid race
+    let mut writer = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(dest)
+        .path_context("create backup", dest)?;
+
+    let bytes = copy(&mut reader, &mut writer, meta.len()).context("copy to backup failed")?;
+    writer.flush().context("flush backup failed")?;
+    preserve_metadata(cfg, &writer, dest, &meta);
+    Ok(bytes)
+}
+
+/// Copy `src` into a fresh `tmp`, sync it, then rename it over `dest`.
+/// `dest` is either left as it was or fully replaced, never partially written.
+fn copy_atomic(cfg: &BackupConfig, src: &Path, tmp: &Path, dest: &Path) -> Result<u64> {
+    copy_atomic_by(cfg, src, tmp, dest, |reader, writer, _| {
+        copy_buffered(reader, writer, cfg.copy_buffer_size)
+    })
+}
+
+/// Like [`copy_atomic`], with the byte transfer done by `copy(reader, writer, source_len)`.
+fn copy_atomic_by<F>(
+    cfg: &BackupConfig,
+    src: &Path,
+    tmp: &Path,
+    dest: &Path,
+    copy: F,
+) -> Result<u64>
+where
+    F: FnOnce(&mut File, &mut File, u64) -> io::Result<u64>,
+{
+    ensure_distinct(src, dest)?;
+    // Open source for read
+    let mut reader = File::open(src).path_context("open source", src)?;
+    let meta = reader.metadata().path_context("metadata", src)?;
+
+    // Create temp new file; it is removed again on any early return
+    let mut temp = TempFile::create_new(tmp)?;
+    let writer = temp.file();
+
+    let bytes = copy(&mut reader, writer, meta.len())
+        .with_context(|| format!("copy {} to temp failed", src.display()))?;
+    writer.flush().context("flush temp failed")?;
+    preserve_metadata(cfg, writer, tmp, &meta);
+    // Data must be on disk before the rename makes it visible under the real name.
+    writer.sync_all().path_context("sync temp", tmp)?;
+
+    // Atomic replace
+    temp.persist(dest)?;
+    sync_dir(dest.parent().unwrap_or(&cfg.base_dir))?;
+    Ok(bytes)
+}
+
+/// A temp file created with `create_new`, deleted on drop (early return, error, or panic)
+/// unless [`TempFile::persist`] has renamed it into place.
+struct TempFile<'a> {
+    file: Option<File>,
+    path: &'a Path,
+    armed: bool,
+}
+
+impl<'a> TempFile<'a> {
+    fn create_new(path: &'a Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .path_context("create temp", path)?;
+        Ok(Self {
+            file: Some(file),
+            path,
+            armed: true,
+        })
+    }
+
+    fn file(&mut self) -> &mut File {
+        self.file
+            .as_mut()
+            .expect("temp file is open until persisted")
+    }
+
+    /// Close the file and rename it over `dest`. Should the two still end up on different
+    /// filesystems, the file is copied next to `dest` and renamed from there instead.
+    fn persist(mut self, dest: &Path) -> Result<()> {
+        drop(self.file.take());
+        match fs::rename(self.path, dest) {
+            Ok(()) => self.armed = false,
+            // Still armed, so dropping `self` removes the original temp.
+            Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+                copy_across_devices(self.path, dest).map_err(|source| BackupError::CrossDevice {
+                    path: dest.to_path_buf(),
+                    source,
+                })?
+            }
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!("rename {} to {}", self.path.display(), dest.display())
+                })
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The temp file a write to `dest` goes through. It sits in the same directory, so
+/// renaming it over `dest` stays on one filesystem and is atomic.
+fn temp_path(dest: &Path) -> PathBuf {
+    let mut name = dest.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    dest.with_file_name(name)
+}
+
+/// Fallback for [`TempFile::persist`] when `tmp` and `dest` are on different filesystems:
+/// copy `tmp` to a second temp beside `dest`, sync it, and rename that into place.
+fn copy_across_devices(tmp: &Path, dest: &Path) -> io::Result<()> {
+    let mut name = dest.file_name().unwrap_or_default().to_os_string();
+    name.push(".xdev.tmp");
+    let local = dest.with_file_name(name);
+    let result = (|| {
+        let mut from = File::open(tmp)?;
+        let meta = from.metadata()?;
+        let mut to = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&local)?;
+        io::copy(&mut from, &mut to)?;
+        to.set_permissions(meta.permissions())?;
+        to.set_modified(meta.modified()?)?;
+        to.sync_all()?;
+        drop(to);
+        fs::rename(&local, dest)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&local);
+    }
+    result
+}
+
+impl Drop for TempFile<'_> {
+    fn drop(&mut self) {
+        // Close first: some platforms refuse to remove a file that is still open.
+        drop(self.file.take());
+        if self.armed {
+            let _ = fs::remove_file(self.path);
+        }
+    }
+}
+
+/// Carry permissions and mtime from `meta` over to the freshly written `dest`.
+/// Best effort: where the platform refuses, the copy is kept and a warning is logged.
+fn preserve_metadata(cfg: &BackupConfig, dest_file: &File, dest: &Path, meta: &fs::Metadata) {
+    let result = meta
+        .modified()
+        .and_then(|mtime| dest_file.set_modified(mtime))
+        .and_then(|()| fs::set_permissions(dest, meta.permissions()));
+    if let Err(e) = result {
+        log_event(
+            cfg,
+            "WARN",
+            "preserve_metadata",
+            &dest.display().to_string(),
+            &format!("Could not preserve metadata on {}: {}", dest.display(), e),
+        )
+        .ok();
+    }
+}
+
+/// Flush a directory's entries to disk so a completed rename survives a crash.
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> Result<()> {
+    File::open(dir)
+        .and_then(|d| d.sync_all())
+        .path_context("sync directory", dir)
+}
+
+/// Directories cannot be opened for syncing here; rename durability is left to the OS.
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Outcome of a copy: where the bytes went and how many were transferred.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BackupReport {
+    pub path: PathBuf,
+    pub bytes: u64,
+}
+
+/// A filename that has passed [`sanitize_filename`] and the base-dir checks, from
+/// [`prepare`]. Pass it to [`backup`], [`restore`], or [`delete`] to skip validating the
+/// name again. Symlinks and the base dir are checked again on the stored paths, and the
+/// state of the files themselves by each operation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidatedTarget {
+    filename: String,
+    path: PathBuf,
+    backup: PathBuf,
+    // The settings `filename`, `path`, and `backup` were derived under.
+    base_dir: PathBuf,
+    backup_suffix: String,
+    backup_dir: Option<PathBuf>,
+    allowed_exts: Vec<String>,
+    allow_subdirs: bool,
+    allow_absolute: bool,
+    unicode_names: bool,
+    max_name_len: usize,
+    follow_symlinks: bool,
+}
+
+impl ValidatedTarget {
+    /// The sanitized name, relative to the base dir.
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    /// Path of the file: the base dir joined with [`filename`](Self::filename). Symlinks
+    /// are not resolved.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Path of its `.bak`, under `backup_dir` when one is set.
+    pub fn backup_path(&self) -> &Path {
+        &self.backup
+    }
+
+    /// Fail with [`BackupError::InvalidArgument`] unless `cfg` has every setting the name
+    /// was validated under, then repeat the symlink and base-dir checks on both paths.
+    fn check(&self, cfg: &BackupConfig) -> Result<()> {
+        let changed = [
+            (self.base_dir != cfg.base_dir, "base_dir"),
+            (self.backup_suffix != cfg.backup_suffix, "backup_suffix"),
+            (self.backup_dir != cfg.backup_dir, "backup_dir"),
+            (self.allowed_exts != cfg.allowed_exts, "allowed_exts"),
+            (self.allow_subdirs != cfg.allow_subdirs, "allow_subdirs"),
+            (self.allow_absolute != cfg.allow_absolute, "allow_absolute"),
+            (self.unicode_names != cfg.unicode_names, "unicode_names"),
+            (self.max_name_len != cfg.max_name_len, "max_name_len"),
+            (
+                self.follow_symlinks != cfg.follow_symlinks,
+                "follow_symlinks",
+            ),
+        ];
+        if let Some((_, setting)) = changed.iter().find(|(differs, _)| *differs) {
+            return Err(BackupError::InvalidArgument(format!(
+                "{} was prepared under a different {}",
+                self.filename, setting
+            )));
+        }
+        for path in [&self.path, &self.backup] {
+            within_base(cfg, path)?;
+            check_symlink(cfg, path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Validate `filename` once, for a caller about to run several operations on it.
+/// Neither the file nor its backup has to exist yet.
+pub fn prepare(filename: &str) -> Result<ValidatedTarget> {
+    prepare_in(&BackupConfig::from_env()?, filename)
+}
+
+/// Like [`prepare`], but resolves `filename` against `cfg.base_dir`.
+pub fn prepare_in(cfg: &BackupConfig, filename: &str) -> Result<ValidatedTarget> {
+    let filename = cfg.sanitize(filename)?;
+    within_base(cfg, Path::new(&filename))?;
+    let backup = cfg.backup_name(&cfg.suffixed(&filename));
+    within_base(cfg, Path::new(&backup))?;
+    Ok(ValidatedTarget {
+        path: cfg.resolve(&filename),
+        backup: cfg.resolve(&backup),
+        filename,
+        base_dir: cfg.base_dir.clone(),
+        backup_suffix: cfg.backup_suffix.clone(),
+        backup_dir: cfg.backup_dir.clone(),
+        allowed_exts: cfg.allowed_exts.clone(),
+        allow_subdirs: cfg.allow_subdirs,
+        allow_absolute: cfg.allow_absolute,
+        unicode_names: cfg.unicode_names,
+        max_name_len: cfg.max_name_len,
+        follow_symlinks: cfg.follow_symlinks,
+    })
+}
+
+/// [`backup_file`] for a target from [`prepare`].
+pub fn backup(target: &ValidatedTarget) -> Result<PathBuf> {
+    backup_in(&BackupConfig::from_env()?, target)
+}
+
+/// Like [`backup`], for a target prepared with [`prepare_in`] under the same `cfg`.
+pub fn backup_in(cfg: &BackupConfig, target: &ValidatedTarget) -> Result<PathBuf> {
+    cfg.counted(Tally::Backup, || {
+        target.check(cfg)?;
+        backup_target(cfg, target, BackupOptions::default()).map(|r| r.path)
+    })
+}
+
+/// Create `<filename>.bak` without overwriting. The copy is written to `<filename>.bak.tmp`
+/// and renamed into place once complete, so the backup is never seen half-written.
+/// Its size and SHA-256 are then recorded in [`MANIFEST_NAME`]; see [`verify_manifest`].
+pub fn backup_file(filename: &str) -> Result<PathBuf> {
+    backup_file_in(&BackupConfig::from_env()?, filename)
+}
+
+/// Like [`backup_file`], but resolves `filename` against `cfg.base_dir`.
+pub fn backup_file_in(cfg: &BackupConfig, filename: &str) -> Result<PathBuf> {
+    backup_file_reported_in(cfg, filename).map(|r| r.path)
+}
+
+/// Like [`backup_file`], but also reports the number of bytes copied.
+pub fn backup_file_reported(filename: &str) -> Result<BackupReport> {
+    backup_file_reported_in(&BackupConfig::from_env()?, filename)
+}
+
+/// Like [`backup_file_reported`], but resolves `filename` against `cfg.base_dir`.
+pub fn backup_file_reported_in(cfg: &BackupConfig, filename: &str) -> Result<BackupReport> {
+    backup_with(cfg, filename, BackupOptions::default())
+}
+
+/// Knobs for [`backup_file_opts`]. The default refuses to replace an existing backup.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BackupOptions {
+    /// Replace an existing `<filename>.bak` atomically instead of failing.
+    pub overwrite: bool,
+}
+
+/// Like [`backup_file`], with options. With `overwrite`, the new copy is written to
+/// `<filename>.bak.tmp` and renamed over the old backup, so a failed copy leaves it intact.
+pub fn backup_file_opts(filename: &str, opts: BackupOptions) -> Result<PathBuf> {
+    backup_file_opts_in(&BackupConfig::from_env()?, filename, opts)
+}
+
+/// Like [`backup_file_opts`], but resolves `filename` against `cfg.base_dir`.
+pub fn backup_file_opts_in(
+    cfg: &BackupConfig,
+    filename: &str,
+    opts: BackupOptions,
+) -> Result<PathBuf> {
+    backup_with(cfg, filename, opts).map(|r| r.path)
+}
+
+fn backup_with(cfg: &BackupConfig, filename: &str, opts: BackupOptions) -> Result<BackupReport> {
+    cfg.counted(Tally::Backup, || make_backup(cfg, filename, opts))
+}
+
+fn make_backup(cfg: &BackupConfig, filename: &str, opts: BackupOptions) -> Result<BackupReport> {
+    backup_target(cfg, &prepare_in(cfg, filename)?, opts)
+}
+
+fn backup_target(
+    cfg: &BackupConfig,
+    target: &ValidatedTarget,
+    opts: BackupOptions,
+) -> Result<BackupReport> {
+    let start = Instant::now();
+    let filename = target.filename();
+    let (src, bak) = (target.path(), target.backup_path());
+    check_backup_source(cfg, src)?;
+    create_backup_dir(cfg, bak)?;
+    let _lock = lock_file(cfg, filename)?;
+
+    ensure_distinct(src, bak)?;
+    if !opts.overwrite && bak.exists() {
+        return Err(BackupError::BackupExists);
+    }
+    // Written aside and renamed in, so a crash never leaves a partial `.bak` behind.
+    let bytes = copy_atomic(cfg, src, &temp_path(bak), bak)?;
+    record_in_manifest(cfg, filename, bak)?;
+
+    log_event(
+        cfg,
+        "INFO",
+        "backup",
+        filename,
+        &format!("Backup created for {} {}", filename, timing(bytes, start)),
+    )?;
+    Ok(BackupReport {
+        path: bak.to_path_buf(),
+        bytes,
+    })
+}
+
+/// Copy `external`, a file anywhere on disk given by absolute path, into the base dir as
+/// `dest_name`, then back it up as [`backup_file`] does. Fails before reading anything if
+/// `dest_name` or its backup already exists. Returns the imported file and its backup.
+pub fn import_and_backup(external: &Path, dest_name: &str) -> Result<(PathBuf, PathBuf)> {
+    import_and_backup_in(&BackupConfig::from_env()?, external, dest_name)
+}
+
+/// Like [`import_and_backup`], importing into `cfg.base_dir`.
+pub fn import_and_backup_in(
+    cfg: &BackupConfig,
+    external: &Path,
+    dest_name: &str,
+) -> Result<(PathBuf, PathBuf)> {
+    cfg.counted(Tally::Backup, || import(cfg, external, dest_name))
+}
+
+fn import(cfg: &BackupConfig, external: &Path, dest_name: &str) -> Result<(PathBuf, PathBuf)> {
+    let start = Instant::now();
+    let dest_name = cfg.sanitize(dest_name)?;
+    if !external.is_absolute() {
+        return Err(BackupError::InvalidArgument(format!(
+            "import source {} must be an absolute path",
+            external.display()
+        )));
+    }
+    match fs::metadata(external) {
+        Ok(meta) if meta.is_file() => {}
+        Ok(_) => return Err(BackupError::NotRegularFile),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(BackupError::SourceMissing),
+        Err(e) => return Err(e).path_context("metadata", external),
+    }
+    check_size(cfg, external)?;
+    check_text_content(cfg, external)?;
+
+    within_base(cfg, Path::new(&dest_name))?;
+    let dest = cfg.resolve(&dest_name);
+    check_not_log_file(cfg, &dest)?;
+    let bak = backup_dest(cfg, &cfg.suffixed(&dest_name))?;
+    {
+        let _lock = lock_file(cfg, &dest_name)?;
+        if fs::symlink_metadata(&dest).is_ok() {
+            return Err(BackupError::DestinationExists);
+        }
+        if bak.exists() {
+            return Err(BackupError::BackupExists);
+        }
+        log_event(
+            cfg,
+            "INFO",
+            "import",
+            &dest_name,
+            &format!(
+                "Reading external file {} into {}",
+                external.display(),
+                dest_name
+            ),
+        )?;
+        let bytes = copy_atomic(cfg, external, &temp_path(&dest), &dest)?;
+        log_event(
+            cfg,
+            "INFO",
+            "import",
+            &dest_name,
+            &format!(
+                "Imported {} as {} {}",
+                external.display(),
+                dest_name,
+                timing(bytes, start)
+            ),
+        )?;
+    }
+    // The lock is not reentrant, so the backup takes it afresh.
+    let report = make_backup(cfg, &dest_name, BackupOptions::default())?;
+    Ok((dest, report.path))
+}
+
+/// Like [`backup_file`], but copies in 8192-byte chunks and calls
+/// `on_progress(bytes_done, total_bytes)` after each one. `total_bytes` is the source
+/// length when the copy starts. The callback only observes; it cannot abort the copy.
+pub fn backup_file_with_progress(
+    filename: &str,
+    on_progress: impl FnMut(u64, u64),
+) -> Result<PathBuf> {
+    backup_file_with_progress_in(&BackupConfig::from_env()?, filename, on_progress)
+}
+
+/// Like [`backup_file_with_progress`], but resolves `filename` against `cfg.base_dir`.
+pub fn backup_file_with_progress_in(
+    cfg: &BackupConfig,
+    filename: &str,
+    on_progress: impl FnMut(u64, u64),
+) -> Result<PathBuf> {
+    cfg.counted(Tally::Backup, || {
+        backup_with_progress(cfg, filename, on_progress)
+    })
+}
+
+fn backup_with_progress(
+    cfg: &BackupConfig,
+    filename: &str,
+    mut on_progress: impl FnMut(u64, u64),
+) -> Result<PathBuf> {
+    let start = Instant::now();
+    let filename = cfg.sanitize(filename)?;
+    let src = backup_source(cfg, &filename)?;
+    let bak = backup_dest(cfg, &cfg.suffixed(&filename))?;
+    let _lock = lock_file(cfg, &filename)?;
+    if bak.exists() {
+        return Err(BackupError::BackupExists);
+    }
+    let tmp = backup_dest(cfg, &format!("{}.tmp", cfg.suffixed(&filename)))?;
+
+    let bytes = copy_atomic_by(cfg, &src, &tmp, &bak, |reader, writer, total| {
+        let mut chunk = [0u8; 8192];
+        let mut done = 0u64;
+        loop {
+            let n = match reader.read(&mut chunk) {
+                Ok(0) => return Ok(done),
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            writer.write_all(&chunk[..n])?;
+            done += n as u64;
+            on_progress(done, total);
+        }
+    })?;
+    record_in_manifest(cfg, &filename, &bak)?;
+
+    log_event(
+        cfg,
+        "INFO",
+        "backup",
+        &filename,
+        &format!("Backup created for {} {}", filename, timing(bytes, start)),
+    )?;
+    Ok(bak)
+}
+
+/// Validate `filename` like [`backup_file`] and copy it into `dest` instead of a local
+/// `.bak`, e.g. an upload stream. Returns the bytes written; `dest` is flushed but not closed.
+pub fn backup_to_writer(filename: &str, dest: &mut impl Write) -> Result<u64> {
+    backup_to_writer_in(&BackupConfig::from_env()?, filename, dest)
+}
+
+/// Like [`backup_to_writer`], but resolves `filename` against `cfg.base_dir`.
+pub fn backup_to_writer_in(
+    cfg: &BackupConfig,
+    filename: &str,
+    dest: &mut impl Write,
+) -> Result<u64> {
+    cfg.counted(Tally::Backup, || stream_backup(cfg, filename, dest))
+}
+
+fn stream_backup(cfg: &BackupConfig, filename: &str, dest: &mut impl Write) -> Result<u64> {
+    let start = Instant::now();
+    let filename = cfg.sanitize(filename)?;
+    let src = backup_source(cfg, &filename)?;
+    let _lock = lock_file(cfg, &filename)?;
+
+    let reader = File::open(&src).path_context("open source", &src)?;
+    let mut reader = BufReader::with_capacity(
+        cfg.copy_buffer_size.clamp(8192, MAX_COPY_BUFFER_SIZE),
+        reader,
+    );
+    let bytes = io::copy(&mut reader, dest)
+        .with_context(|| format!("copy {} to writer failed", src.display()))?;
+    dest.flush().context("flush writer failed")?;
+
+    log_event(
+        cfg,
+        "INFO",
+        "backup",
+        &filename,
+        &format!("Backup streamed for {} {}", filename, timing(bytes, start)),
+    )?;
+    Ok(bytes)
+}
+
+/// Write everything read from `src` to `<name>.bak`, as if `name` had been backed up,
+/// without `name` having to exist, e.g. for content piped to stdin. `name` must pass the
+/// same checks as for [`backup_file`], an existing backup is never replaced, and a stream
+/// longer than `max_file_size` is refused without leaving a backup behind.
+pub fn backup_stdin(name: &str, src: &mut impl Read) -> Result<PathBuf> {
+    backup_stdin_in(&BackupConfig::from_env()?, name, src)
+}
+
+/// Like [`backup_stdin`], but writes the backup under `cfg.base_dir`.
+pub fn backup_stdin_in(cfg: &BackupConfig, name: &str, src: &mut impl Read) -> Result<PathBuf> {
+    cfg.counted(Tally::Backup, || backup_stream(cfg, name, src))
+}
+
+fn backup_stream(cfg: &BackupConfig, name: &str, src: &mut impl Read) -> Result<PathBuf> {
+    let start = Instant::now();
+    let filename = cfg.sanitize(name)?;
+    within_base(cfg, Path::new(&filename))?;
+    check_not_log_file(cfg, &cfg.resolve(&filename))?;
+    let bak = backup_dest(cfg, &cfg.suffixed(&filename))?;
+    let _lock = lock_file(cfg, &filename)?;
+    if bak.exists() {
+        return Err(BackupError::BackupExists);
+    }
+
+    let tmp = backup_dest(cfg, &format!("{}.tmp", cfg.suffixed(&filename)))?;
+    let mut temp = TempFile::create_new(&tmp)?;
+    let writer = temp.file();
+    let bytes = match cfg.max_file_size {
+        Some(max) => {
+            let bytes = io::copy(&mut src.take(max.saturating_add(1)), writer)
+                .with_context(|| format!("copy stream to {} failed", tmp.display()))?;
+            if bytes > max {
+                // Drain the rest only to report the real size.
+                let rest = io::copy(src, &mut io::sink()).context("read stream failed")?;
+                return Err(BackupError::FileTooLarge {
+                    size: bytes + rest,
+                    max,
+                });
+            }
+            bytes
+        }
+        None => io::copy(src, writer)
+            .with_context(|| format!("copy stream to {} failed", tmp.display()))?,
+    };
+    writer.sync_all().path_context("sync temp", &tmp)?;
+    temp.persist(&bak)?;
+    sync_dir(bak.parent().unwrap_or(&cfg.base_dir))?;
+    record_in_manifest(cfg, &filename, &bak)?;
+
+    log_event(
+        cfg,
+        "INFO",
+        "backup",
+        &filename,
+        &format!(
+            "Backup created for {} from a stream {}",
+            filename,
+            timing(bytes, start)
+        ),
+    )?;
+    Ok(bak)
+}
+
+/// How often [`backup_file_resumable`] syncs `.part`, so a crash loses at most this much.
+const RESUME_SYNC_INTERVAL: u64 = 8 * 1024 * 1024;
+
+/// Like [`backup_file`], but copies into `<filename>.bak.part` and can pick up where an
+/// interrupted run stopped. The bytes already in `.part` are compared with the start of
+/// the source first; if they differ, or `.part` is longer than the source, the copy starts
+/// over. The finished `.part` is renamed to `<filename>.bak`.
+pub fn backup_file_resumable(filename: &str) -> Result<PathBuf> {
+    backup_file_resumable_in(&BackupConfig::from_env()?, filename)
+}
+
+/// Like [`backup_file_resumable`], but resolves `filename` against `cfg.base_dir`.
+pub fn backup_file_resumable_in(cfg: &BackupConfig, filename: &str) -> Result<PathBuf> {
+    cfg.counted(Tally::Backup, || backup_resumable(cfg, filename))
+}
+
+fn backup_resumable(cfg: &BackupConfig, filename: &str) -> Result<PathBuf> {
+    let start = Instant::now();
+    let filename = cfg.sanitize(filename)?;
+    let src = backup_source(cfg, &filename)?;
+    let bak = backup_dest(cfg, &cfg.suffixed(&filename))?;
+    let _lock = lock_file(cfg, &filename)?;
+
+    ensure_distinct(&src, &bak)?;
+    if bak.exists() {
+        return Err(BackupError::BackupExists);
+    }
+    let part = backup_dest(cfg, &format!("{}.part", cfg.suffixed(&filename)))?;
+    check_symlink(cfg, &part)?;
+
+    let mut reader = File::open(&src).path_context("open source", &src)?;
+    let meta = reader.metadata().path_context("metadata", &src)?;
+    let mut writer = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&part)
+        .path_context("open partial backup", &part)?;
+    let done = writer.metadata().path_context("metadata", &part)?.len();
+    let resume_at = if done <= meta.len() && same_prefix(&mut reader, &mut writer, done)? {
+        done
+    } else {
+        if done > 0 {
+            log_event(
+                cfg,
+                "WARN",
+                "backup",
+                &filename,
+                &format!(
+                    "Partial backup of {} does not match; starting over",
+                    filename
+                ),
+            )?;
+        }
+        writer.set_len(0).path_context("truncate", &part)?;
+        0
+    };
+    reader
+        .seek(SeekFrom::Start(resume_at))
+        .path_context("seek", &src)?;
+    writer
+        .seek(SeekFrom::Start(resume_at))
+        .path_context("seek", &part)?;
+
+    let mut buf = vec![0u8; cfg.copy_buffer_size.clamp(8192, MAX_COPY_BUFFER_SIZE)];
+    let mut copied = 0u64;
+    let mut unsynced = 0u64;
+    loop {
+        let n = read_full(&mut reader, &mut buf).path_context("read", &src)?;
+        if n == 0 {
+            break;
+        }
+        writer.write_all(&buf[..n]).path_context("write", &part)?;
+        copied += n as u64;
+        unsynced += n as u64;
+        if unsynced >= RESUME_SYNC_INTERVAL {
+            writer.sync_data().path_context("sync", &part)?;
+            unsynced = 0;
+        }
+    }
+    preserve_metadata(cfg, &writer, &part, &meta);
+    writer.sync_all().path_context("sync", &part)?;
+    drop(writer);
+    fs::rename(&part, &bak)
+        .with_context(|| format!("rename {} to {}", part.display(), bak.display()))?;
+    sync_dir(bak.parent().unwrap_or(&cfg.base_dir))?;
+    record_in_manifest(cfg, &filename, &bak)?;
+
+    let resumed = if resume_at > 0 {
+        format!(", resumed at byte {}", resume_at)
+    } else {
+        String::new()
+    };
+    log_event(
+        cfg,
+        "INFO",
+        "backup",
+        &filename,
+        &format!(
+            "Backup created for {} {}{}",
+            filename,
+            timing(resume_at + copied, start),
+            resumed
+        ),
+    )?;
+    Ok(bak)
+}
+
+/// Whether the first `len` bytes of `a` and `b` are the same, reading both from the start.
+fn same_prefix(a: &mut File, b: &mut File, len: u64) -> Result<bool> {
+    a.rewind().context("seek failed")?;
+    b.rewind().context("seek failed")?;
+    let mut a = a.take(len);
+    let mut b = b.take(len);
+    let mut buf_a = [0u8; 8192];
+    let mut buf_b = [0u8; 8192];
+    loop {
+        let na = read_full(&mut a, &mut buf_a).context("read failed")?;
+        let nb = read_full(&mut b, &mut buf_b).context("read failed")?;
+        if buf_a[..na] != buf_b[..nb] {
+            return Ok(false);
+        }
+        if na == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+/// `(<bytes> bytes in <n>ms)`, appended to the completion log lines.
+fn timing(bytes: u64, start: Instant) -> String {
+    format!("({} bytes in {}ms)", bytes, start.elapsed().as_millis())
+}
+
+/// Hex SHA-256 of a file, read in fixed-size chunks so large files are not buffered.
+fn sha256_file(path: &Path) -> Result<String> {
+    let reader = File::open(path).path_context("open", path)?;
+    sha256_reader(reader).path_context("hash", path)
+}
+
+fn sha256_reader(mut reader: impl Read) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut reader, &mut hasher)?;
+    Ok(hex_digest(hasher))
+}
+
+fn hex_digest(hasher: Sha256) -> String {
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Like [`backup_file`], then re-reads source and backup and compares their SHA-256.
+/// Returns the backup path and its hex digest.
+pub fn backup_file_verified(filename: &str) -> Result<(PathBuf, String)> {
+    backup_file_verified_in(&BackupConfig::from_env()?, filename)
+}
+
+/// Like [`backup_file_verified`], but resolves `filename` against `cfg.base_dir`.
+pub fn backup_file_verified_in(cfg: &BackupConfig, filename: &str) -> Result<(PathBuf, String)> {
+    let bak = backup_file_in(cfg, filename)?;
+    let source_hex = sha256_file(&cfg.resolve(&cfg.sanitize(filename)?))?;
+    let backup_hex = sha256_file(&bak)?;
+    if source_hex != backup_hex {
+        return Err(BackupError::ChecksumMismatch {
+            source_hex,
+            backup_hex,
+        });
+    }
+    Ok((bak, backup_hex))
+}
+
+/// Re-hash `<filename>.bak` and compare it with a digest from [`backup_file_verified`].
+pub fn verify_backup(filename: &str, expected_hex: &str) -> Result<bool> {
+    verify_backup_in(&BackupConfig::from_env()?, filename, expected_hex)
+}
+
+/// Like [`verify_backup`], but resolves `filename` against `cfg.base_dir`.
+pub fn verify_backup_in(cfg: &BackupConfig, filename: &str, expected_hex: &str) -> Result<bool> {
+    let filename = cfg.sanitize(filename)?;
+    let bak_name = cfg.backup_name(&cfg.suffixed(&filename));
+    within_base(cfg, Path::new(&bak_name))?;
+    let bak = cfg.resolve(&bak_name);
+    if !bak.is_file() {
+        return Err(BackupError::BackupMissing);
+    }
+    Ok(sha256_file(&bak)?.eq_ignore_ascii_case(expected_hex.trim()))
+}
+
+/// Replace `<filename>.bak` with a fresh copy of the source if the two differ, e.g. after
+/// a partial write or bit rot. The new copy is renamed over the old one, so the backup is
+/// never missing. Returns the backup path, untouched when it already matched.
+/// Fails with [`BackupError::SourceMissing`] when there is no source to repair from.
+pub fn repair_backup(filename: &str) -> Result<PathBuf> {
+    repair_backup_in(&BackupConfig::from_env()?, filename)
+}
+
+/// Like [`repair_backup`], but resolves `filename` against `cfg.base_dir`.
+pub fn repair_backup_in(cfg: &BackupConfig, filename: &str) -> Result<PathBuf> {
+    cfg.counted(Tally::Backup, || repair(cfg, filename))
+}
+
+fn repair(cfg: &BackupConfig, filename: &str) -> Result<PathBuf> {
+    let start = Instant::now();
+    let filename = cfg.sanitize(filename)?;
+    let src = backup_source(cfg, &filename)?;
+    let bak = backup_dest(cfg, &cfg.suffixed(&filename))?;
+    let _lock = lock_file(cfg, &filename)?;
+
+    ensure_distinct(&src, &bak)?;
+    if !bak.is_file() {
+        return Err(BackupError::BackupMissing);
+    }
+    if same_contents(&src, &bak)? {
+        return Ok(bak);
+    }
+    let tmp = backup_dest(cfg, &format!("{}.tmp", cfg.suffixed(&filename)))?;
+    let bytes = copy_atomic(cfg, &src, &tmp, &bak)?;
+    record_in_manifest(cfg, &filename, &bak)?;
+
+    log_event(
+        cfg,
+        "WARN",
+        "repair",
+        &filename,
+        &format!(
+            "Backup repaired for {}: it no longer matched the source {}",
+            filename,
+            timing(bytes, start)
+        ),
+    )?;
+    Ok(bak)
+}
+
+/// Name of the checksum inventory kept next to the backups.
+pub const MANIFEST_NAME: &str = "backups.manifest";
+
+/// One backup recorded in [`MANIFEST_NAME`], stored as a JSON object per line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ManifestEntry {
+    pub filename: String,
+    /// Backup path relative to `base_dir`.
+    pub backup: PathBuf,
+    /// Length of the backup file, which is the compressed size for `.bak.gz`.
+    pub size: u64,
+    /// Hex SHA-256 of the backup file as written.
+    pub sha256: String,
+    /// RFC 3339 time the backup was created.
+    pub timestamp: String,
+    /// RFC 3339 time after which [`expire_backups`] deletes the backup; `None` keeps it.
+    pub expires: Option<String>,
+}
+
+/// What [`verify_manifest`] found for one entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum VerifyStatus {
+    Ok,
+    /// The backup's current digest differs from the recorded one.
+    Mismatch {
+        actual: String,
+    },
+    Missing,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VerifyResult {
+    pub entry: ManifestEntry,
+    pub status: VerifyStatus,
+}
+
+impl ManifestEntry {
+    fn to_line(&self) -> String {
+        serde_json::json!({
+            "filename": self.filename,
+            "backup": self.backup.to_string_lossy(),
+            "size": self.size,
+            "sha256": self.sha256,
+            "timestamp": self.timestamp,
+            "expires": self.expires,
+        })
+        .to_string()
+    }
+
+    fn from_line(line: &str) -> Option<Self> {
+        let v: serde_json::Value = serde_json::from_str(line).ok()?;
+        let text = |key: &str| v.get(key)?.as_str().map(str::to_string);
+        Some(Self {
+            filename: text("filename")?,
+            backup: PathBuf::from(text("backup")?),
+            size: v.get("size")?.as_u64()?,
+            sha256: text("sha256")?,
+            timestamp: text("timestamp")?,
+            // Absent in manifests written before backups could expire.
+            expires: text("expires"),
+        })
+    }
+}
+
+fn read_manifest(path: &Path) -> Result<Vec<ManifestEntry>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).path_context("read", path),
+    };
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            ManifestEntry::from_line(line).ok_or_else(|| BackupError::Io {
+                context: format!("parse {} line {}", path.display(), i + 1),
+                source: io::Error::new(io::ErrorKind::InvalidData, "malformed manifest entry"),
+            })
+        })
+        .collect()
+}
+
+/// Record `bak` in the manifest, replacing any earlier entry for the same backup.
+/// An expiry already recorded for the backup is kept.
+fn record_in_manifest(cfg: &BackupConfig, filename: &str, bak: &Path) -> Result<()> {
+    record_in_manifest_expiring(cfg, filename, bak, None)
+}
+
+/// Like [`record_in_manifest`], setting the backup to expire at `expires` when it is given.
+fn record_in_manifest_expiring(
+    cfg: &BackupConfig,
+    filename: &str,
+    bak: &Path,
+    expires: Option<DateTime<Utc>>,
+) -> Result<()> {
+    let name = cfg.backup_name(MANIFEST_NAME);
+    let path = cfg.resolve(&name);
+    let _lock = lock_file(cfg, &name)?;
+
+    let backup = bak.strip_prefix(&cfg.base_dir).unwrap_or(bak).to_path_buf();
+    let mut entries = read_manifest(&path)?;
+    let earlier = entries
+        .iter()
+        .rfind(|e| e.backup == backup)
+        .and_then(|e| e.expires.clone());
+    entries.retain(|e| e.backup != backup);
+    let size = fs::metadata(bak).path_context("metadata", bak)?.len();
+    entries.push(ManifestEntry {
+        filename: filename.to_string(),
+        backup,
+        size,
+        sha256: sha256_file(bak)?,
+        timestamp: cfg.now().to_rfc3339(),
+        expires: expires.map(|t| t.to_rfc3339()).or(earlier),
+    });
+    write_manifest(cfg, &name, &path, &entries)
+}
+
+/// Replace the manifest at `path` with `entries`. The caller holds the manifest lock.
+/// The whole manifest is rewritten through a temp file, so a crash leaves the old one intact.
+fn write_manifest(
+    cfg: &BackupConfig,
+    name: &str,
+    path: &Path,
+    entries: &[ManifestEntry],
+) -> Result<()> {
+    let tmp = cfg.resolve(&format!("{}.tmp", name));
+    // Left over from a crash; nobody else writes it while we hold the lock.
+    let _ = fs::remove_file(&tmp);
+    let mut out = TempFile::create_new(&tmp)?;
+    for entry in entries {
+        writeln!(out.file(), "{}", entry.to_line()).context("write manifest failed")?;
+    }
+    out.file().sync_all().context("sync manifest failed")?;
+    out.persist(path)?;
+    sync_dir(path.parent().unwrap_or(&cfg.base_dir))
+}
+
+/// Drop the manifest entries of `backups`, which are absolute or relative to `base_dir`,
+/// once they have been deleted. The manifest is left alone if none of them are listed.
+fn forget_in_manifest(cfg: &BackupConfig, backups: &[PathBuf]) -> Result<()> {
+    if backups.is_empty() {
+        return Ok(());
+    }
+    let gone: Vec<&Path> = backups
+        .iter()
+        .map(|b| b.strip_prefix(&cfg.base_dir).unwrap_or(b))
+        .collect();
+    let name = cfg.backup_name(MANIFEST_NAME);
+    let path = cfg.resolve(&name);
+    let _lock = lock_file(cfg, &name)?;
+    let mut entries = read_manifest(&path)?;
+    let before = entries.len();
+    entries.retain(|e| !gone.contains(&e.backup.as_path()));
+    if entries.len() == before {
+        return Ok(());
+    }
+    write_manifest(cfg, &name, &path, &entries)
+}
+
+/// Re-hash every backup listed in the manifest and report which still match.
+pub fn verify_manifest() -> Result<Vec<VerifyResult>> {
+    verify_manifest_in(&BackupConfig::from_env()?)
+}
+
+/// Like [`verify_manifest`], but reads the manifest under `cfg.base_dir`.
+pub fn verify_manifest_in(cfg: &BackupConfig) -> Result<Vec<VerifyResult>> {
+    let entries = read_manifest(&cfg.resolve(&cfg.backup_name(MANIFEST_NAME)))?;
+    entries
+        .into_iter()
+        .map(|entry| {
+            within_base(cfg, &entry.backup)?;
+            let bak = cfg.base_dir.join(&entry.backup);
+            let status = if !bak.is_file() {
+                VerifyStatus::Missing
+            } else {
+                let actual = sha256_file(&bak)?;
+                if actual == entry.sha256 {
+                    VerifyStatus::Ok
+                } else {
+                    VerifyStatus::Mismatch { actual }
+                }
+            };
+            Ok(VerifyResult { entry, status })
+        })
+        .collect()
+}
+
+/// What [`verify_all`] found for one backup.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BackupCheck {
+    /// The backup has the same contents as its source.
+    Match,
+    Mismatch,
+    /// No source file was found for the backup.
+    SourceMissing,
+    /// The backup or its source could not be read; the message says why.
+    ReadError(String),
+}
+
+/// Compare every `*.bak` under the base dir (or `backup_dir`) with the file it is a backup
+/// of, without changing anything. Versioned and pre-restore backups are compared with
+/// their file too. Symlinks, the logfile, and hidden entries unless `include_hidden` is
+/// set are skipped. Results are sorted by backup path.
+pub fn verify_all() -> Result<Vec<(PathBuf, BackupCheck)>> {
+    verify_all_in(&BackupConfig::from_env()?)
+}
+
+/// Like [`verify_all`], for the backups of `cfg`.
+pub fn verify_all_in(cfg: &BackupConfig) -> Result<Vec<(PathBuf, BackupCheck)>> {
+    let mut results = Vec::new();
+    for found in find_backups(cfg, true)? {
+        let check = if !found.source.is_file() {
+            BackupCheck::SourceMissing
+        } else {
+            match same_contents(&found.source, &found.backup) {
+                Ok(true) => BackupCheck::Match,
+                Ok(false) => BackupCheck::Mismatch,
+                Err(e) => BackupCheck::ReadError(e.to_string()),
+            }
+        };
+        results.push((found.backup, check));
+    }
+    Ok(results)
+}
+
+/// Total length in bytes of every `*.bak` under the base dir (or `backup_dir`), counted
+/// like [`verify_all`] finds them. Only the top directory is scanned unless `recursive`.
+pub fn total_backup_size(recursive: bool) -> Result<u64> {
+    total_backup_size_in(&BackupConfig::from_env()?, recursive)
+}
+
+/// Like [`total_backup_size`], for the backups of `cfg`.
+pub fn total_backup_size_in(cfg: &BackupConfig, recursive: bool) -> Result<u64> {
+    let mut total = 0u64;
+    for found in find_backups(cfg, recursive)? {
+        total += found.len;
+    }
+    Ok(total)
+}
+
+/// A backup file seen by [`find_backups`].
+struct FoundBackup {
+    backup: PathBuf,
+    /// The file it is a backup of, which may no longer exist.
+    source: PathBuf,
+    len: u64,
+}
+
+/// Every regular `*.bak` file under the backup root, sorted by path. Symlinks, the logfile,
+/// and hidden entries unless `include_hidden` is set are skipped.
+fn find_backups(cfg: &BackupConfig, recursive: bool) -> Result<Vec<FoundBackup>> {
+    let root = match &cfg.backup_dir {
+        Some(dir) => {
+            within_base(cfg, dir)?;
+            cfg.base_dir.join(dir)
+        }
+        None => cfg.base_dir.clone(),
+    };
+    let log = cfg.log_path();
+
+    let mut found = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(rel) = pending.pop() {
+        let dir = root.join(&rel);
+        let entries = match fs::read_dir(&dir) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound && dir != cfg.base_dir => continue,
+            entries => entries.path_context("read directory", &dir)?,
+        };
+        for entry in entries {
+            let entry = entry.context("read directory entry failed")?;
+            let name = entry.file_name();
+            let Some(name) = name.to_str() else { continue };
+            if !cfg.include_hidden && name.starts_with('.') {
+                continue;
+            }
+            let path = entry.path();
+            let meta = entry.metadata().path_context("metadata", &path)?;
+            if meta.is_dir() {
+                if recursive {
+                    pending.push(rel.join(name));
+                }
+                continue;
+            }
+            let Some(source_name) = backed_up_name(cfg, name) else {
+                continue;
+            };
+            if !meta.is_file() || same_path(&path, &log) {
+                continue;
+            }
+            found.push(FoundBackup {
+                source: cfg.base_dir.join(&rel).join(source_name),
+                backup: path,
+                len: meta.len(),
+            });
+        }
+    }
+    found.sort_by(|a, b| a.backup.cmp(&b.backup));
+    Ok(found)
+}
+
+/// The filename a backup named `name` was taken of, or `None` if `name` is not a backup:
+/// `notes.txt` for `notes.txt.bak`, `notes.txt.<version>.bak`, and
+/// `notes.txt.pre-restore.bak`.
+fn backed_up_name<'a>(cfg: &BackupConfig, name: &'a str) -> Option<&'a str> {
+    let stem = name.strip_suffix(cfg.backup_suffix())?;
+    if let Some(original) = stem.strip_suffix(".pre-restore") {
+        return Some(original);
+    }
+    match stem.rsplit_once('.') {
+        Some((original, version)) if parse_version(version).is_some() => Some(original),
+        _ if stem.is_empty() => None,
+        _ => Some(stem),
+    }
+}
+
+/// How `<filename>` compares to `<filename>.bak`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DiffSummary {
+    pub identical: bool,
+    pub src_len: u64,
+    pub bak_len: u64,
+}
+
+/// True when `filename` and `<filename>.bak` differ byte-for-byte.
+/// A missing backup is [`BackupError::BackupMissing`], not a difference.
+pub fn diff_backup(filename: &str) -> Result<bool> {
+    diff_backup_in(&BackupConfig::from_env()?, filename)
+}
+
+/// Like [`diff_backup`], but resolves `filename` against `cfg.base_dir`.
+pub fn diff_backup_in(cfg: &BackupConfig, filename: &str) -> Result<bool> {
+    diff_summary_in(cfg, filename).map(|d| !d.identical)
+}
+
+/// Like [`diff_backup`], but also reports both file sizes.
+pub fn diff_summary(filename: &str) -> Result<DiffSummary> {
+    diff_summary_in(&BackupConfig::from_env()?, filename)
+}
+
+/// Like [`diff_summary`], but resolves `filename` against `cfg.base_dir`.
+pub fn diff_summary_in(cfg: &BackupConfig, filename: &str) -> Result<DiffSummary> {
+    let filename = cfg.sanitize(filename)?;
+    let src = source_path(cfg, &filename)?;
+    let bak = backup_path(cfg, &cfg.backup_name(&cfg.suffixed(&filename)))?;
+
+    let src_len = fs::metadata(&src).path_context("metadata", &src)?.len();
+    let bak_len = fs::metadata(&bak).path_context("metadata", &bak)?.len();
+    let identical = src_len == bak_len && same_contents(&src, &bak)?;
+    Ok(DiffSummary {
+        identical,
+        src_len,
+        bak_len,
+    })
+}
+
+/// Compare two files chunk by chunk, stopping at the first difference.
+fn same_contents(a: &Path, b: &Path) -> Result<bool> {
+    let mut fa = File::open(a).path_context("open", a)?;
+    let mut fb = File::open(b).path_context("open", b)?;
+    let mut buf_a = [0u8; 8192];
+    let mut buf_b = [0u8; 8192];
+    loop {
+        let na = read_full(&mut fa, &mut buf_a).path_context("read", a)?;
+        let nb = read_full(&mut fb, &mut buf_b).path_context("read", b)?;
+        if buf_a[..na] != buf_b[..nb] {
+            return Ok(false);
+        }
+        if na == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+/// Read until `buf` is full or EOF; returns the number of bytes read.
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Timestamp used in versioned backup names; contains only filename-safe characters.
+const VERSION_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// Create `<filename>.<UTC timestamp>.bak`, so repeated backups never collide.
+/// A `-<n>` counter is appended to the timestamp if that name is already taken.
+pub fn backup_file_versioned(filename: &str) -> Result<PathBuf> {
+    backup_file_versioned_in(&BackupConfig::from_env()?, filename)
+}
+
+/// Like [`backup_file_versioned`], but resolves `filename` against `cfg.base_dir`.
+pub fn backup_file_versioned_in(cfg: &BackupConfig, filename: &str) -> Result<PathBuf> {
+    cfg.counted(Tally::Backup, || backup_versioned(cfg, filename))
+}
+
+fn backup_versioned(cfg: &BackupConfig, filename: &str) -> Result<PathBuf> {
+    backup_versioned_expiring(cfg, filename, None)
+}
+
+fn backup_versioned_expiring(
+    cfg: &BackupConfig,
+    filename: &str,
+    expires: Option<DateTime<Utc>>,
+) -> Result<PathBuf> {
+    let filename = cfg.sanitize(filename)?;
+    let _lock = lock_file(cfg, &filename)?;
+    write_versioned(cfg, &filename, expires)
+}
+
+/// [`backup_versioned_expiring`] for a sanitized `filename` whose lock the caller holds.
+fn write_versioned(
+    cfg: &BackupConfig,
+    filename: &str,
+    expires: Option<DateTime<Utc>>,
+) -> Result<PathBuf> {
+    let src = backup_source(cfg, filename)?;
+    let stamp = cfg.now().format(VERSION_FORMAT).to_string();
+
+    let mut counter = 0u32;
+    let bak = loop {
+        let version = if counter == 0 {
+            stamp.clone()
+        } else {
+            format!("{}-{}", stamp, counter)
+        };
+        let candidate = backup_dest(
+            cfg,
+            &format!("{}.{}{}", filename, version, cfg.backup_suffix()),
+        )?;
+        if !candidate.exists() {
+            break candidate;
+        }
+        counter += 1;
+    };
+
+    copy_to_new(cfg, &src, &bak)?;
+    record_in_manifest_expiring(cfg, filename, &bak, expires)?;
+
+    let until = match expires {
+        Some(t) => format!(", expiring {}", t.to_rfc3339()),
+        None => String::new(),
+    };
+    log_event(
+        cfg,
+        "INFO",
+        "backup_versioned",
+        filename,
+        &format!(
+            "Versioned backup {} created for {}{}",
+            bak.display(),
+            filename,
+            until
+        ),
+    )?;
+    Ok(bak)
+}
+
+/// Create a versioned backup as [`backup_file_versioned`] does and record in the manifest
+/// that it expires `ttl` from now. [`expire_backups`] deletes it once that time has passed.
+pub fn backup_file_expiring(filename: &str, ttl: Duration) -> Result<PathBuf> {
+    backup_file_expiring_in(&BackupConfig::from_env()?, filename, ttl)
+}
+
+/// Like [`backup_file_expiring`], but resolves `filename` against `cfg.base_dir`.
+pub fn backup_file_expiring_in(
+    cfg: &BackupConfig,
+    filename: &str,
+    ttl: Duration,
+) -> Result<PathBuf> {
+    let expires = chrono::Duration::from_std(ttl)
+        .ok()
+        .and_then(|ttl| cfg.now().checked_add_signed(ttl))
+        .ok_or_else(|| BackupError::InvalidArgument(format!("ttl {:?} is too long", ttl)))?;
+    cfg.counted(Tally::Backup, || {
+        backup_versioned_expiring(cfg, filename, Some(expires))
+    })
+}
+
+/// Securely delete every backup whose expiry, as recorded in the manifest by
+/// [`backup_file_expiring`], is at or before now, and drop it from the manifest.
+/// File modification times are never consulted. Returns the paths deleted; entries whose
+/// backup is already gone are dropped without being reported.
+pub fn expire_backups() -> Result<Vec<PathBuf>> {
+    expire_backups_in(&BackupConfig::from_env()?)
+}
+
+/// Like [`expire_backups`], using the manifest under `cfg.base_dir`.
+pub fn expire_backups_in(cfg: &BackupConfig) -> Result<Vec<PathBuf>> {
+    cfg.counted(Tally::Delete, || expire(cfg))
+}
+
+fn expire(cfg: &BackupConfig) -> Result<Vec<PathBuf>> {
+    let start = Instant::now();
+    let name = cfg.backup_name(MANIFEST_NAME);
+    let path = cfg.resolve(&name);
+    let now = cfg.now();
+
+    let mut expired = Vec::new();
+    for entry in read_manifest(&path)? {
+        let Some(expires) = &entry.expires else {
+            continue;
+        };
+        let expires = DateTime::parse_from_rfc3339(expires).map_err(|e| BackupError::Io {
+            context: format!("parse expiry of {}", entry.backup.display()),
+            source: io::Error::new(io::ErrorKind::InvalidData, e),
+        })?;
+        if expires <= now {
+            within_base(cfg, &entry.backup)?;
+            expired.push(entry);
+        }
+    }
+
+    // Each backup is wiped under its file's lock, which is never held together with the
+    // manifest lock here: backups take them the other way round.
+    let mut deleted = Vec::new();
+    let mut dropped = Vec::new();
+    let mut bytes = 0u64;
+    let wiped = expired.iter().try_for_each(|entry| {
+        let bak = cfg.base_dir.join(&entry.backup);
+        let _lock = lock_file(cfg, &entry.filename)?;
+        match fs::symlink_metadata(&bak) {
+            Ok(meta) if meta.is_file() => {}
+            Ok(_) => return Err(BackupError::NotRegularFile),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                dropped.push(entry.backup.clone());
+                return Ok(());
+            }
+            Err(e) => return Err(e).path_context("metadata", &bak),
+        }
+        bytes += overwrite_file(&bak, OverwritePattern::Zeros, cfg.wipe_buffer_size)?.len;
+        fs::remove_file(&bak).path_context("remove", &bak)?;
+        log_event(
+            cfg,
+            "INFO",
+            "expire",
+            &entry.filename,
+            &format!("Expired backup {} securely deleted", bak.display()),
+        )?;
+        dropped.push(entry.backup.clone());
+        deleted.push(bak);
+        Ok(())
+    });
+
+    // Drop whatever was deleted even if a later entry failed, so the manifest never lists
+    // a backup that is gone.
+    forget_in_manifest(cfg, &dropped)?;
+    wiped?;
+
+    log_event(
+        cfg,
+        "INFO",
+        "expire",
+        "",
+        &format!(
+            "Expired backups deleted: {} files {}",
+            deleted.len(),
+            timing(bytes, start)
+        ),
+    )?;
+    Ok(deleted)
+}
+
+/// Roll `filename` over: copy it to a versioned backup as [`backup_file_versioned`] does,
+/// then truncate it to empty so the application writing it can carry on appending. The
+/// file is truncated in place, keeping its permissions and open handles, and only once the
+/// backup is on disk. Both steps happen under the file's lock. Returns the backup path.
+pub fn rotate_file(filename: &str) -> Result<PathBuf> {
+    rotate_file_in(&BackupConfig::from_env()?, filename)
+}
+
+/// Like [`rotate_file`], but resolves `filename` against `cfg.base_dir`.
+pub fn rotate_file_in(cfg: &BackupConfig, filename: &str) -> Result<PathBuf> {
+    cfg.counted(Tally::Backup, || rotate(cfg, filename))
+}
+
+fn rotate(cfg: &BackupConfig, filename: &str) -> Result<PathBuf> {
+    let start = Instant::now();
+    let filename = cfg.sanitize(filename)?;
+    let src = backup_source(cfg, &filename)?;
+    let _lock = lock_file(cfg, &filename)?;
+
+    let bak = write_versioned(cfg, &filename, None)?;
+    File::open(&bak)
+        .and_then(|f| f.sync_all())
+        .path_context("sync", &bak)?;
+    sync_dir(bak.parent().unwrap_or(&cfg.base_dir))?;
+    let bytes = fs::metadata(&bak).path_context("metadata", &bak)?.len();
+
+    let file = OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .open(&src)
+        .path_context("truncate", &src)?;
+    file.sync_all().path_context("sync", &src)?;
+
+    log_event(
+        cfg,
+        "INFO",
+        "rotate",
+        &filename,
+        &format!(
+            "Rotated {} to {} {}",
+            filename,
+            bak.display(),
+            timing(bytes, start)
+        ),
+    )?;
+    Ok(bak)
+}
+
+/// Like [`backup_file_versioned`], but returns `Ok(None)` without copying anything when the
+/// source's SHA-256 matches the newest backup from [`list_backups`]. The backup's digest is
+/// taken from the manifest when it has one, so the backup itself is not re-read.
+pub fn backup_if_changed(filename: &str) -> Result<Option<PathBuf>> {
+    backup_if_changed_in(&BackupConfig::from_env()?, filename)
+}
+
+/// Like [`backup_if_changed`], but resolves `filename` against `cfg.base_dir`.
+pub fn backup_if_changed_in(cfg: &BackupConfig, filename: &str) -> Result<Option<PathBuf>> {
+    cfg.counted(Tally::Backup, || backup_changed(cfg, filename))
+}
+
+fn backup_changed(cfg: &BackupConfig, filename: &str) -> Result<Option<PathBuf>> {
+    let filename = cfg.sanitize(filename)?;
+    let src = backup_source(cfg, &filename)?;
+    if let Some(latest) = list_backups_in(cfg, &filename)?.pop() {
+        let relative = latest.strip_prefix(&cfg.base_dir).unwrap_or(&latest);
+        let recorded = read_manifest(&cfg.resolve(&cfg.backup_name(MANIFEST_NAME)))?
+            .into_iter()
+            .rev()
+            .find(|e| e.backup == relative)
+            .map(|e| e.sha256);
+        let latest_hex = match recorded {
+            Some(hex) => hex,
+            None => sha256_file(&latest)?,
+        };
+        if sha256_file(&src)? == latest_hex {
+            log_event(
+                cfg,
+                "INFO",
+                "backup_versioned",
+                &filename,
+                &format!(
+                    "Backup skipped for {}: unchanged since {}",
+                    filename,
+                    latest.display()
+                ),
+            )?;
+            return Ok(None);
+        }
+    }
+    backup_versioned(cfg, &filename).map(Some)
+}
+
+/// Create `<filename>.bak.gz`, gzip-compressing the source as it is streamed.
+/// Fails with [`BackupError::BackupExists`] if the compressed backup already exists.
+/// [`restore_file`] falls back to this backup when no plain `.bak` is present.
+pub fn backup_file_compressed(filename: &str) -> Result<PathBuf> {
+    backup_file_compressed_in(&BackupConfig::from_env()?, filename)
+}
+
+/// Like [`backup_file_compressed`], but resolves `filename` against `cfg.base_dir`.
+pub fn backup_file_compressed_in(cfg: &BackupConfig, filename: &str) -> Result<PathBuf> {
+    cfg.counted(Tally::Backup, || backup_compressed(cfg, filename))
+}
+
+fn backup_compressed(cfg: &BackupConfig, filename: &str) -> Result<PathBuf> {
+    let filename = cfg.sanitize(filename)?;
+    let src = backup_source(cfg, &filename)?;
+    // Only the user-supplied base name is sanitized; the suffix is ours.
+    let bak = backup_dest(cfg, &format!("{}.gz", cfg.suffixed(&filename)))?;
+    let _lock = lock_file(cfg, &filename)?;
+    if bak.exists() {
+        return Err(BackupError::BackupExists);
+    }
+
+    copy_to_new_by(cfg, &src, &bak, |reader, writer, _| {
+        let mut encoder = GzEncoder::new(writer, Compression::default());
+        let bytes = io::copy(reader, &mut encoder)?;
+        encoder.finish()?;
+        Ok(bytes)
+    })?;
+    record_in_manifest(cfg, &filename, &bak)?;
+
+    log_event(
+        cfg,
+        "INFO",
+        "backup_compressed",
+        &filename,
+        &format!("Compressed backup created for {}", filename),
+    )?;
+    Ok(bak)
+}
+
+/// Parse the `<timestamp>[-<n>]` part of a versioned backup name.
+fn parse_version(version: &str) -> Option<(DateTime<Utc>, u32)> {
+    let (stamp, counter) = match version.split_once('-') {
+        Some((stamp, n)) => (stamp, n.parse().ok()?),
+        None => (version, 0),
+    };
+    let at = NaiveDateTime::parse_from_str(stamp, VERSION_FORMAT).ok()?;
+    Some((at.and_utc(), counter))
+}
+
+/// List `<filename>.bak` and every `<filename>.<version>.bak`, oldest first.
+/// Versioned backups are ordered by the timestamp in their name, the plain `.bak` by its mtime.
+pub fn list_backups(filename: &str) -> Result<Vec<PathBuf>> {
+    list_backups_in(&BackupConfig::from_env()?, filename)
+}
+
+/// Like [`list_backups`], but scans `cfg.base_dir`.
+pub fn list_backups_in(cfg: &BackupConfig, filename: &str) -> Result<Vec<PathBuf>> {
+    let filename = cfg.sanitize(filename)?;
+    within_base(cfg, Path::new(&filename))?;
+    let (parent, leaf) = filename.rsplit_once('/').unwrap_or(("", &filename));
+    let plain = cfg.suffixed(leaf);
+    let prefix = format!("{}.", leaf);
+
+    let mut found = Vec::new();
+    let dir = cfg.resolve(&cfg.backup_name(parent));
+    let entries = match fs::read_dir(&dir) {
+        // Nothing has been backed up into that directory yet.
+        Err(e) if e.kind() == io::ErrorKind::NotFound && dir != cfg.base_dir => {
+            return Ok(Vec::new())
+        }
+        entries => entries.path_context("read directory", &dir)?,
+    };
+    for entry in entries {
+        let entry = entry.context("read directory entry failed")?;
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else { continue };
+        if !entry.file_type().map(|t| t.is_file()).unwrap_or(false) {
+            continue;
+        }
+        let key = if name == plain {
+            let modified = entry
+                .metadata()
+                .and_then(|m| m.modified())
+                .with_context(|| format!("read mtime of {}", name))?;
+            (DateTime::<Utc>::from(modified), 0)
+        } else if let Some(version) = name
+            .strip_prefix(&prefix)
+            .and_then(|rest| rest.strip_suffix(cfg.backup_suffix()))
+        {
+            match parse_version(version) {
+                Some(key) => key,
+                None => continue,
+            }
+        } else {
+            continue;
+        };
+        found.push((key, entry.path()));
+    }
+
+    found.sort();
+    Ok(found.into_iter().map(|(_, path)| path).collect())
+}
+
+/// What is on disk for a `<filename>.bak`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BackupInfo {
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+/// Inspect `<filename>.bak`: `None` if there is no backup, its path, size, and mtime otherwise.
+pub fn backup_status(filename: &str) -> Result<Option<BackupInfo>> {
+    backup_status_in(&BackupConfig::from_env()?, filename)
+}
+
+/// Like [`backup_status`], but resolves `filename` against `cfg.base_dir`.
+pub fn backup_status_in(cfg: &BackupConfig, filename: &str) -> Result<Option<BackupInfo>> {
+    let filename = cfg.sanitize(filename)?;
+    let path = match backup_path(cfg, &cfg.backup_name(&cfg.suffixed(&filename))) {
+        Ok(path) => path,
+        Err(BackupError::BackupMissing) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let meta = fs::metadata(&path).path_context("metadata", &path)?;
+    let modified = meta.modified().path_context("read mtime of", &path)?;
+    Ok(Some(BackupInfo {
+        path,
+        size: meta.len(),
+        modified,
+    }))
+}
+
+/// Securely delete all but the newest `keep` versioned backups of `filename`.
+/// The plain `<filename>.bak` is left alone. Returns the paths that were removed.
+pub fn prune_backups(filename: &str, keep: usize) -> Result<Vec<PathBuf>> {
+    prune_backups_in(&BackupConfig::from_env()?, filename, keep)
+}
+
+/// Like [`prune_backups`], but scans `cfg.base_dir`.
+pub fn prune_backups_in(cfg: &BackupConfig, filename: &str, keep: usize) -> Result<Vec<PathBuf>> {
+    if keep == 0 {
+        return Err(BackupError::InvalidArgument(
+            "keep must be at least 1".to_string(),
+        ));
+    }
+    let filename = cfg.sanitize(filename)?;
+    let _lock = lock_file(cfg, &filename)?;
+    let plain = cfg.resolve(&cfg.backup_name(&cfg.suffixed(&filename)));
+    let mut versions = list_backups_in(cfg, &filename)?;
+    versions.retain(|p| *p != plain);
+    if versions.len() <= keep {
+        return Ok(Vec::new());
+    }
+
+    let remove = versions.len() - keep;
+    let mut removed = Vec::with_capacity(remove);
+    let pruned = versions.into_iter().take(remove).try_for_each(|path| {
+        overwrite_file(&path, OverwritePattern::default(), cfg.wipe_buffer_size)?;
+        fs::remove_file(&path).path_context("remove", &path)?;
+        let logged = log_event(
+            cfg,
+            "INFO",
+            "prune",
+            &filename,
+            &format!("Pruned backup {}", path.display()),
+        );
+        removed.push(path);
+        logged
+    });
+    // Forget the pruned backups even if a later one failed.
+    forget_in_manifest(cfg, &removed)?;
+    pruned?;
+    Ok(removed)
+}
+
+/// Restore from `<filename>.bak` to `<filename>` atomically by writing to a temp file.
+/// If only `<filename>.bak.gz` exists, it is decompressed on the way.
+pub fn restore_file(filename: &str) -> Result<PathBuf> {
+    restore_file_in(&BackupConfig::from_env()?, filename)
+}
+
+/// Like [`restore_file`], but resolves `filename` against `cfg.base_dir`.
+pub fn restore_file_in(cfg: &BackupConfig, filename: &str) -> Result<PathBuf> {
+    restore_file_reported_in(cfg, filename).map(|r| r.path)
+}
+
+/// Like [`restore_file`], but also reports the number of bytes copied.
+pub fn restore_file_reported(filename: &str) -> Result<BackupReport> {
+    restore_file_reported_in(&BackupConfig::from_env()?, filename)
+}
+
+/// Like [`restore_file_reported`], but resolves `filename` against `cfg.base_dir`.
+pub fn restore_file_reported_in(cfg: &BackupConfig, filename: &str) -> Result<BackupReport> {
+    restore_with(cfg, filename, RestoreOptions::default())
+}
+
+/// Knobs for [`restore_file_opts`]. The default discards the content being replaced.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RestoreOptions {
+    /// Before replacing an existing `<filename>`, copy it to `<filename>.pre-restore.bak`
+    /// so the restore can be undone. Fails with [`BackupError::BackupExists`] instead of
+    /// replacing an older snapshot.
+    pub snapshot: bool,
+    /// Create missing parent directories of `<filename>` inside the base dir, for restoring
+    /// `docs/notes.txt` after `docs/` was removed.
+    pub create_parents: bool,
+    /// Fail with [`BackupError::SourceNewerThanBackup`] instead of replacing a `<filename>`
+    /// modified more recently than its backup.
+    pub refuse_if_newer: bool,
+    /// Restore even when `refuse_if_newer` would refuse.
+    pub force: bool,
+    /// What to do when `<filename>` already exists.
+    pub on_collision: Collision,
+}
+
+/// How a restore treats an existing destination, set in [`RestoreOptions::on_collision`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Collision {
+    /// Fail with [`BackupError::DestinationExists`].
+    Fail,
+    /// Replace it atomically, as [`restore_file`] always has.
+    #[default]
+    Overwrite,
+    /// Move it aside to `<filename>.replaced-<timestamp>[-<n>]` first, so nothing is lost.
+    RenameExisting,
+}
+
+/// Like [`restore_file`], with options.
+pub fn restore_file_opts(filename: &str, opts: RestoreOptions) -> Result<PathBuf> {
+    restore_file_opts_in(&BackupConfig::from_env()?, filename, opts)
+}
+
+/// Like [`restore_file_opts`], but resolves `filename` against `cfg.base_dir`.
+pub fn restore_file_opts_in(
+    cfg: &BackupConfig,
+    filename: &str,
+    opts: RestoreOptions,
+) -> Result<PathBuf> {
+    restore_with(cfg, filename, opts).map(|r| r.path)
+}
+
+fn restore_with(cfg: &BackupConfig, filename: &str, opts: RestoreOptions) -> Result<BackupReport> {
+    cfg.counted(Tally::Restore, || make_restore(cfg, filename, opts))
+}
+
+/// [`restore_file`] for a target from [`prepare`].
+pub fn restore(target: &ValidatedTarget) -> Result<PathBuf> {
+    restore_in(&BackupConfig::from_env()?, target)
+}
+
+/// Like [`restore`], for a target prepared with [`prepare_in`] under the same `cfg`.
+pub fn restore_in(cfg: &BackupConfig, target: &ValidatedTarget) -> Result<PathBuf> {
+    cfg.counted(Tally::Restore, || {
+        target.check(cfg)?;
+        restore_target(cfg, target, RestoreOptions::default()).map(|r| r.path)
+    })
+}
+
+fn make_restore(cfg: &BackupConfig, filename: &str, opts: RestoreOptions) -> Result<BackupReport> {
+    restore_target(cfg, &prepare_in(cfg, filename)?, opts)
+}
+
+fn restore_target(
+    cfg: &BackupConfig,
+    target: &ValidatedTarget,
+    opts: RestoreOptions,
+) -> Result<BackupReport> {
+    let start = Instant::now();
+    let filename = target.filename();
+    let bak = restore_source_path(target.backup_path());
+    let (report, snapshot) = restore_resolved(cfg, &bak, filename, target.path(), opts)?;
+
+    // [`undo_last`] only rolls back to the snapshot recorded here.
+    let snapshot = match snapshot {
+        Some(snap) => format!(" snapshot {} sha256={}", snap.name, snap.sha256),
+        None => String::new(),
+    };
+    // `restore_from` has synced the file and its directory, so this is only logged
+    // once the restore is durable.
+    log_event(
+        cfg,
+        "INFO",
+        "restore",
+        filename,
+        &format!(
+            "Restore completed for {} {}{}",
+            filename,
+            timing(report.bytes, start),
+            snapshot
+        ),
+    )?;
+    Ok(report)
+}
+
+/// Name of the backup [`restore_file`] reads for `filename`: the plain `.bak`,
+/// or `.bak.gz` when only a compressed backup exists.
+fn restore_source(cfg: &BackupConfig, filename: &str) -> String {
+    let plain = cfg.backup_name(&cfg.suffixed(filename));
+    let compressed = format!("{}.gz", plain);
+    if !cfg.resolve(&plain).exists() && cfg.resolve(&compressed).is_file() {
+        compressed
+    } else {
+        plain
+    }
+}
+
+/// Like [`restore_source`], given the path of the plain backup.
+fn restore_source_path(plain: &Path) -> PathBuf {
+    let mut compressed = plain.as_os_str().to_os_string();
+    compressed.push(".gz");
+    let compressed = PathBuf::from(compressed);
+    if !plain.exists() && compressed.is_file() {
+        compressed
+    } else {
+        plain.to_path_buf()
+    }
+}
+
+/// Validate that `bak_name` names an existing backup file inside the base dir.
+fn backup_path(cfg: &BackupConfig, bak_name: &str) -> Result<PathBuf> {
+    within_base(cfg, Path::new(bak_name))?;
+    let src_bak = cfg.resolve(bak_name);
+    check_backup_file(cfg, &src_bak)?;
+    Ok(src_bak)
+}
+
+fn check_backup_file(cfg: &BackupConfig, src_bak: &Path) -> Result<()> {
+    check_symlink(cfg, src_bak)?;
+    if !src_bak.exists() || !src_bak.is_file() {
+        return Err(BackupError::BackupMissing);
+    }
+    Ok(())
+}
+
+/// Atomically copy the backup `bak_name` over `dest_name`, both relative to the base dir.
+fn restore_from(
+    cfg: &BackupConfig,
+    bak_name: &str,
+    dest_name: &str,
+    opts: RestoreOptions,
+) -> Result<BackupReport> {
+    restore_from_snapshotted(cfg, bak_name, dest_name, opts).map(|(report, _)| report)
+}
+
+/// A pre-restore snapshot that was just written.
+struct Snapshot {
+    /// Relative to the base dir.
+    name: String,
+    sha256: String,
+}
+
+/// Like [`restore_from`], also returning the snapshot `opts.snapshot` took, if any.
+fn restore_from_snapshotted(
+    cfg: &BackupConfig,
+    bak_name: &str,
+    dest_name: &str,
+    opts: RestoreOptions,
+) -> Result<(BackupReport, Option<Snapshot>)> {
+    within_base(cfg, Path::new(bak_name))?;
+    within_base(cfg, Path::new(dest_name))?;
+    restore_resolved(
+        cfg,
+        &cfg.resolve(bak_name),
+        dest_name,
+        &cfg.resolve(dest_name),
+        opts,
+    )
+}
+
+/// Like [`restore_from_snapshotted`], for paths already checked to be inside the base dir.
+fn restore_resolved(
+    cfg: &BackupConfig,
+    src_bak: &Path,
+    dest_name: &str,
+    dest: &Path,
+    opts: RestoreOptions,
+) -> Result<(BackupReport, Option<Snapshot>)> {
+    check_backup_file(cfg, src_bak)?;
+    check_not_log_file(cfg, dest)?;
+    if opts.create_parents {
+        if let Some(dir) = dest.parent() {
+            fs::create_dir_all(dir).path_context("create", dir)?;
+        }
+        // Check again now that the directories exist and symlinks among them resolve.
+        within_base(cfg, dest)?;
+    }
+    let _lock = lock_file(cfg, dest_name)?;
+
+    let tmp = temp_path(dest);
+    ensure_distinct(src_bak, dest)?;
+    if opts.refuse_if_newer
+        && !opts.force
+        && dest.exists()
+        && modified_at(dest)? > modified_at(src_bak)?
+    {
+        return Err(BackupError::SourceNewerThanBackup);
+    }
+    if opts.on_collision == Collision::Fail && dest.exists() {
+        return Err(BackupError::DestinationExists);
+    }
+    let snapshot = if opts.snapshot && dest.exists() {
+        Some(snapshot_before_restore(cfg, dest_name, dest)?)
+    } else {
+        None
+    };
+    let moved_aside = if opts.on_collision == Collision::RenameExisting && dest.exists() {
+        Some(move_aside(cfg, dest_name, dest)?)
+    } else {
+        None
+    };
+
+    let copied = if src_bak.extension().is_some_and(|ext| ext == "gz") {
+        copy_atomic_by(cfg, src_bak, &tmp, dest, |reader, writer, _| {
+            io::copy(&mut GzDecoder::new(reader), writer)
+        })
+    } else {
+        copy_atomic(cfg, src_bak, &tmp, dest)
+    };
+    if let (Err(_), Some(aside)) = (&copied, &moved_aside) {
+        // Put the original back rather than leave the name empty.
+        let _ = fs::rename(aside, dest);
+    }
+    let report = BackupReport {
+        path: dest.to_path_buf(),
+        bytes: copied?,
+    };
+    Ok((report, snapshot))
+}
+
+/// Rename the existing `dest` to `<dest_name>.replaced-<timestamp>[-<n>]` and return the
+/// new path.
+fn move_aside(cfg: &BackupConfig, dest_name: &str, dest: &Path) -> Result<PathBuf> {
+    let stamp = cfg.now().format(VERSION_FORMAT).to_string();
+    let mut counter = 0u32;
+    let aside = loop {
+        let name = if counter == 0 {
+            format!("{}.replaced-{}", dest_name, stamp)
+        } else {
+            format!("{}.replaced-{}-{}", dest_name, stamp, counter)
+        };
+        within_base(cfg, Path::new(&name))?;
+        let candidate = cfg.resolve(&name);
+        if !candidate.exists() {
+            break candidate;
+        }
+        counter += 1;
+    };
+    fs::rename(dest, &aside)
+        .with_context(|| format!("rename {} to {}", dest.display(), aside.display()))?;
+
+    log_event(
+        cfg,
+        "INFO",
+        "restore",
+        dest_name,
+        &format!("Moved existing {} aside to {}", dest_name, aside.display()),
+    )?;
+    Ok(aside)
+}
+
+fn modified_at(path: &Path) -> Result<SystemTime> {
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .path_context("read mtime of", path)
+}
+
+/// Save the current `dest` as `<dest_name>.pre-restore.bak`; never replaces an older snapshot.
+fn snapshot_before_restore(cfg: &BackupConfig, dest_name: &str, dest: &Path) -> Result<Snapshot> {
+    let snap_name = format!("{}.pre-restore{}", dest_name, cfg.backup_suffix());
+    within_base(cfg, Path::new(&snap_name))?;
+    let snap = cfg.resolve(&snap_name);
+    if snap.exists() {
+        return Err(BackupError::BackupExists);
+    }
+    let tmp = temp_path(&snap);
+    copy_atomic(cfg, dest, &tmp, &snap)?;
+
+    log_event(
+        cfg,
+        "INFO",
+        "restore",
+        dest_name,
+        &format!("Pre-restore snapshot {} saved for {}", snap_name, dest_name),
+    )?;
+    Ok(Snapshot {
+        sha256: sha256_file(&snap)?,
+        name: snap_name,
+    })
+}
+
+/// Like [`restore_file`], but checks the restored file's SHA-256 against the backup's and
+/// fails with [`BackupError::RestoreVerificationFailed`] if they differ.
+pub fn restore_file_verified(filename: &str) -> Result<PathBuf> {
+    restore_file_verified_in(&BackupConfig::from_env()?, filename)
+}
+
+/// Like [`restore_file_verified`], but resolves `filename` against `cfg.base_dir`.
+pub fn restore_file_verified_in(cfg: &BackupConfig, filename: &str) -> Result<PathBuf> {
+    restore_file_verified_opts_in(cfg, filename, RestoreOptions::default())
+}
+
+/// Like [`restore_file_verified`], with options. With `snapshot`, a failed verification
+/// also puts the snapshotted original back in place.
+pub fn restore_file_verified_opts(filename: &str, opts: RestoreOptions) -> Result<PathBuf> {
+    restore_file_verified_opts_in(&BackupConfig::from_env()?, filename, opts)
+}
+
+/// Like [`restore_file_verified_opts`], but resolves `filename` against `cfg.base_dir`.
+pub fn restore_file_verified_opts_in(
+    cfg: &BackupConfig,
+    filename: &str,
+    opts: RestoreOptions,
+) -> Result<PathBuf> {
+    cfg.counted(Tally::Restore, || restore_verified(cfg, filename, opts))
+}
+
+fn restore_verified(cfg: &BackupConfig, filename: &str, opts: RestoreOptions) -> Result<PathBuf> {
+    let filename = cfg.sanitize(filename)?;
+    let bak_name = restore_source(cfg, &filename);
+    let bak = backup_path(cfg, &bak_name)?;
+    let reader = File::open(&bak).path_context("open", &bak)?;
+    let expected = if bak_name.ends_with(".gz") {
+        sha256_reader(GzDecoder::new(reader))
+    } else {
+        sha256_reader(reader)
+    }
+    .path_context("hash", &bak)?;
+    let had_original = cfg.resolve(&filename).exists();
+
+    let report = restore_from(cfg, &bak_name, &filename, opts)?;
+    if sha256_file(&report.path)? != expected {
+        log_event(
+            cfg,
+            "ERROR",
+            "restore",
+            &filename,
+            &format!("Restore verification failed for {}", filename),
+        )
+        .ok();
+        if opts.snapshot && had_original {
+            let snap = cfg.resolve(&format!("{}.pre-restore{}", filename, cfg.backup_suffix()));
+            let tmp = temp_path(&report.path);
+            copy_atomic(cfg, &snap, &tmp, &report.path)?;
+            log_event(
+                cfg,
+                "WARN",
+                "restore",
+                &filename,
+                &format!("Original {} put back from its snapshot", filename),
+            )
+            .ok();
+        }
+        return Err(BackupError::RestoreVerificationFailed);
+    }
+
+    log_event(
+        cfg,
+        "INFO",
+        "restore",
+        &filename,
+        &format!("Restore verified for {} ({} bytes)", filename, report.bytes),
+    )?;
+    Ok(report.path)
+}
+
+/// Append the contents of `<filename>.bak` to the end of `<filename>`, creating it if needed,
+/// to recover lost tail entries without discarding newer ones. Only `.log` files may be
+/// restored this way; anything else fails with [`BackupError::InvalidArgument`].
+///
+/// Unlike [`restore_file`] this is not atomic: if it fails partway, part of the backup
+/// may already have been appended.
+pub fn restore_file_append(filename: &str) -> Result<PathBuf> {
+    restore_file_append_in(&BackupConfig::from_env()?, filename)
+}
+
+/// Like [`restore_file_append`], but resolves `filename` against `cfg.base_dir`.
+pub fn restore_file_append_in(cfg: &BackupConfig, filename: &str) -> Result<PathBuf> {
+    cfg.counted(Tally::Restore, || restore_append(cfg, filename))
+}
+
+fn restore_append(cfg: &BackupConfig, filename: &str) -> Result<PathBuf> {
+    let start = Instant::now();
+    let filename = cfg.sanitize(filename)?;
+    if Path::new(&filename).extension().and_then(|e| e.to_str()) != Some("log") {
+        return Err(BackupError::InvalidArgument(format!(
+            "append restore is only allowed for .log files, not {}",
+            filename
+        )));
+    }
+    let bak_name = restore_source(cfg, &filename);
+    let src_bak = backup_path(cfg, &bak_name)?;
+    within_base(cfg, Path::new(&filename))?;
+    let dest = cfg.resolve(&filename);
+    check_not_log_file(cfg, &dest)?;
+    check_symlink(cfg, &dest)?;
+    let _lock = lock_file(cfg, &filename)?;
+    ensure_distinct(&src_bak, &dest)?;
+
+    let mut reader = File::open(&src_bak).path_context("open backup", &src_bak)?;
+    let mut writer = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&dest)
+        .path_context("open for append", &dest)?;
+    let bytes = if bak_name.ends_with(".gz") {
+        io::copy(&mut GzDecoder::new(reader), &mut writer)
+    } else {
+        copy_buffered(&mut reader, &mut writer, cfg.copy_buffer_size)
+    }
+    .with_context(|| format!("append {} to {}", src_bak.display(), dest.display()))?;
+    writer.sync_all().path_context("sync", &dest)?;
+    // The append may have created `dest`.
+    sync_dir(dest.parent().unwrap_or(&cfg.base_dir))?;
+
+    log_event(
+        cfg,
+        "INFO",
+        "restore",
+        &filename,
+        &format!("Restore appended to {} {}", filename, timing(bytes, start)),
+    )?;
+    Ok(dest)
+}
+
+/// Replace `<filename>` with everything read from `src`, e.g. a download stream. The data
+/// lands in `<filename>.tmp` and is renamed into place only once `src` is exhausted, so a
+/// failed read leaves the current file untouched. Returns the restored path.
+pub fn restore_from_reader(filename: &str, src: &mut impl Read) -> Result<PathBuf> {
+    restore_from_reader_in(&BackupConfig::from_env()?, filename, src)
+}
+
+/// Like [`restore_from_reader`], but resolves `filename` against `cfg.base_dir`.
+pub fn restore_from_reader_in(
+    cfg: &BackupConfig,
+    filename: &str,
+    src: &mut impl Read,
+) -> Result<PathBuf> {
+    cfg.counted(Tally::Restore, || stream_restore(cfg, filename, src))
+}
+
+fn stream_restore(cfg: &BackupConfig, filename: &str, src: &mut impl Read) -> Result<PathBuf> {
+    let start = Instant::now();
+    let filename = cfg.sanitize(filename)?;
+    within_base(cfg, Path::new(&filename))?;
+    let dest = cfg.resolve(&filename);
+    check_not_log_file(cfg, &dest)?;
+    check_symlink(cfg, &dest)?;
+    let _lock = lock_file(cfg, &filename)?;
+
+    let tmp = temp_path(&dest);
+    let mut temp = TempFile::create_new(&tmp)?;
+    let writer = temp.file();
+    let bytes = io::copy(src, writer)
+        .with_context(|| format!("copy reader to {} failed", tmp.display()))?;
+    writer.sync_all().path_context("sync temp", &tmp)?;
+    temp.persist(&dest)?;
+    sync_dir(dest.parent().unwrap_or(&cfg.base_dir))?;
+
+    log_event(
+        cfg,
+        "INFO",
+        "restore",
+        &filename,
+        &format!(
+            "Restore from stream completed for {} {}",
+            filename,
+            timing(bytes, start)
+        ),
+    )?;
+    Ok(dest)
+}
+
+/// Restore `<backup_of>.bak` into `dest` instead of over `backup_of`, e.g. to diff it first.
+/// Fails with [`BackupError::DestinationExists`] if `dest` exists, unless `overwrite` is set.
+pub fn restore_file_as(backup_of: &str, dest: &str, overwrite: bool) -> Result<PathBuf> {
+    restore_file_as_in(&BackupConfig::from_env()?, backup_of, dest, overwrite)
+}
+
+/// Like [`restore_file_as`], but resolves both names against `cfg.base_dir`.
+pub fn restore_file_as_in(
+    cfg: &BackupConfig,
+    backup_of: &str,
+    dest: &str,
+    overwrite: bool,
+) -> Result<PathBuf> {
+    cfg.counted(Tally::Restore, || {
+        restore_as(cfg, backup_of, dest, overwrite)
+    })
+}
+
+fn restore_as(cfg: &BackupConfig, backup_of: &str, dest: &str, overwrite: bool) -> Result<PathBuf> {
+    let backup_of = cfg.sanitize(backup_of)?;
+    let dest = cfg.sanitize(dest)?;
+    if !overwrite && cfg.resolve(&dest).exists() {
+        return Err(BackupError::DestinationExists);
+    }
+    let report = restore_from(
+        cfg,
+        &cfg.backup_name(&cfg.suffixed(&backup_of)),
+        &dest,
+        RestoreOptions::default(),
+    )?;
+
+    log_event(
+        cfg,
+        "INFO",
+        "restore_as",
+        &backup_of,
+        &format!("Restore of {} written to {}", backup_of, dest),
+    )?;
+    Ok(report.path)
+}
+
+/// Restore `<filename>.<version>.bak`, as written by [`backup_file_versioned`], over `filename`.
+/// `version` is the `<timestamp>[-<n>]` part of the backup name.
+pub fn restore_version(filename: &str, version: &str) -> Result<PathBuf> {
+    restore_version_in(&BackupConfig::from_env()?, filename, version)
+}
+
+/// Like [`restore_version`], but resolves `filename` against `cfg.base_dir`.
+pub fn restore_version_in(cfg: &BackupConfig, filename: &str, version: &str) -> Result<PathBuf> {
+    cfg.counted(Tally::Restore, || {
+        restore_named_version(cfg, filename, version)
+    })
+}
+
+fn restore_named_version(cfg: &BackupConfig, filename: &str, version: &str) -> Result<PathBuf> {
+    let filename = cfg.sanitize(filename)?;
+    if parse_version(version).is_none() {
+        return Err(BackupError::InvalidArgument(format!(
+            "not a backup version: {}",
+            version
+        )));
+    }
+    let report = restore_from(
+        cfg,
+        &cfg.backup_name(&format!("{}.{}{}", filename, version, cfg.backup_suffix())),
+        &filename,
+        RestoreOptions::default(),
+    )?;
+
+    log_event(
+        cfg,
+        "INFO",
+        "restore_version",
+        &filename,
+        &format!("Restore of version {} completed for {}", version, filename),
+    )?;
+    Ok(report.path)
+}
+
+/// Restore the newest versioned backup of `filename`.
+/// Fails with [`BackupError::BackupMissing`] if there are no versioned backups.
+pub fn restore_latest(filename: &str) -> Result<PathBuf> {
+    restore_latest_in(&BackupConfig::from_env()?, filename)
+}
+
+/// Like [`restore_latest`], but resolves `filename` against `cfg.base_dir`.
+pub fn restore_latest_in(cfg: &BackupConfig, filename: &str) -> Result<PathBuf> {
+    let filename = cfg.sanitize(filename)?;
+    let leaf = filename.rsplit('/').next().unwrap_or(&filename);
+    let prefix = format!("{}.", leaf);
+    let latest = list_backups_in(cfg, &filename)?
+        .into_iter()
+        .rev()
+        .find_map(|path| {
+            let name = path.file_name()?.to_str()?;
+            let version = name
+                .strip_prefix(&prefix)?
+                .strip_suffix(cfg.backup_suffix())?;
+            Some(version.to_string())
+        })
+        .ok_or(BackupError::BackupMissing)?;
+    restore_version_in(cfg, &filename, &latest)
+}
+
+/// A backup taken by [`begin_edit`]. Dropping the guard without calling
+/// [`BackupGuard::commit`] restores the file from that backup.
+#[derive(Debug)]
+pub struct BackupGuard {
+    cfg: BackupConfig,
+    filename: String,
+    backup: PathBuf,
+    done: bool,
+}
+
+impl BackupGuard {
+    /// The backup this guard restores from.
+    pub fn backup_path(&self) -> &Path {
+        &self.backup
+    }
+
+    /// Keep the edited file and its backup.
+    pub fn commit(mut self) {
+        self.done = true;
+    }
+
+    /// Restore the file from the backup now, reporting any failure.
+    pub fn rollback(mut self) -> Result<()> {
+        self.done = true;
+        restore_file_in(&self.cfg, &self.filename).map(|_| ())
+    }
+}
+
+impl Drop for BackupGuard {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        if let Err(e) = restore_file_in(&self.cfg, &self.filename) {
+            log_event(
+                &self.cfg,
+                "ERROR",
+                "restore",
+                &self.filename,
+                &format!("Rollback failed for {}: {}", self.filename, e),
+            )
+            .ok();
+        }
+    }
+}
+
+/// Back up `filename` before editing it in place. Call [`BackupGuard::commit`] once the
+/// edit succeeded; if the guard is dropped first, the file is restored from the backup.
+/// Like [`backup_file`], fails if `<filename>.bak` already exists.
+pub fn begin_edit(filename: &str) -> Result<BackupGuard> {
+    begin_edit_in(&BackupConfig::from_env()?, filename)
+}
+
+/// Like [`begin_edit`], but resolves `filename` against `cfg.base_dir`.
+pub fn begin_edit_in(cfg: &BackupConfig, filename: &str) -> Result<BackupGuard> {
+    let filename = cfg.sanitize(filename)?;
+    let backup = backup_file_in(cfg, &filename)?;
+    Ok(BackupGuard {
+        cfg: cfg.clone(),
+        filename,
+        backup,
+        done: false,
+    })
+}
+
+/// Byte pattern used by the secure-delete overwrite passes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OverwritePattern {
+    /// One pass of `0x00`.
+    #[default]
+    Zeros,
+    /// One pass of `0xFF`.
+    Ones,
+    /// One pass of bytes from the OS CSPRNG, refilled for every chunk so no block repeats.
+    Random,
+    /// DoD 5220.22-M: `0x00`, then `0xFF`, then random.
+    DoD5220,
+    /// Gutmann's 35 passes: 4 random, 27 fixed patterns, 4 random.
+    Gutmann,
+}
+
+/// A single overwrite pass: a repeating byte sequence or fresh random data.
+#[derive(Debug, Clone, Copy)]
+enum Pass {
+    Fill(&'static [u8]),
+    Random,
+}
+
+const GUTMANN_FIXED: [&[u8]; 27] = [
+    &[0x55],
+    &[0xAA],
+    &[0x92, 0x49, 0x24],
+    &[0x49, 0x24, 0x92],
+    &[0x24, 0x92, 0x49],
+    &[0x00],
+    &[0x11],
+    &[0x22],
+    &[0x33],
+    &[0x44],
+    &[0x55],
+    &[0x66],
+    &[0x77],
+    &[0x88],
+    &[0x99],
+    &[0xAA],
+    &[0xBB],
+    &[0xCC],
+    &[0xDD],
+    &[0xEE],
+    &[0xFF],
+    &[0x92, 0x49, 0x24],
+    &[0x49, 0x24, 0x92],
+    &[0x24, 0x92, 0x49],
+    &[0x6D, 0xB6, 0xDB],
+    &[0xB6, 0xDB, 0x6D],
+    &[0xDB, 0x6D, 0xB6],
+];
+
+impl OverwritePattern {
+    /// Number of full-length overwrite passes this pattern performs.
+    pub fn pass_count(self) -> u32 {
+        self.passes().len() as u32
+    }
+
+    fn passes(self) -> Vec<Pass> {
+        match self {
+            OverwritePattern::Zeros => vec![Pass::Fill(&[0x00])],
+            OverwritePattern::Ones => vec![Pass::Fill(&[0xFF])],
+            OverwritePattern::Random => vec![Pass::Random],
+            OverwritePattern::DoD5220 => {
+                vec![Pass::Fill(&[0x00]), Pass::Fill(&[0xFF]), Pass::Random]
+            }
+            OverwritePattern::Gutmann => {
+                let mut passes = vec![Pass::Random; 4];
+                passes.extend(GUTMANN_FIXED.iter().map(|p| Pass::Fill(p)));
+                passes.extend([Pass::Random; 4]);
+                passes
+            }
+        }
+    }
+}
+
+/// What [`overwrite_file`] wrote.
+struct Overwritten {
+    len: u64,
+    /// Hex SHA-256 of the bytes written by the final pass, for read-back checks.
+    final_digest: String,
+}
+
+/// Overwrite the full length of `path` once per pass, syncing to disk after each.
+/// `buffer_size` is clamped to `512..=MAX_WIPE_BUFFER_SIZE`. On Windows its alternate data
+/// streams are overwritten first; see [`BackupError::StreamsNotWiped`].
+fn overwrite_file(
+    path: &Path,
+    pattern: OverwritePattern,
+    buffer_size: usize,
+) -> Result<Overwritten> {
+    wipe_alternate_streams(path, pattern, buffer_size)?;
+    overwrite_data(path, pattern, buffer_size)
+}
+
+/// [`overwrite_file`] for a single stream.
+fn overwrite_data(
+    path: &Path,
+    pattern: OverwritePattern,
+    buffer_size: usize,
+) -> Result<Overwritten> {
+    let metadata = fs::metadata(path).path_context("metadata", path)?;
+    let len = metadata.len();
+    let mut f = OpenOptions::new()
+        .write(true)
+        .open(path)
+        .path_context("open for overwrite", path)?;
+
+    let chunk_size = buffer_size.clamp(512, MAX_WIPE_BUFFER_SIZE);
+    let mut chunk = vec![0u8; chunk_size];
+    let mut final_digest = String::new();
+    for pass in pattern.passes() {
+        f.seek(SeekFrom::Start(0))
+            .context("rewind for overwrite failed")?;
+        let mut hasher = Sha256::new();
+        // Write in chunks
+        let mut written: u64 = 0;
+        while written < len {
+            let to_write = std::cmp::min(chunk_size as u64, len - written) as usize;
+            let buf = &mut chunk[..to_write];
+            match pass {
+                // Continue multi-byte patterns across chunk boundaries.
+                Pass::Fill(bytes) => {
+                    for (i, b) in buf.iter_mut().enumerate() {
+                        *b = bytes[(written as usize + i) % bytes.len()];
+                    }
+                }
+                Pass::Random => {
+                    getrandom::fill(buf)
+                        .map_err(|e| io::Error::other(e.to_string()))
+                        .context("random source failed")?;
+                }
+            }
+            f.write_all(buf).context("overwrite failed")?;
+            hasher.update(&*buf);
+            written += to_write as u64;
+        }
+        f.flush().context("flush overwrite failed")?;
+        f.sync_all().path_context("sync after overwrite", path)?;
+        final_digest = hex_digest(hasher);
+    }
+    Ok(Overwritten { len, final_digest })
+}
+
+/// Overwrite every alternate data stream of `path`, so nothing hidden in one survives a
+/// secure delete. Streams the file's ACLs do not let us write are skipped and reported
+/// together once the rest are wiped, before the main data is touched.
+#[cfg(windows)]
+fn wipe_alternate_streams(
+    path: &Path,
+    pattern: OverwritePattern,
+    buffer_size: usize,
+) -> Result<()> {
+    let mut denied = Vec::new();
+    for name in alternate_streams(path)? {
+        let mut stream = path.as_os_str().to_owned();
+        stream.push(&name);
+        match overwrite_data(Path::new(&stream), pattern, buffer_size) {
+            Ok(_) => {}
+            Err(BackupError::PermissionDenied { .. }) => {
+                denied.push(name.to_string_lossy().into_owned())
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    if !denied.is_empty() {
+        return Err(BackupError::StreamsNotWiped {
+            path: path.to_path_buf(),
+            streams: denied,
+        });
+    }
+    Ok(())
+}
+
+/// Files have no alternate data streams here.
+#[cfg(not(windows))]
+fn wipe_alternate_streams(
+    _path: &Path,
+    _pattern: OverwritePattern,
+    _buffer_size: usize,
+) -> Result<()> {
+    Ok(())
+}
+
+/// Names of the named data streams of `path`, such as `:hidden:$DATA`, ready to append to it.
+#[cfg(windows)]
+fn alternate_streams(path: &Path) -> Result<Vec<std::ffi::OsString>> {
+    use std::os::windows::ffi::{OsStrExt, OsStringExt};
+    use windows_sys::Win32::Foundation::{ERROR_HANDLE_EOF, INVALID_HANDLE_VALUE};
+    use windows_sys::Win32::Storage::FileSystem::{
+        FindClose, FindFirstStreamW, FindNextStreamW, FindStreamInfoStandard,
+        WIN32_FIND_STREAM_DATA,
+    };
+
+    let at_end = |e: &io::Error| e.raw_os_error() == Some(ERROR_HANDLE_EOF as i32);
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    // SAFETY: WIN32_FIND_STREAM_DATA is plain data, valid when zeroed.
+    let mut data: WIN32_FIND_STREAM_DATA = unsafe { std::mem::zeroed() };
+    // SAFETY: `wide` is NUL-terminated and `data` is the struct FindStreamInfoStandard fills.
+    let handle = unsafe {
+        FindFirstStreamW(
+            wide.as_ptr(),
+            FindStreamInfoStandard,
+            (&mut data as *mut WIN32_FIND_STREAM_DATA).cast(),
+            0,
+        )
+    };
+    if handle == INVALID_HANDLE_VALUE {
+        let err = io::Error::last_os_error();
+        if at_end(&err) {
+            return Ok(Vec::new());
+        }
+        return Err(err).path_context("list streams of", path);
+    }
+
+    let mut streams = Vec::new();
+    let listed = loop {
+        let len = data
+            .cStreamName
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(data.cStreamName.len());
+        let name = std::ffi::OsString::from_wide(&data.cStreamName[..len]);
+        // The unnamed stream is the file's main data, which the caller overwrites itself.
+        if name != "::$DATA" {
+            streams.push(name);
+        }
+        // SAFETY: `handle` is an open find handle and `data` is the struct it fills.
+        if unsafe { FindNextStreamW(handle, (&mut data as *mut WIN32_FIND_STREAM_DATA).cast()) }
+            == 0
+        {
+            let err = io::Error::last_os_error();
+            if at_end(&err) {
+                break Ok(streams);
+            }
+            break Err(err).path_context("list streams of", path);
+        }
+    };
+    // SAFETY: `handle` came from FindFirstStreamW and is closed only here.
+    unsafe { FindClose(handle) };
+    listed
+}
+
+/// Validate that `filename` names an existing file inside the base dir that may be deleted.
+fn delete_target(cfg: &BackupConfig, filename: &str) -> Result<PathBuf> {
+    within_base(cfg, Path::new(filename))?;
+    let path = cfg.resolve(filename);
+    check_deletable(cfg, &path)?;
+    Ok(path)
+}
+
+fn check_deletable(cfg: &BackupConfig, path: &Path) -> Result<()> {
+    check_not_log_file(cfg, path)?;
+    check_symlink(cfg, path)?;
+    if !path.exists() || !path.is_file() {
+        return Err(BackupError::SourceMissing);
+    }
+    Ok(())
+}
+
+/// `statfs` `f_type` values of Linux filesystems that write modified blocks to a new
+/// location (copy-on-write or log-structured), so an in-place overwrite leaves the old
+/// data on disk.
+#[cfg(target_os = "linux")]
+pub const COPY_ON_WRITE_FS_MAGIC: &[(u32, &str)] = &[
+    (0x9123_683E, "btrfs"),
+    (0x2FC1_2FC1, "zfs"),
+    (0xCA45_1A4E, "bcachefs"),
+    (0xF2F5_2010, "f2fs"),
+    (0x3434, "nilfs2"),
+];
+
+/// Name of the filesystem holding `path` if overwriting in place is known not to reach
+/// the old blocks there.
+#[cfg(target_os = "linux")]
+fn untrusted_overwrite_fs(path: &Path) -> Result<Option<&'static str>> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| BackupError::InvalidArgument("path contains a NUL byte".to_string()))?;
+    let mut stat = std::mem::MaybeUninit::<libc::statfs>::uninit();
+    // SAFETY: `c_path` is NUL-terminated and `stat` is only read after a successful call.
+    if unsafe { libc::statfs(c_path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error()).path_context("statfs", path);
+    }
+    // SAFETY: statfs returned 0, so it filled in `stat`.
+    let magic = unsafe { stat.assume_init() }.f_type as u32;
+    Ok(COPY_ON_WRITE_FS_MAGIC
+        .iter()
+        .find(|(m, _)| *m == magic)
+        .map(|(_, name)| *name))
+}
+
+/// APFS is copy-on-write; it is the default on every current macOS install.
+#[cfg(target_os = "macos")]
+fn untrusted_overwrite_fs(path: &Path) -> Result<Option<&'static str>> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| BackupError::InvalidArgument("path contains a NUL byte".to_string()))?;
+    let mut stat = std::mem::MaybeUninit::<libc::statfs>::uninit();
+    // SAFETY: `c_path` is NUL-terminated and `stat` is only read after a successful call.
+    if unsafe { libc::statfs(c_path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error()).path_context("statfs", path);
+    }
+    // SAFETY: statfs returned 0, so it filled in `stat`, including a NUL-terminated name.
+    let stat = unsafe { stat.assume_init() };
+    let name = unsafe { std::ffi::CStr::from_ptr(stat.f_fstypename.as_ptr()) };
+    Ok((name.to_bytes() == b"apfs").then_some("apfs"))
+}
+
+/// The filesystem type cannot be checked here.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn untrusted_overwrite_fs(_path: &Path) -> Result<Option<&'static str>> {
+    Ok(None)
+}
+
+/// Whether overwriting files in place, as [`delete_file`] does, actually replaces their data
+/// on the filesystem holding the base dir. `false` on copy-on-write and log-structured
+/// filesystems: btrfs, ZFS, bcachefs, F2FS, and NILFS on Linux (by `statfs` magic number)
+/// and APFS on macOS. Elsewhere the type is not checked and `true` is returned.
+///
+/// Even when this is `true`, SSD wear-leveling may keep old copies of the data in flash
+/// blocks the OS cannot reach; that cannot be detected from here.
+pub fn is_secure_delete_effective() -> Result<bool> {
+    is_secure_delete_effective_in(&BackupConfig::from_env()?)
+}
+
+/// Like [`is_secure_delete_effective`], for the filesystem holding `cfg.base_dir`.
+pub fn is_secure_delete_effective_in(cfg: &BackupConfig) -> Result<bool> {
+    Ok(untrusted_overwrite_fs(&cfg.base_dir)?.is_none())
+}
+
+/// Log a warning before overwriting `path` where the overwrite cannot be trusted.
+fn warn_if_overwrite_untrusted(cfg: &BackupConfig, filename: &str, path: &Path) {
+    if let Ok(Some(fs_name)) = untrusted_overwrite_fs(path) {
+        log_event(
+            cfg,
+            "WARN",
+            "delete",
+            filename,
+            &format!(
+                "{} is on {}, which does not overwrite in place; secure delete may be ineffective",
+                filename, fs_name
+            ),
+        )
+        .ok();
+    }
+}
+
+/// Warn about, or with `cfg.refuse_hard_linked` refuse with [`BackupError::HardLinked`],
+/// a secure delete of `path` while other hard links share its data.
+#[cfg(unix)]
+fn check_hard_links(cfg: &BackupConfig, filename: &str, path: &Path) -> Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    let links = fs::metadata(path).path_context("metadata", path)?.nlink();
+    if links <= 1 {
+        return Ok(());
+    }
+    if cfg.refuse_hard_linked {
+        return Err(BackupError::HardLinked { links });
+    }
+    log_event(
+        cfg,
+        "WARN",
+        "delete",
+        filename,
+        &format!(
+            "{} has {} hard links; the overwrite wipes the data of all of them, but only this one is removed",
+            filename, links
+        ),
+    )
+}
+
+/// Link counts are not checked here.
+#[cfg(not(unix))]
+fn check_hard_links(_cfg: &BackupConfig, _filename: &str, _path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Securely delete a file by overwriting with zeros and then removing.
+pub fn delete_file(filename: &str) -> Result<()> {
+    delete_file_in(&BackupConfig::from_env()?, filename)
+}
+
+/// Like [`delete_file`], but resolves `filename` against `cfg.base_dir`.
+pub fn delete_file_in(cfg: &BackupConfig, filename: &str) -> Result<()> {
+    delete_file_with_in(cfg, filename, OverwritePattern::Zeros)
+}
+
+/// Proof that a destructive operation on one file was confirmed, from [`confirm`].
+/// It cannot be built any other way, cloned, or used twice.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Confirmation {
+    filename: String,
+}
+
+impl Confirmation {
+    /// The filename this confirms, exactly as passed to [`confirm`].
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+}
+
+/// Confirm a destructive operation on `filename`, e.g. after the user typed it back.
+/// Pass the token to [`delete_file_confirmed`] or [`restore_file_confirmed`].
+pub fn confirm(filename: &str) -> Confirmation {
+    Confirmation {
+        filename: filename.to_string(),
+    }
+}
+
+fn check_confirmation(filename: &str, confirmation: &Confirmation) -> Result<()> {
+    if confirmation.filename != filename {
+        return Err(BackupError::ConfirmationMismatch {
+            confirmed: confirmation.filename.clone(),
+            requested: filename.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Like [`delete_file`], but only with a [`Confirmation`] for exactly this `filename`;
+/// any other fails with [`BackupError::ConfirmationMismatch`] before anything is touched.
+pub fn delete_file_confirmed(filename: &str, confirmation: Confirmation) -> Result<()> {
+    delete_file_confirmed_in(&BackupConfig::from_env()?, filename, confirmation)
+}
+
+/// Like [`delete_file_confirmed`], but resolves `filename` against `cfg.base_dir`.
+pub fn delete_file_confirmed_in(
+    cfg: &BackupConfig,
+    filename: &str,
+    confirmation: Confirmation,
+) -> Result<()> {
+    check_confirmation(filename, &confirmation)?;
+    delete_file_in(cfg, filename)
+}
+
+/// Like [`restore_file`], which replaces `filename`, but only with a [`Confirmation`] for
+/// exactly this `filename`, as for [`delete_file_confirmed`].
+pub fn restore_file_confirmed(filename: &str, confirmation: Confirmation) -> Result<PathBuf> {
+    restore_file_confirmed_in(&BackupConfig::from_env()?, filename, confirmation)
+}
+
+/// Like [`restore_file_confirmed`], but resolves `filename` against `cfg.base_dir`.
+pub fn restore_file_confirmed_in(
+    cfg: &BackupConfig,
+    filename: &str,
+    confirmation: Confirmation,
+) -> Result<PathBuf> {
+    check_confirmation(filename, &confirmation)?;
+    restore_file_in(cfg, filename)
+}
+
+/// Longest note [`backup_file_note`] and friends accept, in bytes.
+pub const MAX_NOTE_LEN: usize = 256;
+
+/// Like [`backup_file`], with ` [note: <note>]` appended to each message it logs, e.g. a
+/// ticket number. Surrounding whitespace is trimmed; a note that is empty, over
+/// [`MAX_NOTE_LEN`] bytes, or contains control characters such as newlines is refused.
+pub fn backup_file_note(filename: &str, note: &str) -> Result<PathBuf> {
+    backup_file_note_in(&BackupConfig::from_env()?, filename, note)
+}
+
+/// Like [`backup_file_note`], but resolves `filename` against `cfg.base_dir`.
+pub fn backup_file_note_in(cfg: &BackupConfig, filename: &str, note: &str) -> Result<PathBuf> {
+    backup_file_in(&cfg.with_note(note)?, filename)
+}
+
+/// Like [`restore_file`], with a note added to its log messages as for [`backup_file_note`].
+pub fn restore_file_note(filename: &str, note: &str) -> Result<PathBuf> {
+    restore_file_note_in(&BackupConfig::from_env()?, filename, note)
+}
+
+/// Like [`restore_file_note`], but resolves `filename` against `cfg.base_dir`.
+pub fn restore_file_note_in(cfg: &BackupConfig, filename: &str, note: &str) -> Result<PathBuf> {
+    restore_file_in(&cfg.with_note(note)?, filename)
+}
+
+/// Like [`delete_file`], with a note added to its log messages as for [`backup_file_note`].
+pub fn delete_file_note(filename: &str, note: &str) -> Result<()> {
+    delete_file_note_in(&BackupConfig::from_env()?, filename, note)
+}
+
+/// Like [`delete_file_note`], but resolves `filename` against `cfg.base_dir`.
+pub fn delete_file_note_in(cfg: &BackupConfig, filename: &str, note: &str) -> Result<()> {
+    delete_file_in(&cfg.with_note(note)?, filename)
+}
+
+/// Securely delete a file using the given overwrite pattern, then remove it.
+pub fn delete_file_with(filename: &str, pattern: OverwritePattern) -> Result<()> {
+    delete_file_with_in(&BackupConfig::from_env()?, filename, pattern)
+}
+
+/// Like [`delete_file_with`], but resolves `filename` against `cfg.base_dir`.
+pub fn delete_file_with_in(
+    cfg: &BackupConfig,
+    filename: &str,
+    pattern: OverwritePattern,
+) -> Result<()> {
+    delete_file_reported_in(cfg, filename, pattern).map(|_| ())
+}
+
+/// What a secure delete did, for wipe certificates and compliance reports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeleteReport {
+    pub filename: String,
+    /// File length overwritten by each pass.
+    pub bytes_overwritten: u64,
+    pub passes: u32,
+}
+
+/// Like [`delete_file_with`], but reports how much was overwritten and how many times.
+pub fn delete_file_reported(filename: &str, pattern: OverwritePattern) -> Result<DeleteReport> {
+    delete_file_reported_in(&BackupConfig::from_env()?, filename, pattern)
+}
+
+/// Like [`delete_file_reported`], but resolves `filename` against `cfg.base_dir`.
+pub fn delete_file_reported_in(
+    cfg: &BackupConfig,
+    filename: &str,
+    pattern: OverwritePattern,
+) -> Result<DeleteReport> {
+    cfg.counted(Tally::Delete, || delete_reported(cfg, filename, pattern))
+}
+
+/// [`delete_file`] for a target from [`prepare`].
+pub fn delete(target: &ValidatedTarget) -> Result<()> {
+    delete_in(&BackupConfig::from_env()?, target)
+}
+
+/// Like [`delete`], for a target prepared with [`prepare_in`] under the same `cfg`.
+pub fn delete_in(cfg: &BackupConfig, target: &ValidatedTarget) -> Result<()> {
+    cfg.counted(Tally::Delete, || {
+        target.check(cfg)?;
+        check_deletable(cfg, target.path())?;
+        delete_checked(
+            cfg,
+            target.filename(),
+            target.path(),
+            OverwritePattern::Zeros,
+        )
+        .map(|_| ())
+    })
+}
+
+fn delete_reported(
+    cfg: &BackupConfig,
+    filename: &str,
+    pattern: OverwritePattern,
+) -> Result<DeleteReport> {
+    let filename = cfg.sanitize(filename)?;
+    let path = delete_target(cfg, &filename)?;
+    delete_checked(cfg, &filename, &path, pattern)
+}
+
+/// Wipe and remove `path`, the file `filename` names, once [`check_deletable`] passed.
+fn delete_checked(
+    cfg: &BackupConfig,
+    filename: &str,
+    path: &Path,
+    pattern: OverwritePattern,
+) -> Result<DeleteReport> {
+    let start = Instant::now();
+    let _lock = lock_file(cfg, filename)?;
+
+    warn_if_overwrite_untrusted(cfg, filename, path);
+    check_hard_links(cfg, filename, path)?;
+    let written = overwrite_file(path, pattern, cfg.wipe_buffer_size)?;
+
+    fs::remove_file(path).path_context("remove", path)?;
+    log_event(
+        cfg,
+        "INFO",
+        "delete",
+        filename,
+        &format!(
+            "Secure delete completed for {} {}",
+            filename,
+            timing(written.len, start)
+        ),
+    )?;
+    Ok(DeleteReport {
+        filename: filename.to_string(),
+        bytes_overwritten: written.len,
+        passes: pattern.pass_count(),
+    })
+}
+
+/// Securely delete `<filename>.bak`, overwriting it with zeros before removing it.
+/// The original `<filename>` is left untouched.
+pub fn delete_backup(filename: &str) -> Result<()> {
+    delete_backup_in(&BackupConfig::from_env()?, filename)
+}
+
+/// Like [`delete_backup`], but resolves `filename` against `cfg.base_dir`.
+pub fn delete_backup_in(cfg: &BackupConfig, filename: &str) -> Result<()> {
+    cfg.counted(Tally::Delete, || wipe_backup(cfg, filename))
+}
+
+fn wipe_backup(cfg: &BackupConfig, filename: &str) -> Result<()> {
+    let start = Instant::now();
+    let filename = cfg.sanitize(filename)?;
+    let path = backup_path(cfg, &cfg.backup_name(&cfg.suffixed(&filename)))?;
+    let _lock = lock_file(cfg, &filename)?;
+
+    warn_if_overwrite_untrusted(cfg, &filename, &path);
+    check_hard_links(cfg, &filename, &path)?;
+    let written = overwrite_file(&path, OverwritePattern::Zeros, cfg.wipe_buffer_size)?;
+
+    fs::remove_file(&path).path_context("remove", &path)?;
+    forget_in_manifest(cfg, std::slice::from_ref(&path))?;
+    log_event(
+        cfg,
+        "INFO",
+        "delete_backup",
+        &filename,
+        &format!(
+            "Backup of {} securely deleted {}",
+            filename,
+            timing(written.len, start)
+        ),
+    )?;
+    Ok(())
+}
+
+/// Like [`delete_file_with`], but re-reads the file after the last pass and only removes
+/// it if every byte matches what was written. On a mismatch the file is left in place,
+/// so a failed wipe is never hidden, and [`BackupError::WipeVerificationFailed`] is returned.
+pub fn delete_file_verified(filename: &str, pattern: OverwritePattern) -> Result<()> {
+    delete_file_verified_in(&BackupConfig::from_env()?, filename, pattern)
+}
+
+/// Like [`delete_file_verified`], but resolves `filename` against `cfg.base_dir`.
+pub fn delete_file_verified_in(
+    cfg: &BackupConfig,
+    filename: &str,
+    pattern: OverwritePattern,
+) -> Result<()> {
+    cfg.counted(Tally::Delete, || delete_verified(cfg, filename, pattern))
+}
+
+fn delete_verified(cfg: &BackupConfig, filename: &str, pattern: OverwritePattern) -> Result<()> {
+    let start = Instant::now();
+    let filename = cfg.sanitize(filename)?;
+    let path = delete_target(cfg, &filename)?;
+    let _lock = lock_file(cfg, &filename)?;
+
+    warn_if_overwrite_untrusted(cfg, &filename, &path);
+    check_hard_links(cfg, &filename, &path)?;
+    let written = overwrite_file(&path, pattern, cfg.wipe_buffer_size)?;
+    // A digest over the whole file also catches a length change.
+    if sha256_file(&path)? != written.final_digest {
+        log_event(
+            cfg,
+            "ERROR",
+            "delete",
+            &filename,
+            &format!(
+                "Wipe verification failed for {}; file left in place",
+                filename
+            ),
+        )
+        .ok();
+        return Err(BackupError::WipeVerificationFailed);
+    }
+    log_event(
+        cfg,
+        "INFO",
+        "delete",
+        &filename,
+        &format!("Wipe verified for {} ({} bytes)", filename, written.len),
+    )?;
+
+    fs::remove_file(&path).path_context("remove", &path)?;
+    log_event(
+        cfg,
+        "INFO",
+        "delete",
+        &filename,
+        &format!(
+            "Secure delete completed for {} {}",
+            filename,
+            timing(written.len, start)
+        ),
+    )?;
+    Ok(())
+}
+
+/// Directory under `base_dir` that [`trash_file`] moves files into.
+pub const TRASH_DIR: &str = ".trash";
+
+/// Move `filename` into [`TRASH_DIR`] as `<filename>.<timestamp>[-<n>]`, keeping any
+/// subdirectories, instead of wiping it. Returns the trashed path; pass its name relative
+/// to the trash to [`restore_from_trash`] to put the file back.
+pub fn trash_file(filename: &str) -> Result<PathBuf> {
+    trash_file_in(&BackupConfig::from_env()?, filename)
+}
+
+/// Like [`trash_file`], but resolves `filename` against `cfg.base_dir`.
+pub fn trash_file_in(cfg: &BackupConfig, filename: &str) -> Result<PathBuf> {
+    cfg.counted(Tally::Delete, || trash(cfg, filename))
+}
+
+fn trash(cfg: &BackupConfig, filename: &str) -> Result<PathBuf> {
+    let filename = cfg.sanitize(filename)?;
+    let path = delete_target(cfg, &filename)?;
+    let _lock = lock_file(cfg, &filename)?;
+
+    let stamp = cfg.now().format(VERSION_FORMAT).to_string();
+    let mut counter = 0u32;
+    let trashed = loop {
+        let version = if counter == 0 {
+            stamp.clone()
+        } else {
+            format!("{}-{}", stamp, counter)
+        };
+        let name = format!("{}/{}.{}", TRASH_DIR, filename, version);
+        within_base(cfg, Path::new(&name))?;
+        let candidate = cfg.resolve(&name);
+        if !candidate.exists() {
+            break candidate;
+        }
+        counter += 1;
+    };
+    if let Some(dir) = trashed.parent() {
+        fs::create_dir_all(dir).path_context("create", dir)?;
+    }
+    fs::rename(&path, &trashed)
+        .with_context(|| format!("rename {} to {}", path.display(), trashed.display()))?;
+
+    log_event(
+        cfg,
+        "INFO",
+        "trash",
+        &filename,
+        &format!("Moved {} to {}", filename, trashed.display()),
+    )?;
+    Ok(trashed)
+}
+
+/// Move the trashed file `name`, such as `notes.txt.20240101T000000Z`, back to where it
+/// came from. Fails with [`BackupError::DestinationExists`] rather than replacing a file.
+pub fn restore_from_trash(name: &str) -> Result<PathBuf> {
+    restore_from_trash_in(&BackupConfig::from_env()?, name)
+}
+
+/// Like [`restore_from_trash`], but uses the trash and files under `cfg.base_dir`.
+pub fn restore_from_trash_in(cfg: &BackupConfig, name: &str) -> Result<PathBuf> {
+    cfg.counted(Tally::Restore, || restore_trashed(cfg, name))
+}
+
+fn restore_trashed(cfg: &BackupConfig, name: &str) -> Result<PathBuf> {
+    let invalid = || BackupError::InvalidArgument(format!("{} is not a trashed file name", name));
+    let (original, version) = name.rsplit_once('.').ok_or_else(invalid)?;
+    if parse_version(version).is_none() {
+        return Err(invalid());
+    }
+    let original = cfg.sanitize(original)?;
+    let trash_name = format!("{}/{}.{}", TRASH_DIR, original, version);
+    within_base(cfg, Path::new(&trash_name))?;
+    let trashed = cfg.resolve(&trash_name);
+    check_symlink(cfg, &trashed)?;
+    if !trashed.is_file() {
+        return Err(BackupError::SourceMissing);
+    }
+    within_base(cfg, Path::new(&original))?;
+    let dest = cfg.resolve(&original);
+    let _lock = lock_file(cfg, &original)?;
+    if dest.exists() {
+        return Err(BackupError::DestinationExists);
+    }
+    fs::rename(&trashed, &dest)
+        .with_context(|| format!("rename {} to {}", trashed.display(), dest.display()))?;
+
+    log_event(
+        cfg,
+        "INFO",
+        "trash",
+        &original,
+        &format!("Restored {} from the trash", original),
+    )?;
+    Ok(dest)
+}
+
+/// Securely delete everything in [`TRASH_DIR`], overwriting each file with zeros before
+/// removing it, then remove the emptied directories. Symlinks are removed without touching
+/// their targets. Returns the number of files wiped.
+pub fn empty_trash() -> Result<usize> {
+    empty_trash_in(&BackupConfig::from_env()?)
+}
+
+/// Like [`empty_trash`], for the trash under `cfg.base_dir`.
+pub fn empty_trash_in(cfg: &BackupConfig) -> Result<usize> {
+    cfg.counted(Tally::Delete, || empty_trash_dir(cfg))
+}
+
+fn empty_trash_dir(cfg: &BackupConfig) -> Result<usize> {
+    let start = Instant::now();
+    let trash = cfg.resolve(TRASH_DIR);
+    match fs::symlink_metadata(&trash) {
+        Ok(meta) if meta.is_dir() => {}
+        Ok(_) => return Err(BackupError::NotADirectory),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e).path_context("metadata", &trash),
+    }
+
+    let mut wiped = 0usize;
+    let mut bytes = 0u64;
+    let mut dirs = Vec::new();
+    let mut pending = vec![trash.clone()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir).path_context("read directory", &dir)? {
+            let entry = entry.context("read directory entry failed")?;
+            let path = entry.path();
+            let file_type = entry.file_type().path_context("file type of", &path)?;
+            if file_type.is_dir() {
+                pending.push(path);
+            } else {
+                if file_type.is_file() {
+                    bytes +=
+                        overwrite_file(&path, OverwritePattern::Zeros, cfg.wipe_buffer_size)?.len;
+                    wiped += 1;
+                }
+                fs::remove_file(&path).path_context("remove", &path)?;
+            }
+        }
+        dirs.push(dir);
+    }
+    // Children were pushed after their parents, so removing in reverse empties each first.
+    for dir in dirs.iter().rev() {
+        fs::remove_dir(dir).path_context("remove", dir)?;
+    }
+
+    log_event(
+        cfg,
+        "INFO",
+        "trash",
+        "",
+        &format!("Trash emptied: {} files {}", wiped, timing(bytes, start)),
+    )?;
+    Ok(wiped)
+}
+
+/// Every temp this crate writes that is still under the base dir, such as the one a crashed
+/// backup or restore left behind, sorted by path. Only the crate's own temp names count:
+/// `<name>.tmp` and `<name>.bak.tmp` for a valid `<name>`, the manifest's, and the
+/// [`wipe_free_space`] filler. Other `*.tmp` files, the trash, and temps whose file is
+/// locked by an operation in progress are left out; symlinks are not followed.
+/// Nothing is changed, but the count found is logged.
+pub fn find_orphaned_temps() -> Result<Vec<PathBuf>> {
+    find_orphaned_temps_in(&BackupConfig::from_env()?)
+}
+
+/// Like [`find_orphaned_temps`], under `cfg.base_dir`.
+pub fn find_orphaned_temps_in(cfg: &BackupConfig) -> Result<Vec<PathBuf>> {
+    let probe = fail_fast(cfg);
+    let temps: Vec<PathBuf> = orphaned_temps(cfg)?
+        .into_iter()
+        .filter(|(_, owner)| !matches!(lock_file(&probe, owner), Err(BackupError::Locked)))
+        .map(|(path, _)| path)
+        .collect();
+    log_event(
+        cfg,
+        "INFO",
+        "temps",
+        "",
+        &format!("Found {} orphaned temp files", temps.len()),
+    )?;
+    Ok(temps)
+}
+
+/// Securely delete every file [`find_orphaned_temps`] reports, overwriting it with zeros
+/// first, and return the paths removed. Each temp is wiped under its file's lock, and one
+/// whose file is in use is skipped; a temp that cannot be removed is logged and skipped.
+pub fn clean_orphaned_temps() -> Result<Vec<PathBuf>> {
+    clean_orphaned_temps_in(&BackupConfig::from_env()?)
+}
+
+/// Like [`clean_orphaned_temps`], under `cfg.base_dir`.
+pub fn clean_orphaned_temps_in(cfg: &BackupConfig) -> Result<Vec<PathBuf>> {
+    cfg.counted(Tally::Delete, || clean_temps(cfg))
+}
+
+fn clean_temps(cfg: &BackupConfig) -> Result<Vec<PathBuf>> {
+    let start = Instant::now();
+    let probe = fail_fast(cfg);
+    let mut temps = Vec::new();
+    let mut bytes = 0u64;
+    for (path, owner) in orphaned_temps(cfg)? {
+        let _lock = match lock_file(&probe, &owner) {
+            Ok(lock) => lock,
+            Err(BackupError::Locked) => {
+                log_event(
+                    cfg,
+                    "INFO",
+                    "temps",
+                    &owner,
+                    &format!("Skipped temp {}: {} is in use", path.display(), owner),
+                )?;
+                continue;
+            }
+            Err(e) => {
+                skip_temp(cfg, &path, &e);
+                continue;
+            }
+        };
+        let wiped =
+            overwrite_file(&path, OverwritePattern::Zeros, cfg.wipe_buffer_size).and_then(|w| {
+                fs::remove_file(&path)
+                    .path_context("remove", &path)
+                    .map(|()| w.len)
+            });
+        match wiped {
+            Ok(len) => bytes += len,
+            Err(e) => {
+                skip_temp(cfg, &path, &e);
+                continue;
+            }
+        }
+        log_event(
+            cfg,
+            "INFO",
+            "temps",
+            "",
+            &format!("Removed orphaned temp {}", path.display()),
+        )?;
+        temps.push(path);
+    }
+    log_event(
+        cfg,
+        "INFO",
+        "temps",
+        "",
+        &format!(
+            "Orphaned temps cleaned: {} files {}",
+            temps.len(),
+            timing(bytes, start)
+        ),
+    )?;
+    Ok(temps)
+}
+
+/// `cfg` with [`LockMode::FailFast`], for probing whether a temp's file is in use.
+fn fail_fast(cfg: &BackupConfig) -> BackupConfig {
+    BackupConfig {
+        lock_mode: LockMode::FailFast,
+        ..cfg.clone()
+    }
+}
+
+fn skip_temp(cfg: &BackupConfig, path: &Path, e: &BackupError) {
+    log_event(
+        cfg,
+        "WARN",
+        "temps",
+        "",
+        &format!("Skipped {}: {}", path.display(), e),
+    )
+    .ok();
+}
+
+/// Each crate temp under the base dir, with the filename whose lock guards it. A directory
+/// or entry that cannot be read is logged and skipped, except the base dir itself.
+fn orphaned_temps(cfg: &BackupConfig) -> Result<Vec<(PathBuf, String)>> {
+    let log = cfg.log_path();
+    let trash = cfg.resolve(TRASH_DIR);
+    let mut temps = Vec::new();
+    let mut pending = vec![cfg.base_dir.clone()];
+    while let Some(dir) = pending.pop() {
+        let entries = match fs::read_dir(&dir).path_context("read directory", &dir) {
+            Ok(entries) => entries,
+            Err(e) if dir != cfg.base_dir => {
+                skip_temp(cfg, &dir, &e);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let scanned = entry
+                .context("read directory entry failed")
+                .and_then(|entry| {
+                    let path = entry.path();
+                    let file_type = entry.file_type().path_context("file type of", &path)?;
+                    Ok((path, file_type))
+                });
+            let (path, file_type) = match scanned {
+                Ok(scanned) => scanned,
+                Err(e) => {
+                    skip_temp(cfg, &dir, &e);
+                    continue;
+                }
+            };
+            if file_type.is_dir() {
+                if path != trash {
+                    pending.push(path);
+                }
+            } else if file_type.is_file() && !same_path(&path, &log) {
+                let owner = path
+                    .strip_prefix(&cfg.base_dir)
+                    .ok()
+                    .and_then(|rel| temp_owner(cfg, rel));
+                if let Some(owner) = owner {
+                    temps.push((path, owner));
+                }
+            }
+        }
+    }
+    temps.sort();
+    Ok(temps)
+}
+
+/// The filename whose lock guards the temp at `rel`, relative to the base dir, or `None`
+/// if `rel` is not a name this crate writes temps under: `notes.txt` for `notes.txt.tmp`,
+/// `notes.txt.xdev.tmp`, and `notes.txt.bak.tmp` (under `backup_dir` when one is set).
+fn temp_owner(cfg: &BackupConfig, rel: &Path) -> Option<String> {
+    let name = rel.file_name()?.to_str()?.strip_suffix(TEMP_SUFFIX)?;
+    let name = name.strip_suffix(".xdev").unwrap_or(name);
+    let parent = rel.parent().unwrap_or(Path::new(""));
+    if parent.as_os_str().is_empty() && name == WIPE_FILL_NAME {
+        return Some(name.to_string());
+    }
+    let manifest = cfg.backup_name(MANIFEST_NAME);
+    if parent.join(name) == Path::new(&manifest) {
+        return Some(manifest);
+    }
+    let in_backup_dir = |dir: &Path| parent.strip_prefix(dir).ok();
+    let (parent, original) = match (backed_up_name(cfg, name), &cfg.backup_dir) {
+        (Some(original), Some(dir)) => (in_backup_dir(dir)?, original),
+        (Some(original), None) => (parent, original),
+        (None, Some(dir)) if in_backup_dir(dir).is_some() => return None,
+        (None, _) => (parent, name),
+    };
+    sanitize_file(
+        original,
+        &cfg.allowed_exts,
+        cfg.unicode_names,
+        cfg.name_len_limit(),
+    )
+    .ok()?;
+    Some(parent.join(original).to_string_lossy().into_owned())
+}
+
+/// Completion messages [`undo_last`] recognizes, and the operation each one records.
+const UNDOABLE: [(&str, &str); 5] = [
+    ("Backup created for ", "backup"),
+    ("Restore completed for ", "restore"),
+    ("Secure delete completed for ", "delete"),
+    ("Wipe verified for ", "delete"),
+    ("Undo completed for ", "undo"),
+];
+
+/// INFO messages that record no change to any file, which [`undo_last`] looks past.
+const NOT_OPERATIONS: [&str; 2] = ["Log checkpoint ", "Found "];
+
+/// The message of the newest INFO line in the current logfile that records an operation.
+/// Works for both log formats, since the message is the same.
+fn last_logged_operation(cfg: &BackupConfig) -> Result<Option<String>> {
+    let path = cfg.log_path();
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).path_context("read", &path),
+    };
+    for line in text.lines().rev() {
+        let message = match cfg.log_format {
+            LogFormat::Text => match line.split_once("] INFO: ") {
+                Some((_, message)) => message.to_string(),
+                None => continue,
+            },
+            LogFormat::Json => match serde_json::from_str::<serde_json::Value>(line) {
+                Ok(v) if v["level"] == "INFO" => match v["message"].as_str() {
+                    Some(message) => message.to_string(),
+                    None => continue,
+                },
+                _ => continue,
+            },
+        };
+        if !NOT_OPERATIONS
+            .iter()
+            .any(|prefix| message.starts_with(prefix))
+        {
+            return Ok(Some(message));
+        }
+    }
+    Ok(None)
+}
+
+/// The operation and filename `message` records, if it is one of [`UNDOABLE`].
+fn logged_operation(message: &str) -> Option<(&'static str, String)> {
+    UNDOABLE.iter().find_map(|(prefix, operation)| {
+        let rest = message.strip_prefix(prefix)?;
+        // Sanitized names never contain spaces, so the name ends at the first one.
+        Some((
+            *operation,
+            rest.split(' ').next().unwrap_or(rest).to_string(),
+        ))
+    })
+}
+
+/// The snapshot the logged restore `message` of `filename` took, checked to still be the
+/// file it wrote, so an older snapshot is never rolled back to by mistake.
+fn logged_snapshot(cfg: &BackupConfig, filename: &str, message: &str) -> Result<String> {
+    let logged = message
+        .split_once(" snapshot ")
+        .and_then(|(_, rest)| rest.split_once(" sha256="));
+    let Some((snap_name, expected)) = logged else {
+        return Err(BackupError::CannotUndo(format!(
+            "restore of {} was made without a snapshot",
+            filename
+        )));
+    };
+    let expected = expected.split(' ').next().unwrap_or(expected);
+    within_base(cfg, Path::new(snap_name))?;
+    let snap = cfg.resolve(snap_name);
+    if !snap.is_file() || sha256_file(&snap)? != expected {
+        return Err(BackupError::CannotUndo(format!(
+            "snapshot {} is not the one the restore of {} took",
+            snap_name, filename
+        )));
+    }
+    Ok(snap_name.to_string())
+}
+
+/// Best-effort reversal of the most recent operation in the logfile: a backup's `.bak` is
+/// securely deleted, and a restore made with [`RestoreOptions::snapshot`] is rolled back
+/// from its `.pre-restore.bak`, provided that still has the SHA-256 the restore logged.
+/// Any other operation, including deletes and anything already undone, fails with
+/// [`BackupError::CannotUndo`]. Only the current logfile is read, not rotated ones.
+pub fn undo_last() -> Result<()> {
+    undo_last_in(&BackupConfig::from_env()?)
+}
+
+/// Like [`undo_last`], but reads the log and files of `cfg`.
+pub fn undo_last_in(cfg: &BackupConfig) -> Result<()> {
+    let Some(message) = last_logged_operation(cfg)? else {
+        return Err(BackupError::CannotUndo(
+            "no operation in the log".to_string(),
+        ));
+    };
+    // Only the newest operation is undone; reaching past one that cannot be would
+    // reverse something older instead.
+    let Some((operation, filename)) = logged_operation(&message) else {
+        return Err(BackupError::CannotUndo(format!(
+            "the last operation cannot be undone: {}",
+            message
+        )));
+    };
+    let filename = cfg.sanitize(&filename)?;
+    match operation {
+        "backup" => delete_backup_in(cfg, &filename)?,
+        "restore" => {
+            let snap_name = logged_snapshot(cfg, &filename, &message)?;
+            restore_from(cfg, &snap_name, &filename, RestoreOptions::default())?;
+            let snap = cfg.resolve(&snap_name);
+            fs::remove_file(&snap).path_context("remove", &snap)?;
+        }
+        "delete" => {
+            return Err(BackupError::CannotUndo(format!(
+                "{} was securely deleted",
+                filename
+            )))
+        }
+        _ => {
+            return Err(BackupError::CannotUndo(format!(
+                "the last operation on {} was already undone",
+                filename
+            )))
+        }
+    }
+    log_event(
+        cfg,
+        "INFO",
+        "undo",
+        &filename,
+        &format!("Undo completed for {} (reverted {})", filename, operation),
+    )?;
+    Ok(())
+}
+
+/// Bytes available to unprivileged writers on the filesystem holding `path`.
+#[cfg(unix)]
+fn available_space(path: &Path) -> Result<Option<u64>> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| BackupError::InvalidArgument("path contains a NUL byte".to_string()))?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `c_path` is NUL-terminated and `stat` is only read after a successful call.
+    let rc = unsafe { libc::statvfs(c_path.as_ptr(), stat.as_mut_ptr()) };
+    if rc != 0 {
+        return Err(io::Error::last_os_error()).path_context("statvfs", path);
+    }
+    // SAFETY: statvfs returned 0, so it filled in `stat`.
+    let stat = unsafe { stat.assume_init() };
+    // The field widths differ between platforms.
+    #[allow(clippy::unnecessary_cast)]
+    Ok(Some(stat.f_bavail as u64 * stat.f_frsize as u64))
+}
+
+/// Free space cannot be queried here; [`wipe_free_space`] stops at its size limit or when
+/// the disk reports full.
+#[cfg(not(unix))]
+fn available_space(_path: &Path) -> Result<Option<u64>> {
+    Ok(None)
+}
+
+/// Bytes free for writing on the volume holding the base dir. Compare with
+/// [`estimate_backup_space`] before starting a batch.
+pub fn free_space() -> Result<u64> {
+    free_space_in(&BackupConfig::from_env()?)
+}
+
+/// Like [`free_space`], for the volume holding `cfg.base_dir`.
+pub fn free_space_in(cfg: &BackupConfig) -> Result<u64> {
+    available_space(&cfg.base_dir)?.ok_or_else(|| BackupError::Io {
+        context: "free space is not available on this platform".to_string(),
+        source: io::Error::from(io::ErrorKind::Unsupported),
+    })
+}
+
+/// Total size in bytes of the sources in `filenames`, which is what [`backup_many`] needs
+/// to write. Every name goes through the same checks as [`backup_file`], and the first one
+/// that fails is returned as the error.
+pub fn estimate_backup_space(filenames: &[&str]) -> Result<u64> {
+    estimate_backup_space_in(&BackupConfig::from_env()?, filenames)
+}
+
+/// Like [`estimate_backup_space`], but resolves names against `cfg.base_dir`.
+pub fn estimate_backup_space_in(cfg: &BackupConfig, filenames: &[&str]) -> Result<u64> {
+    let mut total = 0u64;
+    for name in filenames {
+        let filename = cfg.sanitize(name)?;
+        let src = backup_source(cfg, &filename)?;
+        total += fs::metadata(&src).path_context("metadata", &src)?.len();
+    }
+    Ok(total)
+}
+
+/// Overwrite free space in the base dir's filesystem, so data from files removed without
+/// [`delete_file`] cannot be recovered from it. A temp file of random data is grown to at
+/// most `size_limit` bytes, leaving [`BackupConfig::free_space_margin`] bytes free, then
+/// removed. Returns the number of bytes written.
+pub fn wipe_free_space(size_limit: u64) -> Result<u64> {
+    wipe_free_space_in(&BackupConfig::from_env()?, size_limit)
+}
+
+/// The filler [`wipe_free_space`] writes, less its `.tmp`; its lock is taken on this name.
+const WIPE_FILL_NAME: &str = ".safe_backup_wipe";
+
+/// Like [`wipe_free_space`], but fills the filesystem holding `cfg.base_dir`.
+pub fn wipe_free_space_in(cfg: &BackupConfig, size_limit: u64) -> Result<u64> {
+    let budget = match available_space(&cfg.base_dir)? {
+        Some(free) => size_limit.min(free.saturating_sub(cfg.free_space_margin)),
+        None => size_limit,
+    };
+    let start = Instant::now();
+    let _lock = lock_file(cfg, WIPE_FILL_NAME)?;
+    let path = cfg.resolve(&format!("{}{}", WIPE_FILL_NAME, TEMP_SUFFIX));
+    // Removed on drop, including when a write fails partway.
+    let mut filler = TempFile::create_new(&path)?;
+    let f = filler.file();
+
+    let chunk_size = cfg.wipe_buffer_size.clamp(512, MAX_WIPE_BUFFER_SIZE);
+    let mut chunk = vec![0u8; chunk_size];
+    let mut written: u64 = 0;
+    while written < budget {
+        let to_write = std::cmp::min(chunk_size as u64, budget - written) as usize;
+        let buf = &mut chunk[..to_write];
+        getrandom::fill(buf)
+            .map_err(|e| io::Error::other(e.to_string()))
+            .context("random source failed")?;
+        match f.write_all(buf) {
+            Ok(()) => written += to_write as u64,
+            // The margin is only an estimate; a full disk ends the wipe rather than failing it.
+            Err(e) if e.kind() == io::ErrorKind::StorageFull => break,
+            Err(e) => return Err(e).path_context("write", &path),
+        }
+    }
+    f.sync_all().path_context("sync", &path)?;
+    drop(filler);
+
+    log_event(
+        cfg,
+        "INFO",
+        "wipe_free_space",
+        "",
+        &format!("Free space wiped {}", timing(written, start)),
+    )?;
+    Ok(written)
+}
+
+/// Mirror the directory `dirname` into `<dirname>.bak/`, copying every allowed file.
+/// Symlinks are never followed, and entries whose names fail validation (including
+/// disallowed extensions) are skipped with a logged warning instead of aborting.
+pub fn backup_dir(dirname: &str) -> Result<PathBuf> {
+    backup_dir_in(&BackupConfig::from_env()?, dirname)
+}
+
+/// Like [`backup_dir`], but resolves `dirname` against `cfg.base_dir`.
+pub fn backup_dir_in(cfg: &BackupConfig, dirname: &str) -> Result<PathBuf> {
+    cfg.counted(Tally::Backup, || mirror_dir(cfg, dirname))
+}
+
+fn mirror_dir(cfg: &BackupConfig, dirname: &str) -> Result<PathBuf> {
+    sanitize_name(dirname, cfg.unicode_names, cfg.name_len_limit())?;
+    within_base(cfg, Path::new(dirname))?;
+    let root = cfg.resolve(dirname);
+    let meta = fs::symlink_metadata(&root).map_err(|_| BackupError::SourceMissing)?;
+    if !meta.is_dir() {
+        return Err(BackupError::NotADirectory);
+    }
+    let mirror_name = cfg.suffixed(dirname);
+    within_base(cfg, Path::new(&mirror_name))?;
+    let mirror = cfg.resolve(&mirror_name);
+    if mirror.exists() {
+        return Err(BackupError::BackupExists);
+    }
+    fs::create_dir(&mirror).path_context("create", &mirror)?;
+
+    let mut copied = 0usize;
+    let mut pending = vec![PathBuf::new()];
+    while let Some(rel) = pending.pop() {
+        let dir = root.join(&rel);
+        // Read in full first, so the lockfiles taken below are not listed as entries.
+        let entries = fs::read_dir(&dir)
+            .path_context("read directory", &dir)?
+            .collect::<io::Result<Vec<_>>>()
+            .context("read directory entry failed")?;
+        for entry in entries {
+            let name = entry.file_name();
+            let entry_rel = rel.join(&name);
+            let shown = Path::new(dirname).join(&entry_rel).display().to_string();
+            let skip = |reason: &str| {
+                log_event(
+                    cfg,
+                    "WARN",
+                    "backup_dir",
+                    &shown,
+                    &format!("Skipped {}: {}", shown, reason),
+                )
+            };
+            let Some(name) = name.to_str() else {
+                skip("name is not valid UTF-8")?;
+                continue;
+            };
+            if !cfg.include_hidden && name.starts_with('.') {
+                log_event(
+                    cfg,
+                    "DEBUG",
+                    "backup_dir",
+                    &shown,
+                    &format!("Skipped {}: hidden", shown),
+                )?;
+                continue;
+            }
+            let file_type = entry
+                .file_type()
+                .with_context(|| format!("file type of {}", shown))?;
+            if file_type.is_symlink() {
+                skip("symbolic links are not followed")?;
+                continue;
+            }
+            let checked = if file_type.is_dir() {
+                sanitize_name(name, cfg.unicode_names, cfg.name_len_limit())
+            } else {
+                cfg.sanitize(name)
+                    .and_then(|_| check_size(cfg, &entry.path()))
+                    .and_then(|_| check_text_content(cfg, &entry.path()))
+            };
+            if let Err(e) = checked {
+                skip(&e.to_string())?;
+                continue;
+            }
+            within_base(cfg, &Path::new(dirname).join(&entry_rel))?;
+            let dest = mirror.join(&entry_rel);
+            if file_type.is_dir() {
+                fs::create_dir(&dest).path_context("create", &dest)?;
+                pending.push(entry_rel);
+            } else if file_type.is_file() {
+                let source_name = Path::new(dirname).join(&entry_rel);
+                let _lock = lock_file(cfg, &source_name.to_string_lossy())?;
+                copy_to_new(cfg, &entry.path(), &dest)?;
+                copied += 1;
+            } else {
+                skip("not a regular file")?;
+            }
+        }
+    }
+
+    log_event(
+        cfg,
+        "INFO",
+        "backup_dir",
+        dirname,
+        &format!(
+            "Directory backup created for {} ({} files)",
+            dirname, copied
+        ),
+    )?;
+    Ok(mirror)
+}
+
+/// Run `op` on every name independently, logging each failure, and keep input order.
+fn run_many<T>(
+    cfg: &BackupConfig,
+    filenames: &[&str],
+    operation: &str,
+    label: &str,
+    op: impl Fn(&BackupConfig, &str) -> Result<T>,
+) -> Vec<(String, Result<T>)> {
+    filenames
+        .iter()
+        .map(|&name| {
+            let result = op(cfg, name);
+            if let Err(e) = &result {
+                log_event(
+                    cfg,
+                    "ERROR",
+                    operation,
+                    name,
+                    &format!("{} failed for {}: {}", label, name, e),
+                )
+                .ok();
+            }
+            (name.to_string(), result)
+        })
+        .collect()
+}
+
+/// The [`BackupConfig::from_env`] error `e` as the result of each file in `filenames`, as
+/// [`BackupError::InvalidConfig`] so every file can carry its own copy.
+fn config_failed<T>(filenames: &[&str], e: BackupError) -> Vec<(String, Result<T>)> {
+    let message = match e {
+        BackupError::InvalidConfig(message) => message,
+        e => e.to_string(),
+    };
+    filenames
+        .iter()
+        .map(|&name| {
+            let e = BackupError::InvalidConfig(message.clone());
+            (name.to_string(), Err(e))
+        })
+        .collect()
+}
+
+/// Back up every file in `filenames`; one failure does not stop the rest.
+/// Results are returned in input order.
+pub fn backup_many(filenames: &[&str]) -> Vec<(String, Result<PathBuf>)> {
+    match BackupConfig::from_env() {
+        Ok(cfg) => backup_many_in(&cfg, filenames),
+        Err(e) => config_failed(filenames, e),
+    }
+}
+
+/// Like [`backup_many`], but resolves names against `cfg.base_dir`.
+pub fn backup_many_in(cfg: &BackupConfig, filenames: &[&str]) -> Vec<(String, Result<PathBuf>)> {
+    run_many(cfg, filenames, "backup", "Backup", backup_file_in)
+}
+
+/// Restore every file in `filenames`; one failure does not stop the rest.
+/// Results are returned in input order.
+pub fn restore_many(filenames: &[&str]) -> Vec<(String, Result<PathBuf>)> {
+    match BackupConfig::from_env() {
+        Ok(cfg) => restore_many_in(&cfg, filenames),
+        Err(e) => config_failed(filenames, e),
+    }
+}
+
+/// Like [`restore_many`], but resolves names against `cfg.base_dir`.
+pub fn restore_many_in(cfg: &BackupConfig, filenames: &[&str]) -> Vec<(String, Result<PathBuf>)> {
+    run_many(cfg, filenames, "restore", "Restore", restore_file_in)
+}
+
+/// Securely delete every file in `filenames`; one failure does not stop the rest.
+/// Results are returned in input order.
+pub fn delete_many(filenames: &[&str]) -> Vec<(String, Result<()>)> {
+    match BackupConfig::from_env() {
+        Ok(cfg) => delete_many_in(&cfg, filenames),
+        Err(e) => config_failed(filenames, e),
+    }
+}
+
+/// Like [`delete_many`], but resolves names against `cfg.base_dir`.
+pub fn delete_many_in(cfg: &BackupConfig, filenames: &[&str]) -> Vec<(String, Result<()>)> {
+    run_many(cfg, filenames, "delete", "Secure delete", delete_file_in)
+}
+
+/// What an operation would do, as reported by the `*_dry` functions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PlannedAction {
+    /// File that would be read (or, for delete, wiped).
+    pub source: PathBuf,
+    /// File that would be written; `None` for delete.
+    pub destination: Option<PathBuf>,
+    /// Whether `destination` already exists and would be replaced.
+    pub overwrites: bool,
+}
+
+/// Run every check [`backup_file`] runs and report what it would do, without touching disk.
+/// Returns the same error the real call would fail with.
+pub fn backup_file_dry(filename: &str) -> Result<PlannedAction> {
+    backup_file_dry_in(&BackupConfig::from_env()?, filename)
+}
+
+/// Like [`backup_file_dry`], but resolves `filename` against `cfg.base_dir`.
+pub fn backup_file_dry_in(cfg: &BackupConfig, filename: &str) -> Result<PlannedAction> {
+    let filename = cfg.sanitize(filename)?;
+    let source = backup_source(cfg, &filename)?;
+    let bak_name = cfg.backup_name(&cfg.suffixed(&filename));
+    within_base(cfg, Path::new(&bak_name))?;
+    let bak = cfg.resolve(&bak_name);
+    ensure_distinct(&source, &bak)?;
+    if bak.exists() {
+        return Err(BackupError::BackupExists);
+    }
+    Ok(PlannedAction {
+        source,
+        destination: Some(bak),
+        overwrites: false,
+    })
+}
+
+/// Run every check [`restore_file`] runs and report what it would do, without touching disk.
+pub fn restore_file_dry(filename: &str) -> Result<PlannedAction> {
+    restore_file_dry_in(&BackupConfig::from_env()?, filename)
+}
+
+/// Like [`restore_file_dry`], but resolves `filename` against `cfg.base_dir`.
+pub fn restore_file_dry_in(cfg: &BackupConfig, filename: &str) -> Result<PlannedAction> {
+    let filename = cfg.sanitize(filename)?;
+    let source = backup_path(cfg, &restore_source(cfg, &filename))?;
+    within_base(cfg, Path::new(&filename))?;
+    let dest = cfg.resolve(&filename);
+    ensure_distinct(&source, &dest)?;
+    Ok(PlannedAction {
+        source,
+        overwrites: dest.exists(),
+        destination: Some(dest),
+    })
+}
+
+/// Run every check [`delete_file`] runs and report what it would do, without touching disk.
+pub fn delete_file_dry(filename: &str) -> Result<PlannedAction> {
+    delete_file_dry_in(&BackupConfig::from_env()?, filename)
+}
+
+/// Like [`delete_file_dry`], but resolves `filename` against `cfg.base_dir`.
+pub fn delete_file_dry_in(cfg: &BackupConfig, filename: &str) -> Result<PlannedAction> {
+    let filename = cfg.sanitize(filename)?;
+    Ok(PlannedAction {
+        source: delete_target(cfg, &filename)?,
+        destination: None,
+        overwrites: false,
+    })
+}
+
+/// The absolute paths an operation works with, from [`resolve_paths`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ResolvedPaths {
+    /// File that is read (or, for delete, wiped).
+    pub source: PathBuf,
+    /// File that is written; `None` for delete.
+    pub destination: Option<PathBuf>,
+    /// Temp file the destination is written through before it is renamed into place.
+    pub temp: Option<PathBuf>,
+    /// Logfile the operation appends to.
+    pub log: PathBuf,
+}
+
+/// An operation [`run`] carries out. Where [`Command`] only names what a front-end
+/// offers, this also holds what the operation needs besides the filename.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Operation {
+    /// [`backup_file`].
+    Backup,
+    /// [`backup_stdin`] of this process's standard input.
+    BackupStdin,
+    /// [`restore_file`].
+    Restore,
+    /// [`delete_file_with`] using the pattern given.
+    Delete(OverwritePattern),
+}
+
+impl From<Command> for Operation {
+    /// [`Command::Delete`] overwrites with zeros, as [`delete_file`] does.
+    fn from(command: Command) -> Self {
+        match command {
+            Command::Backup => Operation::Backup,
+            Command::Restore => Operation::Restore,
+            Command::Delete => Operation::Delete(OverwritePattern::Zeros),
+        }
+    }
+}
+
+/// What [`run`] did: the file it wrote or removed, and the bytes copied or overwritten.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OperationResult {
+    pub operation: Operation,
+    /// The backup for [`Operation::Backup`] and [`Operation::BackupStdin`], the restored
+    /// file for [`Operation::Restore`], and the removed file for [`Operation::Delete`].
+    pub path: PathBuf,
+    pub bytes: u64,
+}
+
+/// Carry out `op` on `filename` as the function each [`Operation`] names would, so a
+/// front-end needs one call for every operation it offers.
+pub fn run(op: Operation, filename: &str) -> Result<OperationResult> {
+    run_in(&BackupConfig::from_env()?, op, filename)
+}
+
+/// Like [`run`], but resolves `filename` against `cfg.base_dir`.
+pub fn run_in(cfg: &BackupConfig, op: Operation, filename: &str) -> Result<OperationResult> {
+    let (path, bytes) = match op {
+        Operation::Backup => {
+            let report = backup_file_reported_in(cfg, filename)?;
+            (report.path, report.bytes)
+        }
+        Operation::BackupStdin => {
+            let path = backup_stdin_in(cfg, filename, &mut io::stdin().lock())?;
+            let bytes = fs::metadata(&path).path_context("metadata", &path)?.len();
+            (path, bytes)
+        }
+        Operation::Restore => {
+            let report = restore_file_reported_in(cfg, filename)?;
+            (report.path, report.bytes)
+        }
+        Operation::Delete(pattern) => {
+            let report = delete_file_reported_in(cfg, filename, pattern)?;
+            (cfg.resolve(&report.filename), report.bytes_overwritten)
+        }
+    };
+    Ok(OperationResult {
+        operation: op,
+        path,
+        bytes,
+    })
+}
+
+/// The canonical absolute paths `command` would touch for `filename`, after the same checks
+/// the `*_dry` functions run, e.g. to show them in a confirmation prompt. Nothing is written.
+pub fn resolve_paths(filename: &str, command: Command) -> Result<ResolvedPaths> {
+    resolve_paths_in(&BackupConfig::from_env()?, filename, command)
+}
+
+/// Like [`resolve_paths`], but resolves `filename` against `cfg.base_dir`.
+pub fn resolve_paths_in(
+    cfg: &BackupConfig,
+    filename: &str,
+    command: Command,
+) -> Result<ResolvedPaths> {
+    let plan = match command {
+        Command::Backup => backup_file_dry_in(cfg, filename)?,
+        Command::Restore => restore_file_dry_in(cfg, filename)?,
+        Command::Delete => delete_file_dry_in(cfg, filename)?,
+    };
+    let destination = plan.destination.as_deref().map(canonical).transpose()?;
+    Ok(ResolvedPaths {
+        source: canonical(&plan.source)?,
+        temp: destination.as_deref().map(temp_path),
+        destination,
+        log: canonical(&cfg.log_path())?,
+    })
+}
+
+/// `path` canonicalized as far as it exists, with the components not created yet appended.
+fn canonical(path: &Path) -> Result<PathBuf> {
+    let mut missing = Vec::new();
+    let mut existing = path;
+    loop {
+        match existing.canonicalize() {
+            Ok(resolved) => {
+                return Ok(missing.iter().rev().fold(resolved, |p, c| p.join(c)));
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                match (existing.parent(), existing.file_name()) {
+                    (Some(parent), Some(name)) => {
+                        missing.push(name);
+                        existing = parent;
+                    }
+                    _ => return Err(e).path_context("canonicalize", path),
+                }
+            }
+            Err(e) => return Err(e).path_context("canonicalize", path),
+        }
+    }
+}
@@ -1,6 +1,58 @@
 use std::io::{self, Write};
+use std::process::ExitCode;
 
-use safe_backup::{backup_file, delete_file, restore_file};
+use clap::{Parser, Subcommand, ValueEnum};
+use safe_backup::{
+    run_in, BackupConfig, BackupError, Operation, OperationResult, OverwritePattern,
+};
+
+/// Secure backup, restore, and delete for text-like files (.txt, .log, .md).
+/// Run without arguments for interactive prompts.
+#[derive(Parser)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Print one JSON object describing the result (or error) to stdout
+    #[arg(long, global = true)]
+    json: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create <file>.bak
+    Backup { file: String },
+    /// Create <file>.bak from standard input; <file> itself need not exist
+    BackupStdin { file: String },
+    /// Restore <file> from <file>.bak
+    Restore { file: String },
+    /// Overwrite <file> and remove it
+    Delete {
+        file: String,
+        #[arg(long, value_enum, default_value_t = Pattern::Zeros)]
+        pattern: Pattern,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Pattern {
+    Zeros,
+    Ones,
+    Random,
+    Dod,
+    Gutmann,
+}
+
+impl From<Pattern> for OverwritePattern {
+    fn from(p: Pattern) -> Self {
+        match p {
+            Pattern::Zeros => OverwritePattern::Zeros,
+            Pattern::Ones => OverwritePattern::Ones,
+            Pattern::Random => OverwritePattern::Random,
+            Pattern::Dod => OverwritePattern::DoD5220,
+            Pattern::Gutmann => OverwritePattern::Gutmann,
+        }
+    }
+}
 
 fn read_line(prompt: &str) -> io::Result<String> {
     print!("{}", prompt);
@@ -10,7 +62,8 @@ fn read_line(prompt: &str) -> io::Result<String> {
     Ok(buf.trim().to_string())
 }
 
-fn main() {
+/// Ask for the filename and command the way the tool always has.
+fn prompt_command() -> Option<Command> {
     println!("== SafeBackup (Rust) ==");
     println!("Supported commands: backup, restore, delete");
     println!("Only text-like files are allowed: .txt, .log, .md");
@@ -20,52 +73,129 @@ fn main() {
         Ok(s) => s,
         Err(e) => {
             eprintln!("Failed to read filename: {}", e);
-            return;
+            return None;
         }
     };
     let command = match read_line("Please enter your command (backup, restore, delete): ") {
-        Ok(s) => s.to_lowercase(),
+        Ok(s) => s,
         Err(e) => {
             eprintln!("Failed to read command: {}", e);
-            return;
+            return None;
         }
     };
 
-    let result = match command.as_str() {
-        "backup" => {
-            match backup_file(&filename) {
-                Ok(path) => {
-                    println!("Your backup created: {}", path.display());
-                    Ok(())
-                }
-                Err(e) => Err(e),
-            }
+    match command.parse() {
+        Ok(safe_backup::Command::Backup) => Some(Command::Backup { file: filename }),
+        Ok(safe_backup::Command::Restore) => Some(Command::Restore { file: filename }),
+        Ok(safe_backup::Command::Delete) => Some(Command::Delete {
+            file: filename,
+            pattern: Pattern::Zeros,
+        }),
+        Err(e) => {
+            eprintln!("{}", e);
+            None
         }
-        "restore" => {
-            match restore_file(&filename) {
-                Ok(path) => {
-                    println!("Your file restored from backup to: {}", path.display());
-                    Ok(())
-                }
-                Err(e) => Err(e),
-            }
+    }
+}
+
+impl Command {
+    fn name(&self) -> &'static str {
+        match self {
+            Command::Backup { .. } => "backup",
+            Command::BackupStdin { .. } => "backup-stdin",
+            Command::Restore { .. } => "restore",
+            Command::Delete { .. } => "delete",
         }
-        "delete" => {
-            match delete_file(&filename) {
-                Ok(()) => {
-                    println!("File securely deleted.");
-                    Ok(())
-                }
-                Err(e) => Err(e),
-            }
+    }
+}
+
+fn run(cfg: &BackupConfig, command: Command) -> Result<OperationResult, BackupError> {
+    match command {
+        Command::Backup { file } => run_in(cfg, Operation::Backup, &file),
+        Command::BackupStdin { file } => run_in(cfg, Operation::BackupStdin, &file),
+        Command::Restore { file } => run_in(cfg, Operation::Restore, &file),
+        Command::Delete { file, pattern } => run_in(cfg, Operation::Delete(pattern.into()), &file),
+    }
+}
+
+/// The sentence the tool has always printed for a successful command.
+fn print_human(command: &str, outcome: &OperationResult) {
+    match command {
+        "backup" | "backup-stdin" => println!("Your backup created: {}", outcome.path.display()),
+        "restore" => println!(
+            "Your file restored from backup to: {}",
+            outcome.path.display()
+        ),
+        _ => println!("File securely deleted."),
+    }
+}
+
+fn print_json(value: serde_json::Value) {
+    println!("{}", value);
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let json = cli.json;
+
+    // Catch a bad environment before prompting, rather than on the first operation.
+    let cfg = match BackupConfig::from_env() {
+        Ok(cfg) => cfg,
+        Err(err) if json => {
+            print_json(serde_json::json!({
+                "operation": null,
+                "status": "error",
+                "error": format!("configuration error: {}", err),
+            }));
+            return ExitCode::from(2);
         }
-        _ => {
-            eprintln!("Unknown command");
-            return;
+        Err(err) => {
+            eprintln!("Configuration error: {}", err);
+            return ExitCode::from(2);
         }
     };
 
-    if let Err(err) = result {
-        eprintln!("Operation failed: {}", err);
+    let command = match cli.command {
+        Some(command) => command,
+        None => match prompt_command() {
+            Some(command) => command,
+            None => return ExitCode::from(2),
+        },
+    };
+
+    let operation = command.name();
+    match run(&cfg, command) {
+        Ok(outcome) => {
+            if json {
+                print_json(serde_json::json!({
+                    "operation": operation,
+                    "status": "ok",
+                    "path": outcome.path.to_string_lossy(),
+                    "bytes": outcome.bytes,
+                }));
+            } else {
+                print_human(operation, &outcome);
+            }
+            ExitCode::SUCCESS
+        }
+        Err(err) if json => {
+            print_json(serde_json::json!({
+                "operation": operation,
+                "status": "error",
+                "error": err.to_string(),
+            }));
+            ExitCode::FAILURE
+        }
+        Err(BackupError::PermissionDenied { path, .. }) => {
+            eprintln!(
+                "Operation failed: permission denied; check permissions on {}",
+                path.display()
+            );
+            ExitCode::FAILURE
+        }
+        Err(err) => {
+            eprintln!("Operation failed: {}", err);
+            ExitCode::FAILURE
+        }
     }
 }
@@ -1,34 +1,58 @@
-use std::fs;
-use std::io::Write;
-use std::path::Path;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 
-use safe_backup::{backup_file, delete_file, restore_file};
+use safe_backup::{
+    append_log_checkpoint_in, backup_dir_in, backup_file_compressed_in, backup_file_dry_in,
+    backup_file_expiring_in, backup_file_in, backup_file_note_in, backup_file_opts_in,
+    backup_file_reported_in, backup_file_resumable_in, backup_file_verified_in,
+    backup_file_versioned_in, backup_file_with_progress_in, backup_if_changed_in, backup_in,
+    backup_many, backup_many_in, backup_status_in, backup_stdin_in, backup_to_writer_in,
+    begin_edit_in, clean_orphaned_temps_in, confirm, delete_backup_in, delete_file_confirmed_in,
+    delete_file_dry_in, delete_file_in, delete_file_note_in, delete_file_reported_in,
+    delete_file_verified_in, delete_file_with_in, delete_in, delete_many_in, diff_backup_in,
+    diff_summary_in, empty_trash_in, estimate_backup_space_in, expire_backups_in,
+    find_orphaned_temps_in, free_space_in, import_and_backup_in, is_secure_delete_effective_in,
+    is_within_base, list_backups_in, log_digest_in, metrics_snapshot, prepare_in, prune_backups_in,
+    repair_backup_in, resolve_paths_in, restore_file_append_in, restore_file_as_in,
+    restore_file_confirmed_in, restore_file_dry_in, restore_file_in, restore_file_note_in,
+    restore_file_opts_in, restore_file_reported_in, restore_file_verified_in,
+    restore_from_reader_in, restore_from_trash_in, restore_in, restore_latest_in, restore_many_in,
+    restore_version_in, rotate_file_in, run_in, sanitize_filename, sanitize_filename_typed,
+    sanitize_filename_unicode, sanitize_filename_with, sanitize_relative_path,
+    total_backup_size_in, trash_file_in, undo_last_in, verify_all_in, verify_backup_in,
+    verify_manifest_in, wipe_free_space_in, AllowedExt, BackupCheck, BackupConfig, BackupError,
+    BackupOptions, Collision, Command, DiffSummary, LockMode, LogBatch, LogFormat, Metrics,
+    Operation, OverwritePattern, RestoreOptions, Sanitizer, VerifyStatus, DEFAULT_ALLOWED_EXTS,
+    DEFAULT_BACKUP_SUFFIX, DEFAULT_COPY_BUFFER_SIZE, DEFAULT_MAX_NAME_LEN,
+    DEFAULT_WIPE_BUFFER_SIZE, MANIFEST_NAME, MAX_NOTE_LEN, MAX_SUFFIX_LEN, TRASH_DIR,
+};
 use tempfile::tempdir;
 
 #[test]
 fn test_backup_valid() {
     let dir = tempdir().unwrap();
-    std::env::set_current_dir(dir.path()).unwrap();
+    let cfg = BackupConfig::new(dir.path());
 
     // create sample.txt
-    let mut f = fs::File::create("sample.txt").unwrap();
+    let mut f = fs::File::create(dir.path().join("sample.txt")).unwrap();
     writeln!(f, "hello").unwrap();
 
-    let bak = backup_file("sample.txt").expect("backup should succeed");
+    let bak = backup_file_in(&cfg, "sample.txt").expect("backup should succeed");
     assert!(bak.exists());
 
-    let content_src = fs::read_to_string("sample.txt").unwrap();
-    let content_bak = fs::read_to_string("sample.txt.bak").unwrap();
+    let content_src = fs::read_to_string(dir.path().join("sample.txt")).unwrap();
+    let content_bak = fs::read_to_string(dir.path().join("sample.txt.bak")).unwrap();
     assert_eq!(content_src, content_bak);
 }
 
 #[test]
 fn test_backup_traversal_blocked() {
     let dir = tempdir().unwrap();
-    std::env::set_current_dir(dir.path()).unwrap();
+    let cfg = BackupConfig::new(dir.path());
 
     // malicious path must be rejected
-    let err = backup_file("../../etc/passwd").unwrap_err();
+    let err = backup_file_in(&cfg, "../../etc/passwd").unwrap_err();
     let msg = err.to_string();
     assert!(msg.contains("path separators") || msg.contains("traversal"));
 }
@@ -36,48 +60,2804 @@ fn test_backup_traversal_blocked() {
 #[test]
 fn test_restore_valid() {
     let dir = tempdir().unwrap();
-    std::env::set_current_dir(dir.path()).unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    let path = dir.path().join("data.txt");
 
     // create sample.txt and a backup
     {
-        let mut f = fs::File::create("data.txt").unwrap();
+        let mut f = fs::File::create(&path).unwrap();
         write!(f, "original").unwrap();
     }
     // backup
-    backup_file("data.txt").unwrap();
+    backup_file_in(&cfg, "data.txt").unwrap();
 
     // modify the original
     {
-        let mut f = fs::File::create("data.txt").unwrap();
+        let mut f = fs::File::create(&path).unwrap();
         write!(f, "modified").unwrap();
     }
 
     // restore
-    restore_file("data.txt").unwrap();
-    let content = fs::read_to_string("data.txt").unwrap();
-    assert_eq!(content, "original\n");
+    restore_file_in(&cfg, "data.txt").unwrap();
+    let content = fs::read_to_string(&path).unwrap();
+    assert_eq!(content, "original");
 }
 
 #[test]
 fn test_delete_valid() {
     let dir = tempdir().unwrap();
-    std::env::set_current_dir(dir.path()).unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    let path = dir.path().join("remove_me.txt");
 
     {
-        let mut f = fs::File::create("remove_me.txt").unwrap();
+        let mut f = fs::File::create(&path).unwrap();
         write!(f, "secret").unwrap();
     }
 
-    delete_file("remove_me.txt").unwrap();
-    assert!(!Path::new("remove_me.txt").exists());
+    delete_file_in(&cfg, "remove_me.txt").unwrap();
+    assert!(!path.exists());
 }
 
 #[test]
 fn test_restore_traversal_blocked() {
     let dir = tempdir().unwrap();
-    std::env::set_current_dir(dir.path()).unwrap();
+    let cfg = BackupConfig::new(dir.path());
 
-    let err = restore_file("../foo.txt").unwrap_err();
+    let err = restore_file_in(&cfg, "../foo.txt").unwrap_err();
     let msg = err.to_string();
     assert!(msg.contains("path separators") || msg.contains("traversal"));
 }
+
+#[test]
+fn test_config_base_dir_roundtrip() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+
+    fs::write(dir.path().join("notes.txt"), "v1").unwrap();
+    let bak = backup_file_in(&cfg, "notes.txt").unwrap();
+    assert_eq!(bak, dir.path().join("notes.txt.bak"));
+
+    fs::write(dir.path().join("notes.txt"), "v2").unwrap();
+    restore_file_in(&cfg, "notes.txt").unwrap();
+    assert_eq!(
+        fs::read_to_string(dir.path().join("notes.txt")).unwrap(),
+        "v1"
+    );
+
+    delete_file_in(&cfg, "notes.txt").unwrap();
+    assert!(!dir.path().join("notes.txt").exists());
+    assert!(dir.path().join("logfile.txt").exists());
+}
+
+#[test]
+fn test_backup_versioned_no_collision() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    fs::write(dir.path().join("log.txt"), "entry").unwrap();
+
+    let first = backup_file_versioned_in(&cfg, "log.txt").unwrap();
+    let second = backup_file_versioned_in(&cfg, "log.txt").unwrap();
+    assert_ne!(first, second);
+    for bak in [&first, &second] {
+        let name = bak.file_name().unwrap().to_str().unwrap();
+        assert!(name.starts_with("log.txt.") && name.ends_with(".bak"));
+        assert_eq!(fs::read_to_string(bak).unwrap(), "entry");
+    }
+}
+
+#[test]
+fn test_list_backups() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    fs::write(dir.path().join("notes.md"), "x").unwrap();
+    assert!(list_backups_in(&cfg, "notes.md").unwrap().is_empty());
+
+    let plain = backup_file_in(&cfg, "notes.md").unwrap();
+    let v1 = backup_file_versioned_in(&cfg, "notes.md").unwrap();
+    let v2 = backup_file_versioned_in(&cfg, "notes.md").unwrap();
+    // unrelated files must not be picked up
+    fs::write(dir.path().join("notes.md.junk.bak"), "x").unwrap();
+    fs::write(dir.path().join("other.md.bak"), "x").unwrap();
+
+    let listed = list_backups_in(&cfg, "notes.md").unwrap();
+    assert_eq!(listed.len(), 3);
+    assert!(listed.contains(&plain));
+    let pos = |p: &PathBuf| listed.iter().position(|x| x == p).unwrap();
+    assert!(pos(&v1) < pos(&v2));
+
+    assert!(list_backups_in(&cfg, "../notes.md").is_err());
+}
+
+#[test]
+fn test_reported_byte_counts() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    fs::write(dir.path().join("sized.txt"), vec![b'a'; 10_000]).unwrap();
+
+    let report = backup_file_reported_in(&cfg, "sized.txt").unwrap();
+    assert_eq!(report.bytes, 10_000);
+    assert_eq!(report.bytes, fs::metadata(&report.path).unwrap().len());
+
+    let report = restore_file_reported_in(&cfg, "sized.txt").unwrap();
+    assert_eq!(report.bytes, 10_000);
+    assert_eq!(report.path, dir.path().join("sized.txt"));
+}
+
+#[test]
+fn test_delete_with_patterns() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    let patterns = [
+        OverwritePattern::Zeros,
+        OverwritePattern::Ones,
+        OverwritePattern::Random,
+        OverwritePattern::DoD5220,
+        OverwritePattern::Gutmann,
+    ];
+    for (i, pattern) in patterns.into_iter().enumerate() {
+        let name = format!("wipe{}.txt", i);
+        fs::write(dir.path().join(&name), vec![b'x'; 20_000]).unwrap();
+        delete_file_with_in(&cfg, &name, pattern).unwrap();
+        assert!(!dir.path().join(&name).exists());
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_metadata_preserved_through_backup_and_restore() {
+    use std::os::unix::fs::PermissionsExt;
+    use std::time::{Duration, SystemTime};
+
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    let path = dir.path().join("script.txt");
+    fs::write(&path, "#!/bin/sh").unwrap();
+    let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+    fs::File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(mtime)
+        .unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o750)).unwrap();
+
+    let bak = backup_file_in(&cfg, "script.txt").unwrap();
+    let meta = fs::metadata(&bak).unwrap();
+    assert_eq!(meta.permissions().mode() & 0o777, 0o750);
+    assert_eq!(meta.modified().unwrap(), mtime);
+
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+    fs::write(&path, "changed").unwrap();
+    restore_file_in(&cfg, "script.txt").unwrap();
+    let meta = fs::metadata(&path).unwrap();
+    assert_eq!(meta.permissions().mode() & 0o777, 0o750);
+    assert_eq!(meta.modified().unwrap(), mtime);
+}
+
+#[test]
+fn test_errors_are_matchable() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+
+    assert!(matches!(
+        sanitize_filename(""),
+        Err(BackupError::EmptyFilename)
+    ));
+    assert!(matches!(
+        sanitize_filename("a/b.txt"),
+        Err(BackupError::PathSeparator)
+    ));
+    assert!(matches!(
+        sanitize_filename("a..txt"),
+        Err(BackupError::TraversalToken)
+    ));
+    assert!(matches!(
+        sanitize_filename("a b.txt"),
+        Err(BackupError::InvalidCharacter)
+    ));
+    for name in ["foo\0.txt", "bar\n.txt", "tab\t.txt", "del\x7f.txt"] {
+        assert!(matches!(
+            sanitize_filename(name),
+            Err(BackupError::ControlCharacter)
+        ));
+    }
+    assert!(matches!(
+        sanitize_filename("a.exe"),
+        Err(BackupError::DisallowedExtension { .. })
+    ));
+    assert!(matches!(
+        backup_file_in(&cfg, "missing.txt"),
+        Err(BackupError::SourceMissing)
+    ));
+
+    fs::write(dir.path().join("twice.txt"), "x").unwrap();
+    backup_file_in(&cfg, "twice.txt").unwrap();
+    assert!(matches!(
+        backup_file_in(&cfg, "twice.txt"),
+        Err(BackupError::BackupExists)
+    ));
+}
+
+#[test]
+fn test_backup_overwrite_replaces_content() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    let src = dir.path().join("conf.txt");
+    fs::write(&src, "old").unwrap();
+    backup_file_in(&cfg, "conf.txt").unwrap();
+
+    fs::write(&src, "new").unwrap();
+    // default options still refuse
+    assert!(matches!(
+        backup_file_opts_in(&cfg, "conf.txt", BackupOptions::default()),
+        Err(BackupError::BackupExists)
+    ));
+    let bak = backup_file_opts_in(&cfg, "conf.txt", BackupOptions { overwrite: true }).unwrap();
+    assert_eq!(fs::read_to_string(&bak).unwrap(), "new");
+    assert!(!dir.path().join("conf.txt.bak.tmp").exists());
+}
+
+#[test]
+fn test_backup_overwrite_failure_keeps_old_backup() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    let src = dir.path().join("conf.txt");
+    fs::write(&src, "good").unwrap();
+    backup_file_in(&cfg, "conf.txt").unwrap();
+
+    // A directory squatting on the temp path makes the copy fail before the rename.
+    fs::create_dir(dir.path().join("conf.txt.bak.tmp")).unwrap();
+    fs::write(&src, "bad").unwrap();
+    assert!(backup_file_opts_in(&cfg, "conf.txt", BackupOptions { overwrite: true }).is_err());
+    assert_eq!(
+        fs::read_to_string(dir.path().join("conf.txt.bak")).unwrap(),
+        "good"
+    );
+}
+
+#[test]
+fn test_backup_verified_and_verify() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    fs::write(dir.path().join("abc.txt"), "abc").unwrap();
+
+    let (bak, hex) = backup_file_verified_in(&cfg, "abc.txt").unwrap();
+    assert!(bak.exists());
+    assert_eq!(
+        hex,
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+    assert!(verify_backup_in(&cfg, "abc.txt", &hex).unwrap());
+
+    fs::write(&bak, "tampered").unwrap();
+    assert!(!verify_backup_in(&cfg, "abc.txt", &hex).unwrap());
+    assert!(matches!(
+        verify_backup_in(&cfg, "none.txt", &hex),
+        Err(BackupError::BackupMissing)
+    ));
+}
+
+#[test]
+fn test_custom_allowed_extensions() {
+    assert!(sanitize_filename("data.csv").is_err());
+    assert_eq!(
+        sanitize_filename_with("data.csv", &["csv", "json"]).unwrap(),
+        "data.csv"
+    );
+    assert!(sanitize_filename_with("notes.txt", &["csv"]).is_err());
+    // other rules are unchanged
+    assert!(sanitize_filename_with("../data.csv", &["csv"]).is_err());
+
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    fs::write(dir.path().join("data.json"), "{}").unwrap();
+    assert!(backup_file_in(&cfg, "data.json").is_err());
+    cfg.allowed_exts.push("json".to_string());
+    assert!(backup_file_in(&cfg, "data.json").is_ok());
+}
+
+#[test]
+fn test_restore_file_as() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    fs::write(dir.path().join("live.txt"), "old").unwrap();
+    backup_file_in(&cfg, "live.txt").unwrap();
+    fs::write(dir.path().join("live.txt"), "new").unwrap();
+
+    let preview = restore_file_as_in(&cfg, "live.txt", "preview.txt", false).unwrap();
+    assert_eq!(fs::read_to_string(&preview).unwrap(), "old");
+    assert_eq!(
+        fs::read_to_string(dir.path().join("live.txt")).unwrap(),
+        "new"
+    );
+
+    assert!(matches!(
+        restore_file_as_in(&cfg, "live.txt", "preview.txt", false),
+        Err(BackupError::DestinationExists)
+    ));
+    restore_file_as_in(&cfg, "live.txt", "preview.txt", true).unwrap();
+    assert!(restore_file_as_in(&cfg, "live.txt", "../out.txt", true).is_err());
+}
+
+#[test]
+fn test_dry_run_touches_nothing() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    fs::write(dir.path().join("plan.txt"), "x").unwrap();
+
+    let plan = backup_file_dry_in(&cfg, "plan.txt").unwrap();
+    assert_eq!(plan.source, dir.path().join("plan.txt"));
+    assert_eq!(plan.destination, Some(dir.path().join("plan.txt.bak")));
+    assert!(!dir.path().join("plan.txt.bak").exists());
+    assert!(matches!(
+        restore_file_dry_in(&cfg, "plan.txt"),
+        Err(BackupError::BackupMissing)
+    ));
+
+    backup_file_in(&cfg, "plan.txt").unwrap();
+    let log_len = fs::metadata(dir.path().join("logfile.txt")).unwrap().len();
+    assert!(matches!(
+        backup_file_dry_in(&cfg, "plan.txt"),
+        Err(BackupError::BackupExists)
+    ));
+    assert!(restore_file_dry_in(&cfg, "plan.txt").unwrap().overwrites);
+
+    let plan = delete_file_dry_in(&cfg, "plan.txt").unwrap();
+    assert_eq!(plan.destination, None);
+    assert!(dir.path().join("plan.txt").exists());
+    assert_eq!(
+        fs::metadata(dir.path().join("logfile.txt")).unwrap().len(),
+        log_len
+    );
+}
+
+#[test]
+fn test_json_log_format() {
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    cfg.log_format = LogFormat::Json;
+    fs::write(dir.path().join("j.txt"), "x").unwrap();
+    backup_file_in(&cfg, "j.txt").unwrap();
+    delete_file_in(&cfg, "j.txt").unwrap();
+
+    let log = fs::read_to_string(dir.path().join("logfile.txt")).unwrap();
+    let lines: Vec<&str> = log.lines().collect();
+    assert_eq!(lines.len(), 2);
+    for (line, op) in lines.iter().zip(["backup", "delete"]) {
+        let entry: serde_json::Value = serde_json::from_str(line).unwrap();
+        assert_eq!(entry["operation"], op);
+        assert_eq!(entry["filename"], "j.txt");
+        assert_eq!(entry["level"], "INFO");
+        assert!(entry["timestamp"].is_string());
+        assert!(entry["message"].is_string());
+    }
+}
+
+#[test]
+fn test_custom_log_path() {
+    let dir = tempdir().unwrap();
+    let logs = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    assert_eq!(cfg.log_path(), dir.path().join("logfile.txt"));
+
+    assert!(matches!(
+        cfg.set_log_path(logs.path().join("missing").join("ops.log")),
+        Err(BackupError::InvalidConfig(_))
+    ));
+    cfg.set_log_path(logs.path().join("ops.log")).unwrap();
+
+    fs::write(dir.path().join("l.txt"), "x").unwrap();
+    backup_file_in(&cfg, "l.txt").unwrap();
+    assert!(!dir.path().join("logfile.txt").exists());
+    let log = fs::read_to_string(logs.path().join("ops.log")).unwrap();
+    assert!(log.contains("Backup created for l.txt"));
+}
+
+#[test]
+fn test_log_rotation() {
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    cfg.log_max_bytes = Some(120);
+    cfg.log_keep = 2;
+
+    for i in 0..8 {
+        let name = format!("r{}.txt", i);
+        fs::write(dir.path().join(&name), "x").unwrap();
+        backup_file_in(&cfg, &name).unwrap();
+    }
+    let current = dir.path().join("logfile.txt");
+    assert!(fs::metadata(&current).unwrap().len() <= 120);
+    assert!(dir.path().join("logfile.1.txt").exists());
+    assert!(dir.path().join("logfile.2.txt").exists());
+    assert!(!dir.path().join("logfile.3.txt").exists());
+    // the newest line is always in the live file
+    assert!(fs::read_to_string(&current).unwrap().contains("r7.txt"));
+}
+
+#[test]
+fn test_backup_dir_mirrors_allowed_files() {
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    cfg.use_own_metrics();
+    let notes = dir.path().join("notes");
+    fs::create_dir_all(notes.join("sub")).unwrap();
+    fs::write(notes.join("a.md"), "a").unwrap();
+    fs::write(notes.join("sub").join("b.txt"), "b").unwrap();
+    fs::write(notes.join("skip.exe"), "bin").unwrap();
+
+    let mirror = backup_dir_in(&cfg, "notes").unwrap();
+    assert_eq!(mirror, dir.path().join("notes.bak"));
+    assert_eq!(fs::read_to_string(mirror.join("a.md")).unwrap(), "a");
+    assert_eq!(
+        fs::read_to_string(mirror.join("sub").join("b.txt")).unwrap(),
+        "b"
+    );
+    assert!(!mirror.join("skip.exe").exists());
+    let log = fs::read_to_string(dir.path().join("logfile.txt")).unwrap();
+    assert!(log.contains("WARN") && log.contains("skip.exe"));
+
+    assert!(matches!(
+        backup_dir_in(&cfg, "notes"),
+        Err(BackupError::BackupExists)
+    ));
+    let metrics = cfg.metrics();
+    assert_eq!((metrics.backups, metrics.backup_failures), (1, 1));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_backup_dir_does_not_follow_symlinks() {
+    let dir = tempdir().unwrap();
+    let outside = tempdir().unwrap();
+    fs::write(outside.path().join("secret.txt"), "secret").unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    let notes = dir.path().join("notes");
+    fs::create_dir(&notes).unwrap();
+    std::os::unix::fs::symlink(outside.path().join("secret.txt"), notes.join("link.txt")).unwrap();
+    std::os::unix::fs::symlink(outside.path(), notes.join("escape")).unwrap();
+
+    let mirror = backup_dir_in(&cfg, "notes").unwrap();
+    assert!(!mirror.join("link.txt").exists());
+    assert!(!mirror.join("escape").exists());
+}
+
+#[test]
+fn test_prune_backups_keeps_newest() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    fs::write(dir.path().join("p.txt"), "x").unwrap();
+    backup_file_in(&cfg, "p.txt").unwrap();
+    let versions: Vec<PathBuf> = (0..4)
+        .map(|_| backup_file_versioned_in(&cfg, "p.txt").unwrap())
+        .collect();
+
+    assert!(matches!(
+        prune_backups_in(&cfg, "p.txt", 0),
+        Err(BackupError::InvalidArgument(_))
+    ));
+    assert!(prune_backups_in(&cfg, "p.txt", 10).unwrap().is_empty());
+
+    let removed = prune_backups_in(&cfg, "p.txt", 1).unwrap();
+    assert_eq!(removed, versions[..3].to_vec());
+    assert!(removed.iter().all(|p| !p.exists()));
+    assert!(versions[3].exists());
+    assert!(dir.path().join("p.txt.bak").exists());
+}
+
+#[test]
+fn test_backup_progress_callback() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    fs::write(dir.path().join("big.log"), vec![b'z'; 20_000]).unwrap();
+
+    let mut calls = Vec::new();
+    let bak =
+        backup_file_with_progress_in(&cfg, "big.log", |done, total| calls.push((done, total)))
+            .unwrap();
+    assert_eq!(fs::metadata(bak).unwrap().len(), 20_000);
+    assert!(calls.len() >= 3);
+    assert!(calls.iter().all(|&(_, total)| total == 20_000));
+    assert!(calls.windows(2).all(|w| w[0].0 < w[1].0));
+    assert_eq!(calls.last().unwrap().0, 20_000);
+}
+
+#[test]
+fn test_wipe_buffer_size_is_clamped() {
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    for size in [0, 1000, usize::MAX] {
+        cfg.wipe_buffer_size = size;
+        fs::write(dir.path().join("w.txt"), vec![b'q'; 70_000]).unwrap();
+        delete_file_in(&cfg, "w.txt").unwrap();
+        assert!(!dir.path().join("w.txt").exists());
+    }
+}
+
+// Timing-sensitive, so not part of the default run: `cargo test -- --ignored`.
+#[test]
+#[ignore]
+fn bench_wipe_buffer_throughput() {
+    use std::time::Instant;
+
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    let mut timed = |size: usize| {
+        cfg.wipe_buffer_size = size;
+        fs::write(dir.path().join("large.txt"), vec![b'q'; 64 * 1024 * 1024]).unwrap();
+        let start = Instant::now();
+        delete_file_in(&cfg, "large.txt").unwrap();
+        start.elapsed()
+    };
+    let small = timed(512);
+    let default = timed(DEFAULT_WIPE_BUFFER_SIZE);
+    println!("512 B chunks: {:?}, default chunks: {:?}", small, default);
+    assert!(default < small);
+}
+
+// Timing-sensitive, so not part of the default run: `cargo test -- --ignored`.
+// No assertion: where the kernel copies file to file, buffer size barely matters.
+#[test]
+#[ignore]
+fn bench_copy_buffer_throughput() {
+    use std::time::Instant;
+
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    fs::write(dir.path().join("large.txt"), vec![b'q'; 256 * 1024 * 1024]).unwrap();
+    let mut timed = |size: usize| {
+        cfg.copy_buffer_size = size;
+        let start = Instant::now();
+        backup_file_opts_in(&cfg, "large.txt", BackupOptions { overwrite: true }).unwrap();
+        restore_file_in(&cfg, "large.txt").unwrap();
+        start.elapsed()
+    };
+    let small = timed(8192);
+    let default = timed(DEFAULT_COPY_BUFFER_SIZE);
+    println!("8 KiB buffers: {:?}, default buffers: {:?}", small, default);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_symlink_source_rejected() {
+    let dir = tempdir().unwrap();
+    let outside = tempdir().unwrap();
+    fs::write(outside.path().join("shadow.txt"), "root:x").unwrap();
+    std::os::unix::fs::symlink(
+        outside.path().join("shadow.txt"),
+        dir.path().join("notes.txt"),
+    )
+    .unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+
+    let err = backup_file_in(&cfg, "notes.txt").unwrap_err();
+    assert!(matches!(err, BackupError::Symlink));
+    assert!(err.to_string().contains("symbolic link"));
+    assert!(matches!(
+        delete_file_in(&cfg, "notes.txt"),
+        Err(BackupError::Symlink)
+    ));
+    assert!(outside.path().join("shadow.txt").exists());
+
+    // opting in still refuses a target outside the base dir
+    cfg.follow_symlinks = true;
+    assert!(matches!(
+        backup_file_in(&cfg, "notes.txt"),
+        Err(BackupError::PathEscapesBase)
+    ));
+    fs::write(dir.path().join("real.txt"), "ok").unwrap();
+    std::os::unix::fs::symlink(dir.path().join("real.txt"), dir.path().join("alias.txt")).unwrap();
+    assert!(backup_file_in(&cfg, "alias.txt").is_ok());
+}
+
+#[test]
+fn test_batch_operations_continue_past_failures() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    fs::write(dir.path().join("one.txt"), "1").unwrap();
+    fs::write(dir.path().join("two.md"), "2").unwrap();
+
+    let names = ["one.txt", "bad.exe", "two.md", "missing.txt"];
+    let results = backup_many_in(&cfg, &names);
+    let got: Vec<&str> = results.iter().map(|(n, _)| n.as_str()).collect();
+    assert_eq!(got, names);
+    let ok: Vec<bool> = results.iter().map(|(_, r)| r.is_ok()).collect();
+    assert_eq!(ok, [true, false, true, false]);
+
+    let restored = restore_many_in(&cfg, &["two.md", "bad.exe"]);
+    assert!(restored[0].1.is_ok() && restored[1].1.is_err());
+
+    let deleted = delete_many_in(&cfg, &["missing.txt", "one.txt"]);
+    assert!(deleted[0].1.is_err() && deleted[1].1.is_ok());
+    assert!(!dir.path().join("one.txt").exists());
+
+    let log = fs::read_to_string(dir.path().join("logfile.txt")).unwrap();
+    assert!(log.contains("ERROR: Backup failed for bad.exe"));
+    assert!(log.contains("Backup created for two.md"));
+}
+
+#[test]
+fn test_delete_verified() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    for pattern in [OverwritePattern::Zeros, OverwritePattern::DoD5220] {
+        fs::write(dir.path().join("audit.txt"), vec![b'k'; 100_000]).unwrap();
+        delete_file_verified_in(&cfg, "audit.txt", pattern).unwrap();
+        assert!(!dir.path().join("audit.txt").exists());
+    }
+    let log = fs::read_to_string(dir.path().join("logfile.txt")).unwrap();
+    assert!(log.contains("Wipe verified for audit.txt (100000 bytes)"));
+}
+
+#[test]
+fn test_delete_report() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    fs::write(dir.path().join("cert.txt"), vec![b'c'; 12_345]).unwrap();
+
+    let report = delete_file_reported_in(&cfg, "cert.txt", OverwritePattern::DoD5220).unwrap();
+    assert_eq!(report.filename, "cert.txt");
+    assert_eq!(report.bytes_overwritten, 12_345);
+    assert_eq!(report.passes, 3);
+    assert_eq!(OverwritePattern::Zeros.pass_count(), 1);
+    assert_eq!(OverwritePattern::Gutmann.pass_count(), 35);
+}
+
+#[test]
+fn test_compressed_backup_roundtrip() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    let data = "2024-01-01 INFO service started\n".repeat(2_000);
+    fs::write(dir.path().join("app.log"), &data).unwrap();
+
+    let gz = backup_file_compressed_in(&cfg, "app.log").unwrap();
+    assert_eq!(gz, dir.path().join("app.log.bak.gz"));
+    assert!(fs::metadata(&gz).unwrap().len() < data.len() as u64 / 10);
+    assert!(!dir.path().join("app.log.bak").exists());
+    assert!(matches!(
+        backup_file_compressed_in(&cfg, "app.log"),
+        Err(BackupError::BackupExists)
+    ));
+
+    fs::write(dir.path().join("app.log"), "truncated").unwrap();
+    let report = restore_file_reported_in(&cfg, "app.log").unwrap();
+    assert_eq!(report.bytes, data.len() as u64);
+    assert_eq!(
+        fs::read_to_string(dir.path().join("app.log")).unwrap(),
+        data
+    );
+}
+
+#[test]
+fn test_restore_version_and_latest() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    let file = dir.path().join("cfg.txt");
+    assert!(matches!(
+        restore_latest_in(&cfg, "cfg.txt"),
+        Err(BackupError::BackupMissing)
+    ));
+
+    fs::write(&file, "v1").unwrap();
+    let first = backup_file_versioned_in(&cfg, "cfg.txt").unwrap();
+    fs::write(&file, "v2").unwrap();
+    backup_file_versioned_in(&cfg, "cfg.txt").unwrap();
+    fs::write(&file, "broken").unwrap();
+
+    let name = first.file_name().unwrap().to_str().unwrap();
+    let version = &name["cfg.txt.".len()..name.len() - ".bak".len()];
+    restore_version_in(&cfg, "cfg.txt", version).unwrap();
+    assert_eq!(fs::read_to_string(&file).unwrap(), "v1");
+
+    restore_latest_in(&cfg, "cfg.txt").unwrap();
+    assert_eq!(fs::read_to_string(&file).unwrap(), "v2");
+
+    assert!(matches!(
+        restore_version_in(&cfg, "cfg.txt", "../../etc"),
+        Err(BackupError::InvalidArgument(_))
+    ));
+    assert!(matches!(
+        restore_version_in(&cfg, "cfg.txt", "20000101T000000Z"),
+        Err(BackupError::BackupMissing)
+    ));
+}
+
+#[test]
+fn test_diff_backup() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    let file = dir.path().join("page.md");
+    fs::write(&file, vec![b'a'; 20_000]).unwrap();
+    assert!(matches!(
+        diff_backup_in(&cfg, "page.md"),
+        Err(BackupError::BackupMissing)
+    ));
+
+    backup_file_in(&cfg, "page.md").unwrap();
+    assert!(!diff_backup_in(&cfg, "page.md").unwrap());
+
+    let mut changed = vec![b'a'; 20_000];
+    changed[15_000] = b'b';
+    fs::write(&file, &changed).unwrap();
+    assert!(diff_backup_in(&cfg, "page.md").unwrap());
+
+    fs::write(&file, "short").unwrap();
+    let summary = diff_summary_in(&cfg, "page.md").unwrap();
+    assert_eq!(
+        summary,
+        DiffSummary {
+            identical: false,
+            src_len: 5,
+            bak_len: 20_000
+        }
+    );
+}
+
+#[test]
+fn test_max_file_size() {
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    fs::write(dir.path().join("big.log"), vec![b'x'; 2048]).unwrap();
+    backup_file_versioned_in(&cfg, "big.log").unwrap();
+
+    cfg.max_file_size = Some(1024);
+    assert!(matches!(
+        backup_file_in(&cfg, "big.log"),
+        Err(BackupError::FileTooLarge {
+            size: 2048,
+            max: 1024
+        })
+    ));
+    assert!(!dir.path().join("big.log.bak").exists());
+    assert!(matches!(
+        backup_file_compressed_in(&cfg, "big.log"),
+        Err(BackupError::FileTooLarge { .. })
+    ));
+
+    cfg.max_file_size = Some(2048);
+    backup_file_in(&cfg, "big.log").unwrap();
+}
+
+#[test]
+fn test_lock_fail_fast() {
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    cfg.lock_mode = LockMode::FailFast;
+    fs::write(dir.path().join("shared.txt"), "data").unwrap();
+
+    let held = File::create(dir.path().join("shared.txt.lock")).unwrap();
+    held.lock().unwrap();
+    assert!(matches!(
+        backup_file_in(&cfg, "shared.txt"),
+        Err(BackupError::Locked)
+    ));
+    assert!(matches!(
+        delete_file_in(&cfg, "shared.txt"),
+        Err(BackupError::Locked)
+    ));
+    assert!(matches!(
+        backup_file_versioned_in(&cfg, "shared.txt"),
+        Err(BackupError::Locked)
+    ));
+    assert!(matches!(
+        backup_file_compressed_in(&cfg, "shared.txt"),
+        Err(BackupError::Locked)
+    ));
+    assert!(matches!(
+        backup_file_with_progress_in(&cfg, "shared.txt", |_, _| {}),
+        Err(BackupError::Locked)
+    ));
+    assert!(matches!(
+        prune_backups_in(&cfg, "shared.txt", 1),
+        Err(BackupError::Locked)
+    ));
+    assert!(dir.path().join("shared.txt").exists());
+    drop(held);
+
+    backup_file_in(&cfg, "shared.txt").unwrap();
+    restore_file_in(&cfg, "shared.txt").unwrap();
+    delete_file_in(&cfg, "shared.txt").unwrap();
+    assert!(!dir.path().join("shared.txt.lock").exists());
+}
+
+#[test]
+fn test_failed_restore_leaves_no_temp() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    fs::write(dir.path().join("data.txt"), "live").unwrap();
+    // Not gzip data, so decompression fails halfway through the copy.
+    fs::write(dir.path().join("data.txt.bak.gz"), "not gzip at all").unwrap();
+
+    for _ in 0..2 {
+        assert!(matches!(
+            restore_file_in(&cfg, "data.txt"),
+            Err(BackupError::Io { .. })
+        ));
+        assert!(!dir.path().join("data.txt.tmp").exists());
+    }
+    assert_eq!(
+        fs::read_to_string(dir.path().join("data.txt")).unwrap(),
+        "live"
+    );
+}
+
+#[test]
+fn test_log_timestamp_format() {
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    assert!(matches!(
+        cfg.set_log_timestamp_format("%Y-%Q"),
+        Err(BackupError::InvalidConfig(_))
+    ));
+    assert_eq!(cfg.log_timestamp_format(), "%Y-%m-%d %H:%M:%S");
+
+    cfg.set_log_timestamp_format("%Y-%m-%dT%H:%M:%S%.3f%:z")
+        .unwrap();
+    cfg.log_local_time = true;
+    fs::write(dir.path().join("t.txt"), "x").unwrap();
+    backup_file_in(&cfg, "t.txt").unwrap();
+
+    let log = fs::read_to_string(dir.path().join("logfile.txt")).unwrap();
+    let stamp = &log[1..log.find(']').unwrap()];
+    // e.g. 2024-01-01T00:00:00.123+02:00
+    assert_eq!(stamp.len(), 29, "{}", stamp);
+    assert_eq!(&stamp[19..20], ".");
+    assert!(matches!(&stamp[23..24], "+" | "-"));
+}
+
+#[test]
+fn test_is_within_base() {
+    let dir = tempdir().unwrap();
+    fs::create_dir(dir.path().join("sub")).unwrap();
+    let base = dir.path();
+
+    assert!(is_within_base(base, Path::new("a.txt")).unwrap());
+    assert!(is_within_base(base, Path::new("sub/a.txt")).unwrap());
+    assert!(is_within_base(base, &base.join("sub/a.txt")).unwrap());
+    assert!(!is_within_base(base, Path::new("../a.txt")).unwrap());
+    assert!(!is_within_base(base, Path::new("sub/../../a.txt")).unwrap());
+    assert!(!is_within_base(base, Path::new("missing/../../a.txt")).unwrap());
+    assert!(!is_within_base(base, Path::new("/etc/passwd")).unwrap());
+}
+
+#[test]
+fn test_windows_reserved_names() {
+    let mut reserved = vec!["CON", "PRN", "AUX", "NUL"]
+        .into_iter()
+        .map(String::from)
+        .collect::<Vec<_>>();
+    for n in 1..=9 {
+        reserved.push(format!("COM{}", n));
+        reserved.push(format!("LPT{}", n));
+    }
+    for name in &reserved {
+        for candidate in [
+            format!("{}.txt", name),
+            format!("{}.log", name.to_lowercase()),
+        ] {
+            assert!(
+                matches!(
+                    sanitize_filename(&candidate),
+                    Err(BackupError::ReservedName { .. })
+                ),
+                "{} was accepted",
+                candidate
+            );
+        }
+    }
+    assert!(sanitize_filename("CON.tar.txt").is_err());
+    assert!(sanitize_filename("COM0.txt").is_ok());
+    assert!(sanitize_filename("console.txt").is_ok());
+    assert!(sanitize_filename("LPT10.txt").is_ok());
+
+    for name in ["notes.", "notes.txt.", "notes.txt "] {
+        assert!(matches!(
+            sanitize_filename(name),
+            Err(BackupError::TrailingDotOrSpace)
+        ));
+    }
+}
+
+#[test]
+fn test_restore_snapshot() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    let file = dir.path().join("page.txt");
+    let snap = dir.path().join("page.txt.pre-restore.bak");
+    fs::write(&file, "good").unwrap();
+    backup_file_in(&cfg, "page.txt").unwrap();
+
+    fs::write(&file, "edited").unwrap();
+    restore_file_in(&cfg, "page.txt").unwrap();
+    assert!(!snap.exists());
+
+    fs::write(&file, "edited again").unwrap();
+    let opts = RestoreOptions {
+        snapshot: true,
+        ..Default::default()
+    };
+    restore_file_opts_in(&cfg, "page.txt", opts).unwrap();
+    assert_eq!(fs::read_to_string(&file).unwrap(), "good");
+    assert_eq!(fs::read_to_string(&snap).unwrap(), "edited again");
+
+    fs::write(&file, "third").unwrap();
+    assert!(matches!(
+        restore_file_opts_in(&cfg, "page.txt", opts),
+        Err(BackupError::BackupExists)
+    ));
+    assert_eq!(fs::read_to_string(&file).unwrap(), "third");
+    assert_eq!(fs::read_to_string(&snap).unwrap(), "edited again");
+}
+
+#[test]
+fn test_backup_status() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    fs::write(dir.path().join("todo.md"), "- one").unwrap();
+    assert_eq!(backup_status_in(&cfg, "todo.md").unwrap(), None);
+    assert!(backup_status_in(&cfg, "../todo.md").is_err());
+
+    let bak = backup_file_in(&cfg, "todo.md").unwrap();
+    let info = backup_status_in(&cfg, "todo.md").unwrap().unwrap();
+    assert_eq!(info.path, bak);
+    assert_eq!(info.size, 5);
+    assert_eq!(
+        info.modified,
+        fs::metadata(&bak).unwrap().modified().unwrap()
+    );
+}
+
+#[test]
+fn test_separate_backup_dir() {
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    cfg.backup_dir = Some(PathBuf::from("backups"));
+    let file = dir.path().join("report.txt");
+    fs::write(&file, "q1").unwrap();
+    assert!(list_backups_in(&cfg, "report.txt").unwrap().is_empty());
+
+    let bak = backup_file_in(&cfg, "report.txt").unwrap();
+    assert_eq!(bak, dir.path().join("backups/report.txt.bak"));
+    assert!(!dir.path().join("report.txt.bak").exists());
+    let v = backup_file_versioned_in(&cfg, "report.txt").unwrap();
+    assert_eq!(v.parent().unwrap(), dir.path().join("backups"));
+    assert_eq!(list_backups_in(&cfg, "report.txt").unwrap().len(), 2);
+    assert!(backup_status_in(&cfg, "report.txt").unwrap().is_some());
+
+    fs::write(&file, "changed").unwrap();
+    restore_file_in(&cfg, "report.txt").unwrap();
+    assert_eq!(fs::read_to_string(&file).unwrap(), "q1");
+
+    cfg.backup_dir = Some(PathBuf::from("../outside"));
+    assert!(matches!(
+        backup_file_versioned_in(&cfg, "report.txt"),
+        Err(BackupError::PathEscapesBase)
+    ));
+    assert!(!dir.path().parent().unwrap().join("outside").exists());
+}
+
+#[test]
+fn test_log_hook() {
+    use std::sync::{Arc, Mutex};
+
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let sink = Arc::clone(&seen);
+    cfg.set_log_hook(move |level, msg| sink.lock().unwrap().push(format!("{} {}", level, msg)));
+    fs::write(dir.path().join("h.txt"), "x").unwrap();
+
+    backup_file_in(&cfg, "h.txt").unwrap();
+    let first = seen.lock().unwrap()[0].clone();
+    assert!(first.starts_with("INFO Backup created for h.txt (1 bytes in "));
+    assert!(dir.path().join("logfile.txt").exists());
+
+    fs::remove_file(dir.path().join("logfile.txt")).unwrap();
+    cfg.log_to_file = false;
+    delete_file_in(&cfg, "h.txt").unwrap();
+    assert_eq!(seen.lock().unwrap().len(), 2);
+    assert!(!dir.path().join("logfile.txt").exists());
+}
+
+#[test]
+fn test_unicode_filenames() {
+    assert!(sanitize_filename("résumé.txt").is_err());
+    assert_eq!(
+        sanitize_filename_unicode("résumé.txt").unwrap(),
+        "résumé.txt"
+    );
+
+    // Decomposed "e" + U+0301 and fullwidth letters normalize to the composed form.
+    let decomposed = "cafe\u{301}.txt";
+    assert_eq!(sanitize_filename_unicode(decomposed).unwrap(), "café.txt");
+    assert_eq!(
+        sanitize_filename_unicode("ｎｏｔｅｓ.txt").unwrap(),
+        "notes.txt"
+    );
+
+    assert!(matches!(
+        sanitize_filename_unicode("a\u{FF0F}b.txt"),
+        Err(BackupError::PathSeparator)
+    ));
+    assert!(matches!(
+        sanitize_filename_unicode("a\u{2025}txt"),
+        Err(BackupError::TraversalToken)
+    ));
+    assert!(matches!(
+        sanitize_filename_unicode("bad\u{85}.txt"),
+        Err(BackupError::ControlCharacter)
+    ));
+    assert!(matches!(
+        sanitize_filename_unicode("a☃.txt"),
+        Err(BackupError::InvalidCharacter)
+    ));
+    assert!(matches!(
+        sanitize_filename_unicode("été.exe"),
+        Err(BackupError::DisallowedExtension { .. })
+    ));
+
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    cfg.unicode_names = true;
+    fs::write(dir.path().join("café.txt"), "au lait").unwrap();
+    let bak = backup_file_in(&cfg, decomposed).unwrap();
+    assert_eq!(bak, dir.path().join("café.txt.bak"));
+}
+
+#[test]
+fn test_interrupted_backup_leaves_no_partial_bak() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    fs::write(dir.path().join("big.txt"), vec![b'z'; 100_000]).unwrap();
+
+    let interrupted = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        backup_file_with_progress_in(&cfg, "big.txt", |done, _| {
+            if done > 16_384 {
+                panic!("simulated crash mid-copy");
+            }
+        })
+    }));
+    assert!(interrupted.is_err());
+    assert!(!dir.path().join("big.txt.bak").exists());
+    assert!(!dir.path().join("big.txt.bak.tmp").exists());
+
+    let bak = backup_file_in(&cfg, "big.txt").unwrap();
+    assert_eq!(fs::read(bak).unwrap().len(), 100_000);
+}
+
+#[test]
+fn test_begin_edit_guard() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    let file = dir.path().join("draft.md");
+    fs::write(&file, "v1").unwrap();
+
+    {
+        let _guard = begin_edit_in(&cfg, "draft.md").unwrap();
+        fs::write(&file, "half-done").unwrap();
+    }
+    assert_eq!(fs::read_to_string(&file).unwrap(), "v1");
+
+    fs::remove_file(dir.path().join("draft.md.bak")).unwrap();
+    let guard = begin_edit_in(&cfg, "draft.md").unwrap();
+    assert_eq!(guard.backup_path(), dir.path().join("draft.md.bak"));
+    fs::write(&file, "v2").unwrap();
+    guard.commit();
+    assert_eq!(fs::read_to_string(&file).unwrap(), "v2");
+    assert!(dir.path().join("draft.md.bak").exists());
+
+    // A failed rollback in drop is logged, not a panic.
+    fs::remove_file(dir.path().join("draft.md.bak")).unwrap();
+    let guard = begin_edit_in(&cfg, "draft.md").unwrap();
+    fs::remove_file(dir.path().join("draft.md.bak")).unwrap();
+    drop(guard);
+    let log = fs::read_to_string(dir.path().join("logfile.txt")).unwrap();
+    assert!(log.contains("ERROR: Rollback failed for draft.md"));
+}
+
+#[test]
+fn test_subdirectory_paths() {
+    assert_eq!(
+        sanitize_relative_path("docs/notes.txt").unwrap(),
+        "docs/notes.txt"
+    );
+    assert!(matches!(
+        sanitize_relative_path("/etc/notes.txt"),
+        Err(BackupError::AbsolutePath)
+    ));
+    assert!(matches!(
+        sanitize_relative_path("docs//notes.txt"),
+        Err(BackupError::EmptyComponent)
+    ));
+    assert!(matches!(
+        sanitize_relative_path("docs/../../notes.txt"),
+        Err(BackupError::TraversalToken)
+    ));
+    assert!(matches!(
+        sanitize_relative_path("docs\\notes.txt"),
+        Err(BackupError::PathSeparator)
+    ));
+    assert!(sanitize_relative_path("docs.txt/notes.exe").is_err());
+    assert!(sanitize_filename("docs/notes.txt").is_err());
+
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    cfg.allow_subdirs = true;
+    fs::create_dir_all(dir.path().join("docs/2024")).unwrap();
+    let file = dir.path().join("docs/2024/notes.txt");
+    fs::write(&file, "nested").unwrap();
+
+    let bak = backup_file_in(&cfg, "docs/2024/notes.txt").unwrap();
+    assert_eq!(bak, dir.path().join("docs/2024/notes.txt.bak"));
+    backup_file_versioned_in(&cfg, "docs/2024/notes.txt").unwrap();
+    assert_eq!(
+        list_backups_in(&cfg, "docs/2024/notes.txt").unwrap().len(),
+        2
+    );
+
+    fs::write(&file, "changed").unwrap();
+    restore_file_in(&cfg, "docs/2024/notes.txt").unwrap();
+    assert_eq!(fs::read_to_string(&file).unwrap(), "nested");
+
+    cfg.backup_dir = Some(PathBuf::from("backups"));
+    let mirrored = backup_file_in(&cfg, "docs/2024/notes.txt").unwrap();
+    assert_eq!(mirrored, dir.path().join("backups/docs/2024/notes.txt.bak"));
+    fs::remove_file(dir.path().join("docs/2024/notes.txt.bak")).unwrap();
+    fs::write(&file, "changed again").unwrap();
+    restore_file_in(&cfg, "docs/2024/notes.txt").unwrap();
+    assert_eq!(fs::read_to_string(&file).unwrap(), "nested");
+}
+
+#[test]
+fn test_log_bytes_and_duration() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    fs::write(dir.path().join("m.txt"), vec![b'm'; 1234]).unwrap();
+    backup_file_in(&cfg, "m.txt").unwrap();
+    restore_file_in(&cfg, "m.txt").unwrap();
+    delete_file_in(&cfg, "m.txt").unwrap();
+
+    let log = fs::read_to_string(dir.path().join("logfile.txt")).unwrap();
+    for prefix in [
+        "INFO: Backup created for m.txt (1234 bytes in ",
+        "INFO: Restore completed for m.txt (1234 bytes in ",
+        "INFO: Secure delete completed for m.txt (1234 bytes in ",
+    ] {
+        let line = log.lines().find(|l| l.contains(prefix)).expect(prefix);
+        assert!(line.ends_with("ms)"), "{}", line);
+    }
+}
+
+#[test]
+fn test_name_length_leaves_room_for_suffix() {
+    let name_of = |len: usize| format!("{}.txt", "a".repeat(len - ".txt".len()));
+    assert!(matches!(
+        sanitize_filename(&name_of(253)),
+        Err(BackupError::FilenameTooLong)
+    ));
+    let longest = DEFAULT_MAX_NAME_LEN - MAX_SUFFIX_LEN;
+    assert!(sanitize_filename(&name_of(longest + 1)).is_err());
+
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    let name = name_of(longest);
+    fs::write(dir.path().join(&name), "fits").unwrap();
+    let bak = backup_file_in(&cfg, &name).unwrap();
+    let versioned = backup_file_versioned_in(&cfg, &name).unwrap();
+    for path in [bak, versioned] {
+        assert!(path.file_name().unwrap().len() <= DEFAULT_MAX_NAME_LEN);
+    }
+
+    cfg.max_name_len = 64;
+    assert!(matches!(
+        backup_file_in(&cfg, &name),
+        Err(BackupError::FilenameTooLong)
+    ));
+}
+
+#[test]
+fn test_source_equals_destination() {
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    cfg.allowed_exts.push("bak".to_string());
+    fs::write(dir.path().join("notes.txt"), "live").unwrap();
+    backup_file_in(&cfg, "notes.txt").unwrap();
+
+    // Backup names are reserved even when their extension is allowed, so neither a backup
+    // of a backup nor restoring notes.txt.bak over itself gets started.
+    assert!(matches!(
+        backup_file_in(&cfg, "notes.txt.bak"),
+        Err(BackupError::ReservedSuffix { .. })
+    ));
+    assert!(matches!(
+        restore_file_as_in(&cfg, "notes.txt", "notes.txt.bak", true),
+        Err(BackupError::ReservedSuffix { .. })
+    ));
+    assert_eq!(
+        fs::read_to_string(dir.path().join("notes.txt.bak")).unwrap(),
+        "live"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_source_equals_destination_via_symlink() {
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    cfg.follow_symlinks = true;
+    fs::write(dir.path().join("self.txt"), "data").unwrap();
+    std::os::unix::fs::symlink(dir.path().join("self.txt"), dir.path().join("self.txt.bak"))
+        .unwrap();
+
+    assert!(matches!(
+        restore_file_in(&cfg, "self.txt"),
+        Err(BackupError::SourceEqualsDestination)
+    ));
+    assert!(matches!(
+        restore_file_dry_in(&cfg, "self.txt"),
+        Err(BackupError::SourceEqualsDestination)
+    ));
+    assert_eq!(
+        fs::read_to_string(dir.path().join("self.txt")).unwrap(),
+        "data"
+    );
+}
+
+#[test]
+fn test_restore_verified() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    let file = dir.path().join("critical.txt");
+    assert!(matches!(
+        restore_file_verified_in(&cfg, "critical.txt"),
+        Err(BackupError::BackupMissing)
+    ));
+
+    fs::write(&file, "port = 8080").unwrap();
+    backup_file_in(&cfg, "critical.txt").unwrap();
+    fs::write(&file, "port = oops").unwrap();
+    restore_file_verified_in(&cfg, "critical.txt").unwrap();
+    assert_eq!(fs::read_to_string(&file).unwrap(), "port = 8080");
+
+    fs::remove_file(dir.path().join("critical.txt.bak")).unwrap();
+    fs::write(dir.path().join("app.log"), "line\n".repeat(500)).unwrap();
+    backup_file_compressed_in(&cfg, "app.log").unwrap();
+    restore_file_verified_in(&cfg, "app.log").unwrap();
+
+    let log = fs::read_to_string(dir.path().join("logfile.txt")).unwrap();
+    assert!(log.contains("Restore verified for critical.txt (11 bytes)"));
+    assert!(log.contains("Restore verified for app.log (2500 bytes)"));
+}
+
+#[test]
+fn test_random_overwrite_keeps_length() {
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    cfg.wipe_buffer_size = 512;
+    // Not a multiple of the chunk size, so the last chunk is partial.
+    fs::write(dir.path().join("key.txt"), vec![b'k'; 1_337]).unwrap();
+    let report = delete_file_reported_in(&cfg, "key.txt", OverwritePattern::Random).unwrap();
+    assert_eq!(report.bytes_overwritten, 1_337);
+    assert_eq!(report.passes, 1);
+
+    // The read-back check hashes the whole file, so it fails if the length changed.
+    fs::write(dir.path().join("key.txt"), vec![b'k'; 1_337]).unwrap();
+    delete_file_verified_in(&cfg, "key.txt", OverwritePattern::Random).unwrap();
+    assert!(!dir.path().join("key.txt").exists());
+}
+
+#[test]
+fn test_command_parsing() {
+    assert_eq!(" Backup\n".parse::<Command>().unwrap(), Command::Backup);
+    assert_eq!("RESTORE".parse::<Command>().unwrap(), Command::Restore);
+    assert_eq!("delete".parse::<Command>().unwrap(), Command::Delete);
+    for c in Command::ALL {
+        assert_eq!(c.to_string().parse::<Command>().unwrap(), c);
+    }
+    let err = " wipe ".parse::<Command>().unwrap_err();
+    assert!(matches!(&err, BackupError::UnknownCommand(s) if s == "wipe"));
+    assert!(err
+        .to_string()
+        .contains("expected backup, restore, or delete"));
+    assert!("".parse::<Command>().is_err());
+}
+
+#[test]
+fn test_log_file_is_not_a_target() {
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    fs::write(dir.path().join("notes.txt"), "x").unwrap();
+    backup_file_in(&cfg, "notes.txt").unwrap();
+
+    assert!(matches!(
+        delete_file_in(&cfg, "logfile.txt"),
+        Err(BackupError::CannotOperateOnLogFile)
+    ));
+    assert!(matches!(
+        backup_file_in(&cfg, "logfile.txt"),
+        Err(BackupError::CannotOperateOnLogFile)
+    ));
+    fs::write(dir.path().join("logfile.txt.bak"), "forged").unwrap();
+    assert!(matches!(
+        restore_file_in(&cfg, "logfile.txt"),
+        Err(BackupError::CannotOperateOnLogFile)
+    ));
+    assert!(fs::read_to_string(dir.path().join("logfile.txt"))
+        .unwrap()
+        .contains("Backup created for notes.txt"));
+
+    // Only the configured log is protected.
+    cfg.set_log_path("audit.log").unwrap();
+    delete_file_in(&cfg, "logfile.txt").unwrap();
+    assert!(matches!(
+        delete_file_in(&cfg, "audit.log"),
+        Err(BackupError::CannotOperateOnLogFile)
+    ));
+}
+
+#[test]
+fn test_backup_manifest() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    assert!(verify_manifest_in(&cfg).unwrap().is_empty());
+
+    fs::write(dir.path().join("a.txt"), "alpha").unwrap();
+    fs::write(dir.path().join("b.txt"), "beta").unwrap();
+    fs::write(dir.path().join("c.log"), "gamma").unwrap();
+    backup_file_in(&cfg, "a.txt").unwrap();
+    backup_file_in(&cfg, "b.txt").unwrap();
+    backup_file_compressed_in(&cfg, "c.log").unwrap();
+    // Replacing a backup replaces its entry instead of adding a stale one.
+    backup_file_opts_in(&cfg, "a.txt", BackupOptions { overwrite: true }).unwrap();
+
+    let manifest = fs::read_to_string(dir.path().join(MANIFEST_NAME)).unwrap();
+    assert_eq!(manifest.lines().count(), 3);
+    assert!(!dir.path().join("backups.manifest.tmp").exists());
+
+    fs::write(dir.path().join("b.txt.bak"), "tampered").unwrap();
+    fs::remove_file(dir.path().join("c.log.bak.gz")).unwrap();
+    let results = verify_manifest_in(&cfg).unwrap();
+    let status = |name: &str| {
+        results
+            .iter()
+            .find(|r| r.entry.filename == name)
+            .map(|r| r.status.clone())
+            .unwrap()
+    };
+    assert_eq!(status("a.txt"), VerifyStatus::Ok);
+    assert!(matches!(status("b.txt"), VerifyStatus::Mismatch { .. }));
+    assert_eq!(status("c.log"), VerifyStatus::Missing);
+    let a = results
+        .iter()
+        .find(|r| r.entry.filename == "a.txt")
+        .unwrap();
+    assert_eq!(a.entry.backup, PathBuf::from("a.txt.bak"));
+    assert_eq!(a.entry.size, 5);
+}
+
+#[test]
+fn test_config_from_env() {
+    let dir = tempdir().unwrap();
+    // Nothing else in this suite reads the environment.
+    std::env::set_var("SAFE_BACKUP_BASE_DIR", dir.path());
+    std::env::set_var("SAFE_BACKUP_ALLOWED_EXTS", "txt, csv");
+    let cfg = BackupConfig::from_env().unwrap();
+    assert_eq!(cfg.base_dir, dir.path());
+    assert_eq!(cfg.allowed_exts, ["txt", "csv"]);
+
+    for bad in ["tar.gz", "a/b", "txt,", ""] {
+        std::env::set_var("SAFE_BACKUP_ALLOWED_EXTS", bad);
+        assert!(matches!(
+            BackupConfig::from_env(),
+            Err(BackupError::InvalidConfig(_))
+        ));
+    }
+    std::env::remove_var("SAFE_BACKUP_ALLOWED_EXTS");
+    std::env::set_var("SAFE_BACKUP_BASE_DIR", dir.path().join("missing"));
+    assert!(matches!(
+        BackupConfig::from_env(),
+        Err(BackupError::InvalidConfig(_))
+    ));
+    let results = backup_many(&["a.txt", "b.txt"]);
+    assert_eq!(results.len(), 2);
+    assert!(results
+        .iter()
+        .all(|(_, r)| matches!(r, Err(BackupError::InvalidConfig(m)) if m.contains("missing"))));
+
+    std::env::remove_var("SAFE_BACKUP_BASE_DIR");
+    let cfg = BackupConfig::from_env().unwrap();
+    assert_eq!(cfg.base_dir, std::env::current_dir().unwrap());
+    assert_eq!(cfg.allowed_exts, ["txt", "log", "md"]);
+}
+
+#[test]
+fn test_sanitize_filename_typed() {
+    let typed = sanitize_filename_typed("release.notes.md").unwrap();
+    assert_eq!(typed.name, "release.notes.md");
+    assert_eq!(typed.ext, AllowedExt::Md);
+    assert_eq!(
+        sanitize_filename_typed("a.log").unwrap().ext,
+        AllowedExt::Log
+    );
+    assert_eq!(
+        sanitize_filename_typed("a.txt").unwrap().ext,
+        AllowedExt::Txt
+    );
+    assert!(matches!(
+        sanitize_filename_typed("a.exe"),
+        Err(BackupError::DisallowedExtension { .. })
+    ));
+    let listed: Vec<_> = AllowedExt::ALL.iter().map(|e| e.as_str()).collect();
+    assert_eq!(listed, DEFAULT_ALLOWED_EXTS);
+}
+
+#[test]
+fn test_restore_append() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    let log = dir.path().join("app.log");
+    fs::write(&log, "old 1\nold 2\n").unwrap();
+    backup_file_in(&cfg, "app.log").unwrap();
+    fs::write(&log, "new 1\n").unwrap();
+
+    let path = restore_file_append_in(&cfg, "app.log").unwrap();
+    assert_eq!(path, log);
+    assert_eq!(fs::read_to_string(&log).unwrap(), "new 1\nold 1\nold 2\n");
+    assert!(!dir.path().join("app.log.tmp").exists());
+
+    // Non-log files keep replacement semantics only.
+    fs::write(dir.path().join("notes.txt"), "x").unwrap();
+    backup_file_in(&cfg, "notes.txt").unwrap();
+    assert!(matches!(
+        restore_file_append_in(&cfg, "notes.txt"),
+        Err(BackupError::InvalidArgument(_))
+    ));
+    assert!(matches!(
+        restore_file_append_in(&cfg, "other.log"),
+        Err(BackupError::BackupMissing)
+    ));
+}
+
+#[test]
+fn test_delete_backup_keeps_source() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    fs::write(dir.path().join("stale.txt"), "live").unwrap();
+    backup_file_in(&cfg, "stale.txt").unwrap();
+
+    delete_backup_in(&cfg, "stale.txt").unwrap();
+    assert!(!dir.path().join("stale.txt.bak").exists());
+    assert_eq!(
+        fs::read_to_string(dir.path().join("stale.txt")).unwrap(),
+        "live"
+    );
+    assert!(matches!(
+        delete_backup_in(&cfg, "stale.txt"),
+        Err(BackupError::BackupMissing)
+    ));
+    assert!(delete_backup_in(&cfg, "../stale.txt").is_err());
+
+    let log = fs::read_to_string(dir.path().join("logfile.txt")).unwrap();
+    assert!(log.contains("Backup of stale.txt securely deleted (4 bytes in"));
+}
+
+#[test]
+fn test_restore_creates_parents() {
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    cfg.allow_subdirs = true;
+    cfg.backup_dir = Some(PathBuf::from("backups"));
+    fs::create_dir(dir.path().join("docs")).unwrap();
+    fs::write(dir.path().join("docs/notes.txt"), "keep me").unwrap();
+    backup_file_in(&cfg, "docs/notes.txt").unwrap();
+    fs::remove_dir_all(dir.path().join("docs")).unwrap();
+
+    assert!(restore_file_in(&cfg, "docs/notes.txt").is_err());
+    assert!(!dir.path().join("docs").exists());
+
+    let opts = RestoreOptions {
+        create_parents: true,
+        ..Default::default()
+    };
+    restore_file_opts_in(&cfg, "docs/notes.txt", opts).unwrap();
+    assert_eq!(
+        fs::read_to_string(dir.path().join("docs/notes.txt")).unwrap(),
+        "keep me"
+    );
+}
+
+#[test]
+fn test_backup_if_changed() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    let file = dir.path().join("cron.txt");
+    fs::write(&file, "v1").unwrap();
+
+    let first = backup_if_changed_in(&cfg, "cron.txt").unwrap().unwrap();
+    assert!(backup_if_changed_in(&cfg, "cron.txt").unwrap().is_none());
+    assert_eq!(list_backups_in(&cfg, "cron.txt").unwrap().len(), 1);
+
+    fs::write(&file, "v2").unwrap();
+    let second = backup_if_changed_in(&cfg, "cron.txt").unwrap().unwrap();
+    assert_ne!(first, second);
+    assert!(backup_if_changed_in(&cfg, "cron.txt").unwrap().is_none());
+
+    // The manifest digest is trusted, so a backup edited behind its back is not re-read.
+    fs::write(&second, "v3").unwrap();
+    fs::write(&file, "v3").unwrap();
+    assert!(backup_if_changed_in(&cfg, "cron.txt").unwrap().is_some());
+}
+
+#[test]
+fn test_fixed_clock() {
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    let fixed = chrono::DateTime::parse_from_rfc3339("2024-03-01T12:30:00Z")
+        .unwrap()
+        .to_utc();
+    cfg.set_clock(move || fixed);
+    fs::write(dir.path().join("t.txt"), "tick").unwrap();
+
+    let bak = backup_file_versioned_in(&cfg, "t.txt").unwrap();
+    assert_eq!(bak, dir.path().join("t.txt.20240301T123000Z.bak"));
+    let log = fs::read_to_string(dir.path().join("logfile.txt")).unwrap();
+    assert_eq!(
+        log,
+        format!(
+            "[2024-03-01 12:30:00] INFO: Versioned backup {} created for t.txt\n",
+            bak.display()
+        )
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_permission_denied() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    let file = dir.path().join("locked.txt");
+    fs::write(&file, "secret").unwrap();
+    fs::set_permissions(&file, fs::Permissions::from_mode(0o444)).unwrap();
+    if fs::OpenOptions::new().write(true).open(&file).is_ok() {
+        // Running as root: permission bits are not enforced.
+        return;
+    }
+
+    match delete_file_in(&cfg, "locked.txt") {
+        Err(BackupError::PermissionDenied { path, .. }) => assert_eq!(path, file),
+        other => panic!("expected PermissionDenied, got {:?}", other),
+    }
+    assert!(file.exists());
+
+    let ro_dir = dir.path().join("ro");
+    fs::create_dir(&ro_dir).unwrap();
+    fs::write(ro_dir.join("a.txt"), "a").unwrap();
+    fs::set_permissions(&ro_dir, fs::Permissions::from_mode(0o555)).unwrap();
+    let ro_cfg = BackupConfig::new(&ro_dir);
+    let err = backup_file_in(&ro_cfg, "a.txt").unwrap_err();
+    fs::set_permissions(&ro_dir, fs::Permissions::from_mode(0o755)).unwrap();
+    assert!(
+        matches!(err, BackupError::PermissionDenied { .. }),
+        "{:?}",
+        err
+    );
+    assert!(err.to_string().starts_with("permission denied: "));
+}
+
+#[test]
+fn test_wipe_free_space() {
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    cfg.free_space_margin = 0;
+    assert_eq!(wipe_free_space_in(&cfg, 100_000).unwrap(), 100_000);
+    assert_eq!(
+        fs::read_dir(dir.path()).unwrap().count(),
+        1,
+        "only the log remains"
+    );
+
+    // A margin larger than any disk leaves nothing to wipe.
+    cfg.free_space_margin = u64::MAX;
+    assert_eq!(wipe_free_space_in(&cfg, 100_000).unwrap(), 0);
+    let log = fs::read_to_string(dir.path().join("logfile.txt")).unwrap();
+    assert!(log.contains("Free space wiped (100000 bytes in"));
+}
+
+#[test]
+fn test_absolute_paths_inside_base() {
+    let dir = tempdir().unwrap();
+    let outside = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    let file = dir.path().join("notes.txt");
+    fs::write(&file, "abs").unwrap();
+    fs::write(outside.path().join("notes.txt"), "other").unwrap();
+    let abs = file.to_str().unwrap();
+
+    assert!(matches!(
+        backup_file_in(&cfg, abs),
+        Err(BackupError::PathSeparator | BackupError::AbsolutePath)
+    ));
+
+    cfg.allow_absolute = true;
+    assert_eq!(
+        backup_file_in(&cfg, abs).unwrap(),
+        dir.path().join("notes.txt.bak")
+    );
+    assert!(matches!(
+        backup_file_in(&cfg, outside.path().join("notes.txt").to_str().unwrap()),
+        Err(BackupError::PathEscapesBase)
+    ));
+    let sneaky = dir.path().join("..").join("notes.txt");
+    assert!(matches!(
+        backup_file_in(&cfg, sneaky.to_str().unwrap()),
+        Err(BackupError::PathEscapesBase)
+    ));
+
+    // Files in subdirectories still need `allow_subdirs`.
+    fs::create_dir(dir.path().join("docs")).unwrap();
+    fs::write(dir.path().join("docs/a.md"), "a").unwrap();
+    let nested = dir.path().join("docs/a.md");
+    assert!(backup_file_in(&cfg, nested.to_str().unwrap()).is_err());
+    cfg.allow_subdirs = true;
+    assert_eq!(
+        backup_file_in(&cfg, nested.to_str().unwrap()).unwrap(),
+        dir.path().join("docs/a.md.bak")
+    );
+}
+
+#[test]
+fn test_undo_last() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    let file = dir.path().join("oops.txt");
+    assert!(matches!(
+        undo_last_in(&cfg),
+        Err(BackupError::CannotUndo(_))
+    ));
+
+    fs::write(&file, "v1").unwrap();
+    backup_file_in(&cfg, "oops.txt").unwrap();
+    undo_last_in(&cfg).unwrap();
+    assert!(!dir.path().join("oops.txt.bak").exists());
+    // The undo itself is the last entry now, so there is nothing further to undo.
+    assert!(matches!(
+        undo_last_in(&cfg),
+        Err(BackupError::CannotUndo(_))
+    ));
+
+    backup_file_in(&cfg, "oops.txt").unwrap();
+    fs::write(&file, "v2").unwrap();
+    let opts = RestoreOptions {
+        snapshot: true,
+        ..Default::default()
+    };
+    restore_file_opts_in(&cfg, "oops.txt", opts).unwrap();
+    assert_eq!(fs::read_to_string(&file).unwrap(), "v1");
+    undo_last_in(&cfg).unwrap();
+    assert_eq!(fs::read_to_string(&file).unwrap(), "v2");
+    assert!(!dir.path().join("oops.txt.pre-restore.bak").exists());
+
+    // A snapshot left from an earlier restore is not what a later restore took.
+    restore_file_opts_in(&cfg, "oops.txt", opts).unwrap();
+    restore_file_in(&cfg, "oops.txt").unwrap();
+    assert!(matches!(
+        undo_last_in(&cfg),
+        Err(BackupError::CannotUndo(_))
+    ));
+    fs::remove_file(dir.path().join("oops.txt.pre-restore.bak")).unwrap();
+    restore_file_opts_in(&cfg, "oops.txt", opts).unwrap();
+    fs::write(dir.path().join("oops.txt.pre-restore.bak"), "tampered").unwrap();
+    assert!(matches!(
+        undo_last_in(&cfg),
+        Err(BackupError::CannotUndo(_))
+    ));
+    assert_eq!(fs::read_to_string(&file).unwrap(), "v1");
+
+    delete_file_in(&cfg, "oops.txt").unwrap();
+    assert!(matches!(
+        undo_last_in(&cfg),
+        Err(BackupError::CannotUndo(_))
+    ));
+
+    // Only the newest operation counts, even when an older one could be undone.
+    fs::write(dir.path().join("a.txt"), "a").unwrap();
+    fs::write(dir.path().join("b.txt"), "b").unwrap();
+    backup_file_in(&cfg, "a.txt").unwrap();
+    trash_file_in(&cfg, "b.txt").unwrap();
+    assert!(matches!(
+        undo_last_in(&cfg),
+        Err(BackupError::CannotUndo(_))
+    ));
+    assert!(dir.path().join("a.txt.bak").exists());
+}
+
+#[test]
+fn test_backup_dir_hidden_entries() {
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    let notes = dir.path().join("notes");
+    fs::create_dir_all(notes.join(".drafts")).unwrap();
+    fs::write(notes.join("visible.md"), "v").unwrap();
+    fs::write(notes.join(".hidden.md"), "h").unwrap();
+    fs::write(notes.join(".drafts/idea.md"), "i").unwrap();
+
+    let mirror = backup_dir_in(&cfg, "notes").unwrap();
+    assert!(mirror.join("visible.md").exists());
+    assert!(!mirror.join(".hidden.md").exists());
+    assert!(!mirror.join(".drafts").exists());
+    let log = fs::read_to_string(dir.path().join("logfile.txt")).unwrap();
+    assert!(log.contains("DEBUG: Skipped notes/.hidden.md: hidden"));
+    assert!(log.contains("DEBUG: Skipped notes/.drafts: hidden"));
+
+    fs::remove_dir_all(&mirror).unwrap();
+    cfg.include_hidden = true;
+    let mirror = backup_dir_in(&cfg, "notes").unwrap();
+    assert!(mirror.join(".hidden.md").exists());
+    assert!(mirror.join(".drafts/idea.md").exists());
+}
+
+#[test]
+fn test_restore_refuses_newer_source() {
+    use std::time::{Duration, SystemTime};
+
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    let file = dir.path().join("draft.md");
+    fs::write(&file, "backed up").unwrap();
+    backup_file_in(&cfg, "draft.md").unwrap();
+    fs::write(&file, "newer work").unwrap();
+    let later = SystemTime::now() + Duration::from_secs(60);
+    File::options()
+        .write(true)
+        .open(&file)
+        .unwrap()
+        .set_modified(later)
+        .unwrap();
+
+    let guarded = RestoreOptions {
+        refuse_if_newer: true,
+        ..Default::default()
+    };
+    assert!(matches!(
+        restore_file_opts_in(&cfg, "draft.md", guarded),
+        Err(BackupError::SourceNewerThanBackup)
+    ));
+    assert_eq!(fs::read_to_string(&file).unwrap(), "newer work");
+
+    let forced = RestoreOptions {
+        force: true,
+        ..guarded
+    };
+    restore_file_opts_in(&cfg, "draft.md", forced).unwrap();
+    assert_eq!(fs::read_to_string(&file).unwrap(), "backed up");
+    // Now the file is as old as its backup, so the guard lets it through.
+    restore_file_opts_in(&cfg, "draft.md", guarded).unwrap();
+}
+
+#[test]
+fn test_log_digest_and_checkpoint() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    let empty = log_digest_in(&cfg).unwrap();
+    assert_eq!(
+        empty,
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+
+    fs::write(dir.path().join("a.txt"), "a").unwrap();
+    backup_file_in(&cfg, "a.txt").unwrap();
+    let before = log_digest_in(&cfg).unwrap();
+    assert_ne!(before, empty);
+    assert_eq!(append_log_checkpoint_in(&cfg).unwrap(), before);
+    let log = fs::read_to_string(cfg.log_path()).unwrap();
+    assert!(log.ends_with(&format!("Log checkpoint sha256={}\n", before)));
+
+    // Editing any earlier line changes the digest the checkpoint recorded.
+    let lines: Vec<_> = log.lines().collect();
+    let prefix = format!("{}\n", lines[0]);
+    fs::write(cfg.log_path(), prefix.replace("a.txt", "b.txt")).unwrap();
+    assert_ne!(log_digest_in(&cfg).unwrap(), before);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_secure_delete_effectiveness_detection() {
+    use safe_backup::COPY_ON_WRITE_FS_MAGIC;
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    let c_path = std::ffi::CString::new(dir.path().as_os_str().as_bytes()).unwrap();
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    assert_eq!(unsafe { libc::statfs(c_path.as_ptr(), &mut stat) }, 0);
+    let cow = COPY_ON_WRITE_FS_MAGIC
+        .iter()
+        .any(|(magic, _)| *magic == stat.f_type as u32);
+    assert_eq!(is_secure_delete_effective_in(&cfg).unwrap(), !cow);
+    assert!(COPY_ON_WRITE_FS_MAGIC.contains(&(0x9123_683E, "btrfs")));
+
+    fs::write(dir.path().join("w.txt"), "w").unwrap();
+    delete_file_in(&cfg, "w.txt").unwrap();
+    let log = fs::read_to_string(dir.path().join("logfile.txt")).unwrap();
+    assert_eq!(log.contains("secure delete may be ineffective"), cow);
+}
+
+#[test]
+fn test_backup_to_writer_and_restore_from_reader() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    fs::write(dir.path().join("s3.txt"), "upload me").unwrap();
+
+    let mut upload = Vec::new();
+    assert_eq!(backup_to_writer_in(&cfg, "s3.txt", &mut upload).unwrap(), 9);
+    assert_eq!(upload, b"upload me");
+    assert!(!dir.path().join("s3.txt.bak").exists());
+    assert!(backup_to_writer_in(&cfg, "../s3.txt", &mut Vec::new()).is_err());
+
+    fs::write(dir.path().join("s3.txt"), "changed").unwrap();
+    let path = restore_from_reader_in(&cfg, "s3.txt", &mut upload.as_slice()).unwrap();
+    assert_eq!(fs::read_to_string(path).unwrap(), "upload me");
+
+    // A stream that fails partway leaves the current file alone.
+    struct Broken;
+    impl std::io::Read for Broken {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::other("connection reset"))
+        }
+    }
+    assert!(restore_from_reader_in(&cfg, "s3.txt", &mut Broken).is_err());
+    assert_eq!(
+        fs::read_to_string(dir.path().join("s3.txt")).unwrap(),
+        "upload me"
+    );
+    assert!(!dir.path().join("s3.txt.tmp").exists());
+}
+
+#[test]
+fn test_operation_metrics() {
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    let before = metrics_snapshot();
+    cfg.use_own_metrics();
+    assert_eq!(cfg.metrics(), Metrics::default());
+
+    fs::write(dir.path().join("m.txt"), "m").unwrap();
+    backup_file_in(&cfg, "m.txt").unwrap();
+    assert!(backup_file_in(&cfg, "m.txt").is_err());
+    backup_file_versioned_in(&cfg, "m.txt").unwrap();
+    restore_file_in(&cfg, "m.txt").unwrap();
+    assert!(restore_file_in(&cfg, "missing.txt").is_err());
+    delete_file_in(&cfg, "m.txt").unwrap();
+
+    // Clones share the counters.
+    let clone = cfg.clone();
+    assert!(delete_file_in(&clone, "m.txt").is_err());
+    assert_eq!(
+        cfg.metrics(),
+        Metrics {
+            backups: 2,
+            backup_failures: 1,
+            restores: 1,
+            restore_failures: 1,
+            deletes: 1,
+            delete_failures: 1,
+        }
+    );
+
+    // A config with its own counters leaves the process-wide ones alone.
+    assert!(metrics_snapshot().backups >= before.backups);
+    let shared = BackupConfig::new(dir.path());
+    fs::write(dir.path().join("n.txt"), "n").unwrap();
+    backup_file_in(&shared, "n.txt").unwrap();
+    assert!(metrics_snapshot().backups > before.backups);
+}
+
+#[test]
+fn test_trash_round_trip() {
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    cfg.allow_subdirs = true;
+    cfg.use_own_metrics();
+    fs::create_dir(dir.path().join("docs")).unwrap();
+    fs::write(dir.path().join("docs/plan.md"), "plan").unwrap();
+    fs::write(dir.path().join("old.txt"), "old").unwrap();
+
+    let trashed = trash_file_in(&cfg, "docs/plan.md").unwrap();
+    assert!(!dir.path().join("docs/plan.md").exists());
+    assert!(trashed.starts_with(dir.path().join(".trash/docs")));
+    let name = trashed
+        .strip_prefix(dir.path().join(TRASH_DIR))
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string();
+    assert!(matches!(
+        restore_from_trash_in(&cfg, "docs/plan.md"),
+        Err(BackupError::InvalidArgument(_))
+    ));
+    let restored = restore_from_trash_in(&cfg, &name).unwrap();
+    assert_eq!(fs::read_to_string(restored).unwrap(), "plan");
+
+    // Trashing the same name twice in one second still gives distinct entries.
+    trash_file_in(&cfg, "docs/plan.md").unwrap();
+    fs::write(dir.path().join("docs/plan.md"), "plan 2").unwrap();
+    trash_file_in(&cfg, "docs/plan.md").unwrap();
+    trash_file_in(&cfg, "old.txt").unwrap();
+    assert_eq!(empty_trash_in(&cfg).unwrap(), 3);
+    assert!(!dir.path().join(TRASH_DIR).exists());
+    assert_eq!(empty_trash_in(&cfg).unwrap(), 0);
+
+    let metrics = cfg.metrics();
+    assert_eq!(metrics.deletes, 6);
+    assert_eq!(metrics.restores, 1);
+    assert_eq!(metrics.restore_failures, 1);
+}
+
+#[test]
+fn test_estimate_backup_space() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    fs::write(dir.path().join("a.txt"), vec![b'a'; 100]).unwrap();
+    fs::write(dir.path().join("b.md"), vec![b'b'; 23]).unwrap();
+
+    assert_eq!(
+        estimate_backup_space_in(&cfg, &["a.txt", "b.md"]).unwrap(),
+        123
+    );
+    assert_eq!(estimate_backup_space_in(&cfg, &[]).unwrap(), 0);
+    assert!(matches!(
+        estimate_backup_space_in(&cfg, &["a.txt", "missing.txt"]),
+        Err(BackupError::SourceMissing)
+    ));
+    assert!(matches!(
+        estimate_backup_space_in(&cfg, &["a.exe"]),
+        Err(BackupError::DisallowedExtension { .. })
+    ));
+    #[cfg(unix)]
+    assert!(free_space_in(&cfg).unwrap() > 0);
+}
+
+#[test]
+fn test_custom_backup_suffix() {
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    for bad in ["bak", ".", ".a.b", ".back/up", ".txt", ".waytoolongsuffix"] {
+        assert!(
+            matches!(
+                cfg.set_backup_suffix(bad),
+                Err(BackupError::InvalidConfig(_))
+            ),
+            "{bad}"
+        );
+    }
+    assert_eq!(cfg.backup_suffix(), DEFAULT_BACKUP_SUFFIX);
+    cfg.set_backup_suffix(".safebak").unwrap();
+
+    fs::write(dir.path().join("notes.txt"), "v1").unwrap();
+    // Another tool's backup is left alone.
+    fs::write(dir.path().join("notes.txt.bak"), "theirs").unwrap();
+    let bak = backup_file_in(&cfg, "notes.txt").unwrap();
+    assert_eq!(bak, dir.path().join("notes.txt.safebak"));
+    assert_eq!(
+        list_backups_in(&cfg, "notes.txt").unwrap(),
+        vec![dir.path().join("notes.txt.safebak")]
+    );
+
+    fs::write(dir.path().join("notes.txt"), "v2").unwrap();
+    restore_file_in(&cfg, "notes.txt").unwrap();
+    assert_eq!(
+        fs::read_to_string(dir.path().join("notes.txt")).unwrap(),
+        "v1"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("notes.txt.bak")).unwrap(),
+        "theirs"
+    );
+
+    // Longer suffixes shorten the names accepted, so backups still fit.
+    let name = format!(
+        "{}.txt",
+        "a".repeat(DEFAULT_MAX_NAME_LEN - MAX_SUFFIX_LEN - 4)
+    );
+    assert!(sanitize_filename(&name).is_ok());
+    assert!(matches!(
+        backup_file_in(&cfg, &name),
+        Err(BackupError::FilenameTooLong)
+    ));
+}
+
+#[test]
+fn test_repair_backup() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    fs::write(dir.path().join("data.txt"), "good contents").unwrap();
+    assert!(matches!(
+        repair_backup_in(&cfg, "data.txt"),
+        Err(BackupError::BackupMissing)
+    ));
+    let bak = backup_file_in(&cfg, "data.txt").unwrap();
+    let mtime = fs::metadata(&bak).unwrap().modified().unwrap();
+
+    // A matching backup is left alone.
+    assert_eq!(repair_backup_in(&cfg, "data.txt").unwrap(), bak);
+    assert_eq!(fs::metadata(&bak).unwrap().modified().unwrap(), mtime);
+
+    fs::write(&bak, "good cont").unwrap();
+    assert_eq!(repair_backup_in(&cfg, "data.txt").unwrap(), bak);
+    assert_eq!(fs::read_to_string(&bak).unwrap(), "good contents");
+    assert!(verify_manifest_in(&cfg)
+        .unwrap()
+        .iter()
+        .all(|r| r.status == VerifyStatus::Ok));
+    let log = fs::read_to_string(dir.path().join("logfile.txt")).unwrap();
+    assert!(log.contains("WARN: Backup repaired for data.txt"));
+
+    fs::remove_file(dir.path().join("data.txt")).unwrap();
+    assert!(matches!(
+        repair_backup_in(&cfg, "data.txt"),
+        Err(BackupError::SourceMissing)
+    ));
+    assert_eq!(fs::read_to_string(&bak).unwrap(), "good contents");
+}
+
+#[test]
+fn test_sanitizer_builder() {
+    let default = Sanitizer::default();
+    for name in ["notes.txt", "a.exe", "../x.txt", "CON.txt", "café.txt"] {
+        assert_eq!(
+            default.validate(name).ok(),
+            sanitize_filename(name).ok(),
+            "{name}"
+        );
+    }
+
+    let rules = Sanitizer::builder()
+        .allowed_extensions(vec!["csv".to_string()])
+        .allow_uppercase(false)
+        .build();
+    assert_eq!(rules.validate("report.csv").unwrap(), "report.csv");
+    assert!(matches!(
+        rules.validate("Report.csv"),
+        Err(BackupError::InvalidCharacter)
+    ));
+    assert!(matches!(
+        rules.validate("report.txt"),
+        Err(BackupError::DisallowedExtension { .. })
+    ));
+
+    let rules = Sanitizer::builder()
+        .allow_unicode(true)
+        .allow_subdirs(true)
+        .max_len(MAX_SUFFIX_LEN + 10)
+        .build();
+    assert_eq!(rules.validate("docs/café.md").unwrap(), "docs/café.md");
+    assert!(matches!(
+        rules.validate("docs/very_long.md"),
+        Err(BackupError::FilenameTooLong)
+    ));
+}
+
+#[test]
+fn test_backup_stdin() {
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    let mut input = io::Cursor::new(b"generated".to_vec());
+    let bak = backup_stdin_in(&cfg, "notes.txt", &mut input).unwrap();
+    assert_eq!(bak, dir.path().join("notes.txt.bak"));
+    assert_eq!(fs::read_to_string(&bak).unwrap(), "generated");
+    assert!(!dir.path().join("notes.txt").exists());
+    assert!(matches!(
+        backup_stdin_in(&cfg, "notes.txt", &mut io::Cursor::new(b"again".to_vec())),
+        Err(BackupError::BackupExists)
+    ));
+    assert!(matches!(
+        backup_stdin_in(&cfg, "run.exe", &mut io::empty()),
+        Err(BackupError::DisallowedExtension { .. })
+    ));
+
+    cfg.max_file_size = Some(4);
+    assert!(matches!(
+        backup_stdin_in(&cfg, "big.txt", &mut io::Cursor::new(vec![b'x'; 10])),
+        Err(BackupError::FileTooLarge { size: 10, max: 4 })
+    ));
+    assert!(!dir.path().join("big.txt.bak").exists());
+    assert!(!dir.path().join("big.txt.bak.tmp").exists());
+    backup_stdin_in(&cfg, "small.txt", &mut io::Cursor::new(b"abcd".to_vec())).unwrap();
+}
+
+#[test]
+fn test_strict_logging() {
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    cfg.set_log_path("audit.txt").unwrap();
+    // A directory in place of the logfile makes every log write fail.
+    fs::remove_file(dir.path().join("audit.txt")).unwrap();
+    fs::create_dir(dir.path().join("audit.txt")).unwrap();
+    fs::write(dir.path().join("a.txt"), "a").unwrap();
+    fs::write(dir.path().join("b.txt"), "b").unwrap();
+
+    backup_file_in(&cfg, "a.txt").unwrap();
+
+    cfg.strict_logging = true;
+    assert!(matches!(
+        backup_file_in(&cfg, "b.txt"),
+        Err(BackupError::AuditLogFailed(_))
+    ));
+    assert!(matches!(
+        append_log_checkpoint_in(&cfg),
+        Err(BackupError::Io { .. })
+    ));
+}
+
+#[test]
+fn test_require_text_content() {
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    let mut binary = b"MZ\x90\x00\x03".to_vec();
+    binary.extend(vec![0xffu8; 100]);
+    fs::write(dir.path().join("doc.txt"), &binary).unwrap();
+    fs::write(dir.path().join("noise.txt"), vec![0x01u8; 50]).unwrap();
+    fs::write(dir.path().join("notes.md"), "# Notes\r\n\tcafé — ok\n").unwrap();
+
+    // Off by default.
+    backup_file_in(&cfg, "doc.txt").unwrap();
+
+    cfg.require_text_content = true;
+    fs::remove_file(dir.path().join("doc.txt.bak")).unwrap();
+    for name in ["doc.txt", "noise.txt"] {
+        assert!(
+            matches!(backup_file_in(&cfg, name), Err(BackupError::NotTextContent)),
+            "{name}"
+        );
+    }
+    backup_file_in(&cfg, "notes.md").unwrap();
+}
+
+#[test]
+fn test_resolve_paths() {
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    cfg.backup_dir = Some(PathBuf::from("backups"));
+    let base = dir.path().canonicalize().unwrap();
+    fs::write(dir.path().join("notes.txt"), "n").unwrap();
+
+    let paths = resolve_paths_in(&cfg, "notes.txt", Command::Backup).unwrap();
+    assert_eq!(paths.source, base.join("notes.txt"));
+    assert_eq!(paths.destination, Some(base.join("backups/notes.txt.bak")));
+    assert_eq!(paths.temp, Some(base.join("backups/notes.txt.bak.tmp")));
+    assert_eq!(paths.log, base.join("logfile.txt"));
+    // Nothing was created along the way.
+    assert!(!dir.path().join("backups").exists());
+    assert!(!dir.path().join("logfile.txt").exists());
+
+    backup_file_in(&cfg, "notes.txt").unwrap();
+    let paths = resolve_paths_in(&cfg, "notes.txt", Command::Restore).unwrap();
+    assert_eq!(paths.source, base.join("backups/notes.txt.bak"));
+    assert_eq!(paths.destination, Some(base.join("notes.txt")));
+    assert_eq!(paths.temp, Some(base.join("notes.txt.tmp")));
+
+    let paths = resolve_paths_in(&cfg, "notes.txt", Command::Delete).unwrap();
+    assert_eq!(paths.source, base.join("notes.txt"));
+    assert_eq!((paths.destination, paths.temp), (None, None));
+
+    assert!(resolve_paths_in(&cfg, "../notes.txt", Command::Backup).is_err());
+}
+
+#[test]
+fn test_backup_file_resumable() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    let contents: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+    fs::write(dir.path().join("big.log"), &contents).unwrap();
+    let part = dir.path().join("big.log.bak.part");
+
+    // An interrupted run left the first 40000 bytes behind.
+    fs::write(&part, &contents[..40_000]).unwrap();
+    let bak = backup_file_resumable_in(&cfg, "big.log").unwrap();
+    assert_eq!(fs::read(&bak).unwrap(), contents);
+    assert!(!part.exists());
+    let log = fs::read_to_string(dir.path().join("logfile.txt")).unwrap();
+    assert!(log.contains("resumed at byte 40000"));
+    assert!(matches!(
+        backup_file_resumable_in(&cfg, "big.log"),
+        Err(BackupError::BackupExists)
+    ));
+
+    // A prefix that does not match the source is thrown away.
+    fs::remove_file(&bak).unwrap();
+    let mut corrupt = contents[..40_000].to_vec();
+    corrupt[123] ^= 0xff;
+    fs::write(&part, &corrupt).unwrap();
+    backup_file_resumable_in(&cfg, "big.log").unwrap();
+    assert_eq!(fs::read(&bak).unwrap(), contents);
+    let log = fs::read_to_string(dir.path().join("logfile.txt")).unwrap();
+    assert!(log.contains("WARN: Partial backup of big.log does not match; starting over"));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_reports() {
+    use safe_backup::{BackupInfo, BackupReport};
+
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    fs::write(dir.path().join("notes.txt"), "hello").unwrap();
+    let report = backup_file_reported_in(&cfg, "notes.txt").unwrap();
+
+    let json = serde_json::to_value(&report).unwrap();
+    assert_eq!(json["bytes"], 5);
+    let back: BackupReport = serde_json::from_value(json).unwrap();
+    assert_eq!(back, report);
+
+    let info = backup_status_in(&cfg, "notes.txt").unwrap().unwrap();
+    let back: BackupInfo = serde_json::from_str(&serde_json::to_string(&info).unwrap()).unwrap();
+    assert_eq!(back, info);
+    assert_eq!(
+        serde_json::to_value(Command::Restore).unwrap(),
+        serde_json::json!("restore")
+    );
+}
+
+#[test]
+fn test_verify_all() {
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    cfg.allow_subdirs = true;
+    fs::create_dir(dir.path().join("docs")).unwrap();
+    for name in ["same.txt", "changed.txt", "gone.txt", "docs/plan.md"] {
+        fs::write(dir.path().join(name), name).unwrap();
+        backup_file_in(&cfg, name).unwrap();
+    }
+    let versioned = backup_file_versioned_in(&cfg, "same.txt").unwrap();
+    fs::write(dir.path().join("changed.txt"), "edited").unwrap();
+    fs::remove_file(dir.path().join("gone.txt")).unwrap();
+
+    let results = verify_all_in(&cfg).unwrap();
+    let base = dir.path();
+    assert_eq!(
+        results,
+        vec![
+            (base.join("changed.txt.bak"), BackupCheck::Mismatch),
+            (base.join("docs/plan.md.bak"), BackupCheck::Match),
+            (base.join("gone.txt.bak"), BackupCheck::SourceMissing),
+            (versioned, BackupCheck::Match),
+            (base.join("same.txt.bak"), BackupCheck::Match),
+        ]
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("changed.txt")).unwrap(),
+        "edited"
+    );
+}
+
+#[test]
+fn test_log_notes() {
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    fs::write(dir.path().join("a.txt"), "a").unwrap();
+
+    backup_file_note_in(&cfg, "a.txt", "  TICKET-42 by alice\n").unwrap();
+    restore_file_note_in(&cfg, "a.txt", "TICKET-43").unwrap();
+    for bad in ["", "two\nlines", "tab\there", &"x".repeat(MAX_NOTE_LEN + 1)] {
+        assert!(
+            matches!(
+                delete_file_note_in(&cfg, "a.txt", bad),
+                Err(BackupError::InvalidArgument(_))
+            ),
+            "{bad:?}"
+        );
+    }
+    assert!(dir.path().join("a.txt").exists());
+
+    cfg.log_format = LogFormat::Json;
+    delete_file_note_in(&cfg, "a.txt", "TICKET-44").unwrap();
+
+    let log = fs::read_to_string(dir.path().join("logfile.txt")).unwrap();
+    let lines: Vec<&str> = log.lines().collect();
+    assert!(lines[0].ends_with("[note: TICKET-42 by alice]"));
+    assert!(lines[1].contains("Restore completed for a.txt"));
+    assert!(lines[1].ends_with("[note: TICKET-43]"));
+    let last: serde_json::Value = serde_json::from_str(lines.last().unwrap()).unwrap();
+    assert!(last["message"]
+        .as_str()
+        .unwrap()
+        .ends_with("[note: TICKET-44]"));
+}
+
+#[test]
+fn test_rotate_file() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    let path = dir.path().join("app.log");
+    fs::write(&path, "line 1\nline 2\n").unwrap();
+    let mut app = fs::OpenOptions::new().append(true).open(&path).unwrap();
+
+    let bak = rotate_file_in(&cfg, "app.log").unwrap();
+    assert_eq!(fs::read_to_string(&bak).unwrap(), "line 1\nline 2\n");
+    assert_eq!(fs::read_to_string(&path).unwrap(), "");
+    assert_eq!(list_backups_in(&cfg, "app.log").unwrap(), vec![bak]);
+
+    // The writer's handle still points at the live file.
+    app.write_all(b"line 3\n").unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "line 3\n");
+    let log = fs::read_to_string(dir.path().join("logfile.txt")).unwrap();
+    assert!(log.contains("INFO: Rotated app.log to "));
+
+    assert!(matches!(
+        rotate_file_in(&cfg, "missing.log"),
+        Err(BackupError::SourceMissing)
+    ));
+}
+
+#[test]
+fn test_reserved_suffixes() {
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    cfg.allowed_exts
+        .extend(["bak".to_string(), "tmp".to_string()]);
+    for name in [
+        "notes.txt.bak",
+        "notes.txt.BAK",
+        "notes.txt.tmp",
+        "notes.txt.bak.tmp",
+    ] {
+        fs::write(dir.path().join(name), "artifact").unwrap();
+        assert!(
+            matches!(
+                backup_file_in(&cfg, name),
+                Err(BackupError::ReservedSuffix { .. })
+            ),
+            "{name}"
+        );
+    }
+    assert!(!dir.path().join("notes.txt.bak.bak").exists());
+
+    // A custom suffix is reserved instead of .bak.
+    cfg.set_backup_suffix(".safebak").unwrap();
+    assert!(matches!(
+        delete_file_in(&cfg, "notes.txt.safebak"),
+        Err(BackupError::ReservedSuffix { suffix }) if suffix == ".safebak"
+    ));
+    backup_file_in(&cfg, "notes.txt.bak").unwrap();
+}
+
+#[test]
+fn test_total_backup_size() {
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    cfg.allow_subdirs = true;
+    assert_eq!(total_backup_size_in(&cfg, true).unwrap(), 0);
+    fs::create_dir(dir.path().join("docs")).unwrap();
+    fs::write(dir.path().join("a.txt"), vec![b'a'; 10]).unwrap();
+    fs::write(dir.path().join("docs/b.md"), vec![b'b'; 5]).unwrap();
+    fs::write(dir.path().join("unrelated.txt"), vec![b'u'; 1000]).unwrap();
+    backup_file_in(&cfg, "a.txt").unwrap();
+    backup_file_versioned_in(&cfg, "a.txt").unwrap();
+    backup_file_in(&cfg, "docs/b.md").unwrap();
+
+    assert_eq!(total_backup_size_in(&cfg, false).unwrap(), 20);
+    assert_eq!(total_backup_size_in(&cfg, true).unwrap(), 25);
+}
+
+#[test]
+fn test_confirmed_operations() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    fs::write(dir.path().join("keep.txt"), "keep").unwrap();
+    fs::write(dir.path().join("drop.txt"), "drop").unwrap();
+    backup_file_in(&cfg, "keep.txt").unwrap();
+
+    assert!(matches!(
+        delete_file_confirmed_in(&cfg, "keep.txt", confirm("drop.txt")),
+        Err(BackupError::ConfirmationMismatch { .. })
+    ));
+    // Only the exact name confirms: no trimming or case folding.
+    assert!(matches!(
+        delete_file_confirmed_in(&cfg, "keep.txt", confirm("Keep.txt ")),
+        Err(BackupError::ConfirmationMismatch { .. })
+    ));
+    assert!(dir.path().join("keep.txt").exists());
+
+    delete_file_confirmed_in(&cfg, "drop.txt", confirm("drop.txt")).unwrap();
+    assert!(!dir.path().join("drop.txt").exists());
+
+    fs::write(dir.path().join("keep.txt"), "edited").unwrap();
+    let token = confirm("keep.txt");
+    assert_eq!(token.filename(), "keep.txt");
+    restore_file_confirmed_in(&cfg, "keep.txt", token).unwrap();
+    assert_eq!(
+        fs::read_to_string(dir.path().join("keep.txt")).unwrap(),
+        "keep"
+    );
+}
+
+#[test]
+fn test_orphaned_temps() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    fs::create_dir(dir.path().join("docs")).unwrap();
+    fs::write(dir.path().join("notes.txt"), "live").unwrap();
+    fs::write(dir.path().join("notes.txt.tmp"), "half a restore").unwrap();
+    fs::write(dir.path().join("docs/plan.md.bak.tmp"), "half a backup").unwrap();
+    fs::write(dir.path().join(".safe_backup_wipe.tmp"), "wipe").unwrap();
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(dir.path().join("notes.txt"), dir.path().join("link.tmp")).unwrap();
+    // Not names this crate writes temps under.
+    fs::write(dir.path().join("notes.tmp"), "user file").unwrap();
+    fs::create_dir(dir.path().join(".trash")).unwrap();
+    fs::write(dir.path().join(".trash/old.txt.tmp"), "trashed").unwrap();
+
+    let expected = vec![
+        dir.path().join(".safe_backup_wipe.tmp"),
+        dir.path().join("docs/plan.md.bak.tmp"),
+        dir.path().join("notes.txt.tmp"),
+    ];
+    assert_eq!(find_orphaned_temps_in(&cfg).unwrap(), expected);
+    assert!(dir.path().join("notes.txt.tmp").exists());
+
+    // A temp whose file is locked is still being written.
+    fs::write(dir.path().join("busy.txt"), "busy").unwrap();
+    fs::write(dir.path().join("busy.txt.bak.tmp"), "in progress").unwrap();
+    let holder = std::fs::File::create(dir.path().join("busy.txt.lock")).unwrap();
+    holder.lock().unwrap();
+    assert_eq!(find_orphaned_temps_in(&cfg).unwrap(), expected);
+    assert_eq!(clean_orphaned_temps_in(&cfg).unwrap(), expected);
+    assert!(dir.path().join("busy.txt.bak.tmp").exists());
+    drop(holder);
+
+    assert!(expected.iter().all(|p| !p.exists()));
+    assert!(dir.path().join("notes.tmp").exists());
+    assert!(dir.path().join(".trash/old.txt.tmp").exists());
+    assert_eq!(
+        fs::read_to_string(dir.path().join("notes.txt")).unwrap(),
+        "live"
+    );
+    assert_eq!(
+        find_orphaned_temps_in(&cfg).unwrap(),
+        vec![dir.path().join("busy.txt.bak.tmp")]
+    );
+    let log = fs::read_to_string(dir.path().join("logfile.txt")).unwrap();
+    assert!(log.contains("Found 3 orphaned temp files"));
+    assert!(log.contains("busy.txt is in use"));
+    assert!(log.contains("Orphaned temps cleaned: 3 files"));
+}
+
+#[test]
+fn test_restore_collision_policy() {
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    let fixed = chrono::DateTime::parse_from_rfc3339("2024-05-06T07:08:09Z")
+        .unwrap()
+        .to_utc();
+    cfg.set_clock(move || fixed);
+    fs::write(dir.path().join("notes.txt"), "backed up").unwrap();
+    backup_file_in(&cfg, "notes.txt").unwrap();
+    assert_eq!(RestoreOptions::default().on_collision, Collision::Overwrite);
+
+    fs::write(dir.path().join("notes.txt"), "current").unwrap();
+    let fail = RestoreOptions {
+        on_collision: Collision::Fail,
+        ..Default::default()
+    };
+    assert!(matches!(
+        restore_file_opts_in(&cfg, "notes.txt", fail),
+        Err(BackupError::DestinationExists)
+    ));
+    assert_eq!(
+        fs::read_to_string(dir.path().join("notes.txt")).unwrap(),
+        "current"
+    );
+
+    let rename = RestoreOptions {
+        on_collision: Collision::RenameExisting,
+        ..Default::default()
+    };
+    restore_file_opts_in(&cfg, "notes.txt", rename).unwrap();
+    fs::write(dir.path().join("notes.txt"), "current again").unwrap();
+    restore_file_opts_in(&cfg, "notes.txt", rename).unwrap();
+    assert_eq!(
+        fs::read_to_string(dir.path().join("notes.txt")).unwrap(),
+        "backed up"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("notes.txt.replaced-20240506T070809Z")).unwrap(),
+        "current"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("notes.txt.replaced-20240506T070809Z-1")).unwrap(),
+        "current again"
+    );
+
+    // With nothing in the way, every policy just restores.
+    fs::remove_file(dir.path().join("notes.txt")).unwrap();
+    restore_file_opts_in(&cfg, "notes.txt", fail).unwrap();
+}
+
+#[test]
+fn test_expiring_backups() {
+    use std::time::Duration;
+
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    let fixed = chrono::DateTime::parse_from_rfc3339("2024-06-01T00:00:00Z")
+        .unwrap()
+        .to_utc();
+    cfg.set_clock(move || fixed);
+    fs::write(dir.path().join("a.txt"), "data").unwrap();
+
+    let short = backup_file_expiring_in(&cfg, "a.txt", Duration::from_secs(3600)).unwrap();
+    let kept = backup_file_versioned_in(&cfg, "a.txt").unwrap();
+    assert!(expire_backups_in(&cfg).unwrap().is_empty());
+
+    // Expiry follows the recorded time, so a fresh mtime does not save the backup.
+    fs::write(&short, "data").unwrap();
+    let later = fixed + chrono::Duration::hours(2);
+    cfg.set_clock(move || later);
+    assert_eq!(expire_backups_in(&cfg).unwrap(), vec![short.clone()]);
+    assert!(!short.exists());
+    assert!(kept.exists());
+
+    let listed: Vec<_> = verify_manifest_in(&cfg)
+        .unwrap()
+        .into_iter()
+        .map(|r| r.entry.backup)
+        .collect();
+    assert_eq!(listed, vec![kept.strip_prefix(dir.path()).unwrap()]);
+    assert!(expire_backups_in(&cfg).unwrap().is_empty());
+}
+
+#[cfg(windows)]
+#[test]
+fn test_delete_wipes_alternate_streams() {
+    use std::io::Read;
+
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    let path = dir.path().join("a.txt");
+    fs::write(&path, "visible").unwrap();
+    let stream = dir.path().join("a.txt:hidden");
+    fs::write(&stream, "hidden data").unwrap();
+    let mut stream_file = fs::File::open(&stream).unwrap();
+
+    delete_file_in(&cfg, "a.txt").unwrap();
+    assert!(!path.exists());
+    // The stream was zeroed before the file went away, so the open handle sees no data.
+    let mut left = Vec::new();
+    stream_file.read_to_end(&mut left).unwrap();
+    assert!(left.iter().all(|&b| b == 0));
+}
+
+#[test]
+fn test_import_and_backup() {
+    let outside = tempdir().unwrap();
+    let external = outside.path().join("report.txt");
+    fs::write(&external, "quarterly").unwrap();
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+
+    let (imported, bak) = import_and_backup_in(&cfg, &external, "report.txt").unwrap();
+    assert_eq!(imported, dir.path().join("report.txt"));
+    assert_eq!(fs::read_to_string(&imported).unwrap(), "quarterly");
+    assert_eq!(fs::read_to_string(&bak).unwrap(), "quarterly");
+    assert!(external.exists());
+    let log = fs::read_to_string(dir.path().join("logfile.txt")).unwrap();
+    assert!(log.contains(&external.display().to_string()));
+
+    assert!(matches!(
+        import_and_backup_in(&cfg, &external, "report.txt"),
+        Err(BackupError::DestinationExists)
+    ));
+    assert!(import_and_backup_in(&cfg, &external, "../escape.txt").is_err());
+    assert!(matches!(
+        import_and_backup_in(&cfg, Path::new("report.txt"), "other.txt"),
+        Err(BackupError::InvalidArgument(_))
+    ));
+}
+
+#[test]
+fn test_log_batch() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    let log_path = dir.path().join("logfile.txt");
+    for name in ["a.txt", "b.txt", "c.txt"] {
+        fs::write(dir.path().join(name), name).unwrap();
+    }
+    let read_log = || fs::read_to_string(&log_path).unwrap_or_default();
+
+    let batch = LogBatch::new(&cfg);
+    let results = backup_many_in(batch.config(), &["a.txt", "b.txt"]);
+    assert!(results.iter().all(|(_, r)| r.is_ok()));
+    assert!(!read_log().contains("Backup created"));
+    batch.flush().unwrap();
+    let flushed = read_log();
+    assert_eq!(flushed.matches("Backup created").count(), 2);
+    assert!(flushed.find("a.txt").unwrap() < flushed.find("b.txt").unwrap());
+
+    backup_file_in(batch.config(), "c.txt").unwrap();
+    assert_eq!(read_log(), flushed);
+    let late = batch.config().clone();
+    drop(batch);
+    assert_eq!(read_log().matches("Backup created").count(), 3);
+
+    // A clone that outlives the batch writes each line straight away.
+    fs::write(dir.path().join("d.txt"), "d").unwrap();
+    backup_file_in(&late, "d.txt").unwrap();
+    assert_eq!(read_log().matches("Backup created").count(), 4);
+
+    // A strict caller sees a failed flush from `finish`.
+    let mut strict = BackupConfig::new(dir.path());
+    strict.strict_logging = true;
+    let batch = LogBatch::new(&strict);
+    backup_file_versioned_in(batch.config(), "a.txt").unwrap();
+    fs::remove_file(&log_path).unwrap();
+    fs::create_dir(&log_path).unwrap();
+    assert!(matches!(
+        batch.finish(),
+        Err(BackupError::AuditLogFailed(_))
+    ));
+}
+
+#[test]
+fn test_run_dispatch() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    let path = dir.path().join("a.txt");
+    fs::write(&path, "hello").unwrap();
+
+    let backup = run_in(&cfg, Operation::Backup, "a.txt").unwrap();
+    assert_eq!(backup.operation, Operation::Backup);
+    assert_eq!(backup.path, dir.path().join("a.txt.bak"));
+    assert_eq!(backup.bytes, 5);
+
+    fs::write(&path, "changed").unwrap();
+    let restore = run_in(&cfg, Operation::Restore, "a.txt").unwrap();
+    assert_eq!(restore.path, path);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "hello");
+
+    let delete = run_in(&cfg, Command::Delete.into(), "a.txt").unwrap();
+    assert_eq!(delete.operation, Operation::Delete(OverwritePattern::Zeros));
+    assert_eq!(delete.path, path);
+    assert_eq!(delete.bytes, 5);
+    assert!(!path.exists());
+    assert!(matches!(
+        run_in(&cfg, Command::Delete.into(), "a.txt"),
+        Err(BackupError::SourceMissing)
+    ));
+
+    fs::write(&path, "again").unwrap();
+    let ones = run_in(&cfg, Operation::Delete(OverwritePattern::Ones), "a.txt").unwrap();
+    assert_eq!(ones.bytes, 5);
+    assert!(!path.exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_delete_hard_linked() {
+    let dir = tempdir().unwrap();
+    let mut cfg = BackupConfig::new(dir.path());
+    let path = dir.path().join("a.txt");
+    let other = dir.path().join("b.txt");
+    fs::write(&path, "shared").unwrap();
+    fs::hard_link(&path, &other).unwrap();
+
+    cfg.refuse_hard_linked = true;
+    assert!(matches!(
+        delete_file_in(&cfg, "a.txt"),
+        Err(BackupError::HardLinked { links: 2 })
+    ));
+    assert_eq!(fs::read_to_string(&other).unwrap(), "shared");
+
+    cfg.refuse_hard_linked = false;
+    delete_file_in(&cfg, "a.txt").unwrap();
+    assert!(!path.exists());
+    assert_eq!(fs::read(&other).unwrap(), vec![0; 6]);
+    let log = fs::read_to_string(dir.path().join("logfile.txt")).unwrap();
+    assert!(log.contains("WARN: a.txt has 2 hard links"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_prepared_target_refuses_symlink() {
+    let dir = tempdir().unwrap();
+    let outside = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    let victim = outside.path().join("victim.txt");
+    fs::write(&victim, "outside").unwrap();
+    std::os::unix::fs::symlink(&victim, dir.path().join("link.txt")).unwrap();
+
+    let target = prepare_in(&cfg, "link.txt").unwrap();
+    assert_eq!(target.path(), dir.path().join("link.txt"));
+    assert!(matches!(
+        backup_in(&cfg, &target),
+        Err(BackupError::Symlink)
+    ));
+    assert!(matches!(
+        restore_in(&cfg, &target),
+        Err(BackupError::Symlink)
+    ));
+    assert!(matches!(
+        delete_in(&cfg, &target),
+        Err(BackupError::Symlink)
+    ));
+    assert_eq!(fs::read_to_string(&victim).unwrap(), "outside");
+    assert!(!dir.path().join("link.txt.bak").exists());
+}
+
+#[test]
+fn test_prepared_target() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    fs::write(dir.path().join("a.txt"), "one").unwrap();
+
+    let target = prepare_in(&cfg, "a.txt").unwrap();
+    assert_eq!(target.filename(), "a.txt");
+    assert_eq!(target.path(), dir.path().join("a.txt"));
+    assert_eq!(target.backup_path(), dir.path().join("a.txt.bak"));
+    assert!(matches!(
+        prepare_in(&cfg, "a.exe"),
+        Err(BackupError::DisallowedExtension { .. })
+    ));
+
+    backup_in(&cfg, &target).unwrap();
+    fs::write(dir.path().join("a.txt"), "two").unwrap();
+    restore_in(&cfg, &target).unwrap();
+    assert_eq!(fs::read_to_string(target.path()).unwrap(), "one");
+    delete_in(&cfg, &target).unwrap();
+    assert!(!target.path().exists());
+
+    let other = tempdir().unwrap();
+    assert!(matches!(
+        backup_in(&BackupConfig::new(other.path()), &target),
+        Err(BackupError::InvalidArgument(_))
+    ));
+    // Same base dir, but the backup would land somewhere else now.
+    let mut moved = BackupConfig::new(dir.path());
+    moved.backup_dir = Some("backups".into());
+    let mut renamed = BackupConfig::new(dir.path());
+    renamed.set_backup_suffix(".orig").unwrap();
+    let mut absolute = BackupConfig::new(dir.path());
+    absolute.allow_absolute = true;
+    let mut unicode = BackupConfig::new(dir.path());
+    unicode.unicode_names = true;
+    let mut shorter = BackupConfig::new(dir.path());
+    shorter.max_name_len = 3;
+    let mut following = BackupConfig::new(dir.path());
+    following.follow_symlinks = true;
+    for changed in [&moved, &renamed, &absolute, &unicode, &shorter, &following] {
+        assert!(matches!(
+            backup_in(changed, &target),
+            Err(BackupError::InvalidArgument(_))
+        ));
+    }
+}
+
+#[test]
+fn test_prune_and_delete_backup_update_manifest() {
+    let dir = tempdir().unwrap();
+    let cfg = BackupConfig::new(dir.path());
+    fs::write(dir.path().join("m.txt"), "m").unwrap();
+    backup_file_in(&cfg, "m.txt").unwrap();
+    let versions: Vec<PathBuf> = (0..3)
+        .map(|_| backup_file_versioned_in(&cfg, "m.txt").unwrap())
+        .collect();
+    let listed = |cfg: &BackupConfig| -> Vec<PathBuf> {
+        verify_manifest_in(cfg)
+            .unwrap()
+            .into_iter()
+            .map(|r| {
+                assert_eq!(r.status, VerifyStatus::Ok);
+                dir.path().join(r.entry.backup)
+            })
+            .collect()
+    };
+
+    prune_backups_in(&cfg, "m.txt", 1).unwrap();
+    assert_eq!(
+        listed(&cfg),
+        vec![dir.path().join("m.txt.bak"), versions[2].clone()]
+    );
+
+    delete_backup_in(&cfg, "m.txt").unwrap();
+    assert_eq!(listed(&cfg), vec![versions[2].clone()]);
+}